@@ -0,0 +1,26 @@
+// サンプルプログラムで共通に使う関数
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use thsdr::constants::SAMPLING_FREQ;
+
+// 16ビットモノラルのWAVファイルに出力する。
+pub fn write_wav(path: &str, samples: &[f32]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let data_size = samples.len() as u32 * 2;
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_size).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?;                       // PCM
+    file.write_all(&1u16.to_le_bytes())?;                       // モノラル
+    file.write_all(&(SAMPLING_FREQ as u32).to_le_bytes())?;
+    file.write_all(&(SAMPLING_FREQ as u32 * 2).to_le_bytes())?;
+    file.write_all(&2u16.to_le_bytes())?;
+    file.write_all(&16u16.to_le_bytes())?;
+    file.write_all(b"data")?;
+    file.write_all(&data_size.to_le_bytes())?;
+    for &s in samples {
+        file.write_all(&((s.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+// SSTVの試験用信号を作るサンプルプログラム
+// 試験画像(カラーバーと階調)をVISコードとともにSSTVの音声にして、48kHzのWAVファイルに出力する。
+// cargo run --example sstv_gen -- sstv.wav 44 のように、ファイル名とVISコードを指定します。
+// 対応するVISコード: Martin M1 44、M2 40、Scottie S1 60、S2 56、Robot 36 8、Robot 72 12
+// 信号はcargo testのデコーダの試験と同じsrc/testsignal/sstv.rsで作る。
+mod common;
+#[path = "../src/testsignal/sstv.rs"]
+mod sstv;
+
+use thsdr::constants::SAMPLING_FREQ;
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let path = args.get(1).map(|s| s.as_str()).unwrap_or("sstv.wav");
+    let vis = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(44);
+
+    let image = sstv::reference_image(sstv::mode_lines(vis));
+    let samples = sstv::generate(vis, &image);
+    common::write_wav(path, &samples)?;
+    println!("{} (VIS {}, {:.1}秒)", path, vis, samples.len() as f64 / SAMPLING_FREQ as f64);
+    Ok(())
+}
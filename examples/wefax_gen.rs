@@ -0,0 +1,21 @@
+// WEFAXの試験用信号を作るサンプルプログラム
+// 開始信号、位相合わせ、試験画像、停止信号を順に並べたWEFAXの音声を、48kHzのWAVファイルに出力する。
+// cargo run --example wefax_gen -- wefax.wav 120 576 のように、ファイル名、LPM、IOCを指定します。
+// 信号はcargo testのデコーダの試験と同じsrc/testsignal/wefax.rsで作る。
+mod common;
+#[path = "../src/testsignal/wefax.rs"]
+mod wefax;
+
+use thsdr::constants::SAMPLING_FREQ;
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let path = args.get(1).map(|s| s.as_str()).unwrap_or("wefax.wav");
+    let lpm = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(120.0);
+    let ioc = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(576);
+
+    let samples = wefax::generate(lpm, ioc, 200);
+    common::write_wav(path, &samples)?;
+    println!("{} ({} LPM, IOC{}, {:.1}秒)", path, lpm, ioc, samples.len() as f32 / SAMPLING_FREQ);
+    Ok(())
+}
@@ -0,0 +1,208 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use crate::fft::power_spectrum;
+
+
+// AFC関連の定数の定義
+const FFT_SIZE: usize = 16384;              // 約0.34秒ごとに、2.9[Hz]の分解能で測定する。
+const BIN_WIDTH: f32 = SAMPLING_FREQ / FFT_SIZE as f32;
+const BLOCK_TIME: f32 = FFT_SIZE as f32 / SAMPLING_FREQ;
+pub const AFC_RANGE: f32 = 1000.0;          // 基準の周波数から±この範囲でキャリアを探す。
+pub const SSB_RANGE: f32 = 100.0;           // SSBで低減搬送波(パイロット)を探す範囲。音声(200[Hz]以上)は含まない。
+pub const CAL_TIME: f32 = 3.0;              // CALコマンドで、キャリアの測定を待つ時間[秒]
+const LINE_RATIO: f32 = 30.0;               // キャリアとみなす、範囲内の電力の中央値に対するピークの比(約15[dB])
+const SLEW_RATE: f32 = 20.0;                // 周波数を変える速さの上限[Hz/秒]
+const TRACK_GAIN: f32 = 0.5;                // 測定した誤差のうち、1回に補正する割合
+const UNLOCK_BLOCKS: u32 = 3;               // キャリアがこの回数続けて見つからなければロックが外れたとする。
+
+/// チャンクごとのAFCの結果
+pub struct AfcStatus {
+    pub carrier: f32,               // 復調に使うキャリアの周波数[Hz]
+    pub measured: Option<f32>,      // このチャンクで測定したキャリアの周波数[Hz](測定した時のみ)
+    pub text: String,               // ロックの状態の変化
+}
+
+/// AFC
+/// IFのスペクトルから基準の周波数(reference)付近のキャリアを探し、キャリアの周波数を追従する。
+/// rangeは追従する範囲\[Hz\]で、この範囲で最も強い線スペクトルを追う。0の時は追従せずに測定のみを行い、基準の周波数をそのまま返す。
+/// 測定の結果(measured)は、rangeによらず基準の周波数±AFC_RANGEで探した値になる。
+/// 周波数の変化はSLEW_RATEで制限し、キャリアが見つからない間は最後の周波数を保持する。
+///
+/// ```
+/// use thsdr::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+/// use thsdr::afc::create_afc;
+///
+/// // 12020[Hz]のキャリアを、基準の12000[Hz]から測定する。
+/// let mut afc = create_afc();
+/// let mut measured = None;
+/// for n in 0..16 {
+///     let chunk: Vec<f32> = (0..CHUNK_SIZE)
+///         .map(|i| (2.0 * std::f32::consts::PI * 12020.0 * ((n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ)).sin())
+///         .collect();
+///     measured = measured.or(afc(&chunk, 12000.0, 0.0).measured);
+/// }
+/// assert!((measured.unwrap() - 12020.0).abs() < 1.0);
+/// ```
+pub fn create_afc() -> impl FnMut(&[f32], f32, f32) -> AfcStatus {
+
+    let mut buffer: Vec<f32> = Vec::with_capacity(FFT_SIZE);
+    let mut offset: f32 = 0.0;      // 基準の周波数からのずれ
+    let mut locked = false;
+    let mut misses = 0;
+
+    move |input: &[f32], reference: f32, range: f32| -> AfcStatus {
+        let mut text = String::new();
+        let mut measured = None;
+
+        buffer.extend_from_slice(input);
+        if buffer.len() >= FFT_SIZE {
+            let spectrum = power_spectrum(&buffer[..FFT_SIZE]);
+            measured = find_carrier(&spectrum, reference, AFC_RANGE);
+            buffer.clear();
+
+            if range <= 0.0 {
+                offset = 0.0;
+                locked = false;
+            } else {
+                // 範囲の外の強い信号に邪魔されないように、追従する範囲の中だけで探す。
+                match find_carrier(&spectrum, reference, range).filter(|f| (f - reference).abs() <= range) {
+                    Some(f) => {
+                        let limit = SLEW_RATE * BLOCK_TIME;
+                        offset += ((f - reference - offset) * TRACK_GAIN).clamp(-limit, limit);
+                        misses = 0;
+                        if !locked {
+                            locked = true;
+                            text = format!("AFC lock {:+.1}Hz\n", f - reference);
+                        }
+                    },
+                    None => {
+                        misses += 1;
+                        if locked && misses >= UNLOCK_BLOCKS {
+                            locked = false;
+                            text = format!("AFC unlock (hold {:+.1}Hz)\n", offset);
+                        }
+                    },
+                }
+            }
+        }
+
+        AfcStatus {
+            carrier: if range > 0.0 { reference + offset } else { reference },
+            measured,
+            text,
+        }
+    }
+}
+
+// 基準の周波数±rangeの範囲で最も強い線スペクトルを探し、周波数を返す。
+// ピークが範囲内の電力の中央値のLINE_RATIO倍に満たなければ、キャリアが無いとしてNoneを返す。
+fn find_carrier(spectrum: &[f32], reference: f32, range: f32) -> Option<f32> {
+    let low = (((reference - range) / BIN_WIDTH) as usize).max(1);
+    let high = (((reference + range) / BIN_WIDTH) as usize).min(spectrum.len() - 2);
+    if low >= high {
+        return None;
+    }
+
+    let (peak, &power) = spectrum[low..=high].iter().enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, p)| (i + low, p))?;
+    let mut sorted = spectrum[low..=high].to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    if power < median * LINE_RATIO || power <= 0.0 {
+        return None;
+    }
+
+    // 対数の電力を放物線で補間して、ビンより細かく周波数を求める。
+    let (a, b, c) = (spectrum[peak - 1].max(1e-20).ln(), power.ln(), spectrum[peak + 1].max(1e-20).ln());
+    let denominator = a - 2.0 * b + c;
+    let delta = if denominator < 0.0 { 0.5 * (a - c) / denominator } else { 0.0 };
+    Some((peak as f32 + delta.clamp(-0.5, 0.5)) * BIN_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CHUNK_SIZE;
+    use crate::testsignal::gaussian_noise;
+    use core::f32::consts::PI;
+
+    const REFERENCE: f32 = 12000.0;
+
+    // (振幅, 周波数)の音と弱い雑音をFFTのblocks回分だけAFCに通し、測定した時の結果を返す。
+    fn run(afc: &mut impl FnMut(&[f32], f32, f32) -> AfcStatus, tones: &[(f32, f32)], blocks: usize, range: f32) -> Vec<AfcStatus> {
+        let mut noise = gaussian_noise(7);
+        let per_block = FFT_SIZE / CHUNK_SIZE;
+        (0..blocks * per_block).filter_map(|n| {
+            let chunk: Vec<f32> = (0..CHUNK_SIZE).map(|i| {
+                let t = (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ;
+                tones.iter().map(|&(a, f)| a * (2.0 * PI * f * t).sin()).sum::<f32>() + 0.01 * noise()
+            }).collect();
+            let status = afc(&chunk, REFERENCE, range);
+            ((n + 1) % per_block == 0).then_some(status)
+        }).collect()
+    }
+
+    #[test]
+    fn limits_slew_rate() {
+        // 500[Hz]離れたキャリアに、1回あたりSLEW_RATE * BLOCK_TIME(約6.8[Hz])ずつ近づく。
+        let mut afc = create_afc();
+        let limit = SLEW_RATE * BLOCK_TIME;
+        let mut last = REFERENCE;
+        for status in run(&mut afc, &[(0.5, 12500.0)], 10, AFC_RANGE) {
+            assert!((status.measured.unwrap() - 12500.0).abs() < 1.0);
+            assert!((status.carrier - last - limit).abs() < 0.01, "{} {}", last, status.carrier);
+            last = status.carrier;
+        }
+    }
+
+    #[test]
+    fn converges_on_near_carrier() {
+        let mut afc = create_afc();
+        let status = run(&mut afc, &[(0.5, 12005.0)], 20, AFC_RANGE).pop().unwrap();
+        assert!((status.carrier - 12005.0).abs() < 0.5, "{}", status.carrier);
+    }
+
+    #[test]
+    fn locks_and_unlocks() {
+        let mut afc = create_afc();
+        let statuses = run(&mut afc, &[(0.5, 12030.0)], 2, AFC_RANGE);
+        assert_eq!(statuses[0].text, "AFC lock +30.0Hz\n");
+        assert_eq!(statuses[1].text, "");
+        let held = statuses[1].carrier;
+
+        // キャリアが消えてもUNLOCK_BLOCKS回続くまではロックを保ち、その後も最後の周波数を保持する。
+        let statuses = run(&mut afc, &[], 4, AFC_RANGE);
+        let texts: Vec<&str> = statuses.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["", "", &format!("AFC unlock (hold {:+.1}Hz)\n", held - REFERENCE), ""]);
+        assert!(statuses.iter().all(|s| s.carrier == held && s.measured.is_none()));
+
+        // 再びキャリアが現れるとロックする。
+        let statuses = run(&mut afc, &[(0.5, 12030.0)], 1, AFC_RANGE);
+        assert_eq!(statuses[0].text, "AFC lock +30.0Hz\n");
+    }
+
+    #[test]
+    fn measures_without_tracking_when_range_is_zero() {
+        let mut afc = create_afc();
+        for status in run(&mut afc, &[(0.5, 12300.0)], 3, 0.0) {
+            assert_eq!(status.carrier, REFERENCE);
+            assert!((status.measured.unwrap() - 12300.0).abs() < 1.0);
+            assert!(status.text.is_empty());
+        }
+    }
+
+    #[test]
+    fn ssb_tracks_pilot_and_ignores_voice() {
+        // 強い音声の成分(+800[Hz])があっても、SSB_RANGEの中の弱いパイロット(+40[Hz])を追う。
+        let mut afc = create_afc();
+        let statuses = run(&mut afc, &[(0.05, 12040.0), (0.5, 12800.0)], 3, SSB_RANGE);
+        assert!((statuses[0].measured.unwrap() - 12800.0).abs() < 1.0);        // 測定は±AFC_RANGEで最も強い音
+        assert_eq!(statuses[0].text, "AFC lock +40.0Hz\n");
+        assert!(statuses.windows(2).all(|w| w[1].carrier > w[0].carrier && w[1].carrier < 12040.0));
+
+        // パイロットが無ければ、音声があってもキャリアは動かさない。
+        let mut afc = create_afc();
+        assert!(run(&mut afc, &[(0.5, 12300.0), (0.5, 12800.0)], 3, SSB_RANGE).iter().all(|s| s.carrier == REFERENCE && s.text.is_empty()));
+    }
+}
@@ -4,6 +4,7 @@ use crate::constants::CHUNK_SIZE;
 
 // フィルター関連の定数の定義
 pub const N: usize = 3;  // フィルタタップ数。フィルタ次数+1になる。
+#[derive(Clone, Copy, Debug)]
 pub enum AGCType {
     AGC05,
     AGC15,
@@ -13,6 +14,17 @@ pub enum AGCType {
     None,
 }
 
+/// AGC  チャンクごとに利得を調整した信号とRSSIを返す。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::agc::{create_agc, AGCType};
+///
+/// let mut agc = create_agc(AGCType::AGC05);
+/// let (output, rssi) = agc(&[0.1; CHUNK_SIZE]);
+/// assert_eq!(output.len(), CHUNK_SIZE);
+/// assert!(rssi.is_finite());
+/// ```
 pub fn create_agc(fselect: AGCType) -> impl FnMut(&[f32]) -> ([f32; CHUNK_SIZE],f32) {
 
     #[cfg(debug_assertions)]
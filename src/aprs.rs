@@ -0,0 +1,447 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use core::f32::consts::PI;
+use std::sync::mpsc::Sender;
+
+
+// AFSK1200(Bell 202)関連の定数の定義
+const BAUD: f32 = 1200.0;
+const MARK_FREQ: f32 = 1200.0;
+const SPACE_FREQ: f32 = 2200.0;
+const BIT_SAMPLES: usize = (SAMPLING_FREQ / BAUD) as usize;     // 1ビットのサンプル数(40)
+const PLL_GAIN: f32 = 0.3;          // ビット同期の引き込みの強さ
+const MIN_FRAME: usize = 18;        // アドレス2つ(14)+制御+PID+FCS(2)
+const MAX_FRAME: usize = 400;
+
+// APRS(AX.25)デコーダ
+// FM復調した音声からAFSK1200を復調し、HDLCのフレームを取り出してTNC2形式の文字列にする。
+// CRCが正しいフレームは、KISSで送信するためにframe_txにも送る。
+pub fn create_aprs_decoder(frame_tx: Sender<Vec<u8>>) -> impl FnMut(&[f32]) -> String {
+
+    // マークとスペースの相関器(1ビット分の移動和)
+    let mut mark_phase: f32 = 0.0;
+    let mut space_phase: f32 = 0.0;
+    let mut history: [[f32; 4]; BIT_SAMPLES] = [[0.0; 4]; BIT_SAMPLES];
+    let mut sums: [f32; 4] = [0.0; 4];
+    let mut pos = 0;
+
+    // ビット同期(DPLL)
+    let mut pll_phase: f32 = 0.0;
+    let mut last_level = false;
+    let mut last_bit_level = false;
+
+    // HDLC
+    let mut hdlc = create_hdlc_receiver();
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+
+        for &x in input {
+            // 相関の計算 (mark_i, mark_q, space_i, space_q)
+            let values = [
+                x * mark_phase.cos(), x * mark_phase.sin(),
+                x * space_phase.cos(), x * space_phase.sin(),
+            ];
+            mark_phase = (mark_phase + 2.0 * PI * MARK_FREQ / SAMPLING_FREQ) % (2.0 * PI);
+            space_phase = (space_phase + 2.0 * PI * SPACE_FREQ / SAMPLING_FREQ) % (2.0 * PI);
+            for k in 0..4 {
+                sums[k] += values[k] - history[pos][k];
+            }
+            history[pos] = values;
+            pos = (pos + 1) % BIT_SAMPLES;
+
+            let mark = sums[0] * sums[0] + sums[1] * sums[1];
+            let space = sums[2] * sums[2] + sums[3] * sums[3];
+            let level = mark > space;
+
+            // 変化点でビットの位相を中央に引き込む。
+            if level != last_level {
+                pll_phase -= (pll_phase - 0.5) * PLL_GAIN;
+                last_level = level;
+            }
+
+            pll_phase += BAUD / SAMPLING_FREQ;
+            if pll_phase >= 1.0 {
+                pll_phase -= 1.0;
+
+                // NRZI 変化が無ければ1、変化があれば0
+                let bit = level == last_bit_level;
+                last_bit_level = level;
+
+                if let Some(frame) = hdlc(bit) {
+                    if let Some(line) = frame_to_tnc2(&frame) {
+                        let _ = frame_tx.send(frame);
+                        text.push_str(&line);
+                        text.push('\n');
+                        if let Some(info) = parse_aprs(&line) {
+                            text.push_str("  ");
+                            text.push_str(&info);
+                            text.push('\n');
+                        }
+                    }
+                }
+            }
+        }
+
+        text
+    }
+}
+
+// HDLCの受信処理
+// フラグ(0x7E)で区切られたビット列からビットスタッフィングを除き、CRCが正しいフレームを返す。
+// 返すフレームにFCSは含まない。
+fn create_hdlc_receiver() -> impl FnMut(bool) -> Option<Vec<u8>> {
+    let mut shift: u8 = 0;
+    let mut ones = 0;
+    let mut byte: u8 = 0;
+    let mut bit_count = 0;
+    let mut frame: Vec<u8> = Vec::new();
+    let mut in_frame = false;
+
+    move |bit: bool| -> Option<Vec<u8>> {
+        shift = (shift >> 1) | if bit { 0x80 } else { 0 };
+
+        // フラグの検出
+        if shift == 0x7E {
+            let mut result = None;
+            if in_frame && frame.len() >= MIN_FRAME && bit_count == 7 && crc_ok(&frame) {
+                frame.truncate(frame.len() - 2);
+                result = Some(std::mem::take(&mut frame));
+            }
+            frame.clear();
+            in_frame = true;
+            byte = 0;
+            bit_count = 0;
+            ones = 0;
+            return result;
+        }
+
+        if !in_frame { return None; }
+
+        if bit {
+            ones += 1;
+            if ones > 6 {
+                in_frame = false;   // アボート
+                frame.clear();
+                return None;
+            }
+        } else {
+            if ones == 5 {
+                ones = 0;           // スタッフィングされた0は捨てる。
+                return None;
+            }
+            ones = 0;
+        }
+
+        // LSBから順に受信する。
+        byte = (byte >> 1) | if bit { 0x80 } else { 0 };
+        bit_count += 1;
+        if bit_count == 8 {
+            frame.push(byte);
+            bit_count = 0;
+            if frame.len() > MAX_FRAME {
+                in_frame = false;
+                frame.clear();
+            }
+        }
+        None
+    }
+}
+
+// CRC-16-CCITT(X.25)の確認。FCSを含めて計算すると0xF0B8になる。
+fn crc_ok(frame: &[u8]) -> bool {
+    crc16(frame) == 0xF0B8
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+// AX.25のフレームをTNC2形式(SRC>DEST,PATH:INFO)にする。
+pub fn frame_to_tnc2(frame: &[u8]) -> Option<String> {
+    let mut addresses: Vec<String> = Vec::new();
+    let mut repeated: Option<usize> = None;     // 中継済みの最後のデジピータ
+    let mut index = 0;
+    loop {
+        if index + 7 > frame.len() || addresses.len() >= 10 { return None; }
+        let field = &frame[index..index + 7];
+        // デジピータのHビット(0x80)が1なら中継済み。宛先と送信元の0x80はCビットなので見ない。
+        if addresses.len() >= 2 && field[6] & 0x80 != 0 {
+            repeated = Some(addresses.len());
+        }
+        addresses.push(decode_address(field)?);
+        index += 7;
+        if field[6] & 0x01 != 0 { break; }  // アドレス拡張ビットが1なら最後のアドレス
+    }
+    if addresses.len() < 2 || index + 2 > frame.len() { return None; }
+    if let Some(n) = repeated {
+        addresses[n].push('*');
+    }
+
+    // UIフレーム(0x03)、PID 0xF0(レイヤ3無し)のみを扱う。
+    if frame[index] & 0xEF != 0x03 || frame[index + 1] != 0xF0 { return None; }
+    let info = String::from_utf8_lossy(&frame[index + 2..]);
+    let info = info.trim_end_matches(['\r', '\n']);
+
+    let mut line = format!("{}>{}", addresses[1], addresses[0]);
+    for address in &addresses[2..] {
+        line.push(',');
+        line.push_str(address);
+    }
+    line.push(':');
+    line.push_str(info);
+    Some(line)
+}
+
+// アドレスフィールド(7バイト)をコールサインにする。
+fn decode_address(field: &[u8]) -> Option<String> {
+    let mut call = String::new();
+    for &b in &field[..6] {
+        let c = (b >> 1) as char;
+        if !c.is_ascii_alphanumeric() && c != ' ' { return None; }
+        if c != ' ' { call.push(c); }
+    }
+    if call.is_empty() { return None; }
+    let ssid = (field[6] >> 1) & 0x0F;
+    if ssid != 0 {
+        call.push_str(&format!("-{}", ssid));
+    }
+    Some(call)
+}
+
+// APRSの情報フィールドを解析して、位置やメッセージを読める形にする。
+pub fn parse_aprs(line: &str) -> Option<String> {
+    let (header, info) = line.split_once(':')?;
+    let destination = header.split_once('>')?.1.split(',').next()?;
+    let info = info.as_bytes();
+    if info.is_empty() { return None; }
+
+    match info[0] {
+        b'!' | b'=' => parse_position(&info[1..]),
+        b'/' | b'@' if info.len() > 8 => parse_position(&info[8..]),
+        b';' if info.len() > 18 => {
+            let name = String::from_utf8_lossy(&info[1..10]).trim().to_string();
+            parse_position(&info[18..]).map(|p| format!("object {} {}", name, p))
+        },
+        b':' if info.len() > 11 && info[10] == b':' => {
+            let addressee = String::from_utf8_lossy(&info[1..10]).trim().to_string();
+            let message = String::from_utf8_lossy(&info[11..]);
+            Some(format!("message to {}: {}", addressee, message))
+        },
+        b'>' => Some(format!("status: {}", String::from_utf8_lossy(&info[1..]))),
+        b'`' | b'\'' => parse_mic_e(destination, info),
+        _ => None,
+    }
+}
+
+// 非圧縮の位置(ddmm.mmN/dddmm.mmE)または圧縮形式の位置
+fn parse_position(data: &[u8]) -> Option<String> {
+    if data.len() >= 19 && data[0].is_ascii_digit() {
+        let text = std::str::from_utf8(&data[..19]).ok()?;
+        let lat_deg: f64 = text.get(0..2)?.parse().ok()?;
+        let lat_min: f64 = text.get(2..7)?.trim().replace(' ', "0").parse().ok()?;
+        let lon_deg: f64 = text.get(9..12)?.parse().ok()?;
+        let lon_min: f64 = text.get(12..17)?.trim().replace(' ', "0").parse().ok()?;
+        let mut lat = lat_deg + lat_min / 60.0;
+        let mut lon = lon_deg + lon_min / 60.0;
+        if data[7] == b'S' { lat = -lat; }
+        if data[17] == b'W' { lon = -lon; }
+        let comment = String::from_utf8_lossy(&data[19..]);
+        return Some(format!("position {:.4} {:.4} {}", lat, lon, comment).trim_end().to_string());
+    }
+    if data.len() >= 13 && (data[0] == b'/' || data[0] == b'\\') {
+        let base91 = |b: &[u8]| b.iter().fold(0.0f64, |acc, &c| acc * 91.0 + (c as f64 - 33.0));
+        let lat = 90.0 - base91(&data[1..5]) / 380926.0;
+        let lon = -180.0 + base91(&data[5..9]) / 190463.0;
+        let comment = String::from_utf8_lossy(&data[13..]);
+        return Some(format!("position {:.4} {:.4} {}", lat, lon, comment).trim_end().to_string());
+    }
+    None
+}
+
+// Mic-E形式の位置。緯度は宛先のコールサインに、経度は情報フィールドに入っている。
+fn parse_mic_e(destination: &str, info: &[u8]) -> Option<String> {
+    let dest = destination.split('-').next()?.as_bytes();
+    if dest.len() < 6 || info.len() < 9 { return None; }
+
+    let mut digits = [0u32; 6];
+    for (d, &c) in digits.iter_mut().zip(dest.iter()) {
+        *d = match c {
+            b'0'..=b'9' => (c - b'0') as u32,
+            b'A'..=b'J' => (c - b'A') as u32,
+            b'P'..=b'Y' => (c - b'P') as u32,
+            b'K' | b'L' | b'Z' => 0,
+            _ => return None,
+        };
+    }
+    let flag = |c: u8| (b'P'..=b'Z').contains(&c);
+    let mut lat = digits[0] as f64 * 10.0 + digits[1] as f64
+        + (digits[2] as f64 * 10.0 + digits[3] as f64 + (digits[4] as f64 * 10.0 + digits[5] as f64) / 100.0) / 60.0;
+    if !flag(dest[3]) { lat = -lat; }
+
+    let mut lon_deg = info[1] as i32 - 28;
+    if flag(dest[4]) { lon_deg += 100; }
+    if (180..=189).contains(&lon_deg) { lon_deg -= 80; }
+    if (190..=199).contains(&lon_deg) { lon_deg -= 190; }
+    let mut lon_min = info[2] as i32 - 28;
+    if lon_min >= 60 { lon_min -= 60; }
+    let lon_hundredths = info[3] as i32 - 28;
+    let mut lon = lon_deg as f64 + (lon_min as f64 + lon_hundredths as f64 / 100.0) / 60.0;
+    if flag(dest[5]) { lon = -lon; }
+
+    // 速度[knot]と進路[度]
+    let sp = info[4] as i32 - 28;
+    let dc = info[5] as i32 - 28;
+    let se = info[6] as i32 - 28;
+    let mut speed = sp * 10 + dc / 10;
+    if speed >= 800 { speed -= 800; }
+    let mut course = (dc % 10) * 100 + se;
+    if course >= 400 { course -= 400; }
+
+    Some(format!("position {:.4} {:.4} speed {} kt course {}", lat, lon, speed, course))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    // アドレスフィールド(コールサイン、SSID、最後のアドレスか、0x80のビット)
+    fn address(call: &str, ssid: u8, last: bool, bit7: bool) -> Vec<u8> {
+        let mut field: Vec<u8> = format!("{:6}", call).bytes().map(|b| b << 1).collect();
+        field.push(0x60 | (ssid << 1) | if last { 0x01 } else { 0 } | if bit7 { 0x80 } else { 0 });
+        field
+    }
+
+    // 宛先、送信元、デジピータ(コールサイン、SSID、中継済み)と情報フィールドからフレーム(FCS無し)を作る。
+    fn frame(digis: &[(&str, u8, bool)], info: &str) -> Vec<u8> {
+        let mut frame = address("APRS", 0, false, true);            // コマンドなので宛先のCビットは1
+        frame.extend(address("JA1XYZ", 7, digis.is_empty(), false));
+        for (n, &(call, ssid, used)) in digis.iter().enumerate() {
+            frame.extend(address(call, ssid, n == digis.len() - 1, used));
+        }
+        frame.extend([0x03, 0xF0]);
+        frame.extend(info.bytes());
+        frame
+    }
+
+    // FCSを付ける(下位バイトから)。
+    fn with_fcs(frame: &[u8]) -> Vec<u8> {
+        let fcs = !crc16(frame);
+        let mut data = frame.to_vec();
+        data.extend(fcs.to_le_bytes());
+        data
+    }
+
+    // フラグで挟み、ビットスタッフィングしたビット列にする(LSBから)。
+    fn hdlc_bits(data: &[u8]) -> Vec<bool> {
+        let flag = |bits: &mut Vec<bool>| bits.extend((0..8).map(|k| 0x7E >> k & 1 != 0));
+        let mut bits = Vec::new();
+        for _ in 0..4 { flag(&mut bits); }
+        let mut ones = 0;
+        for &b in data {
+            for k in 0..8 {
+                let bit = b >> k & 1 != 0;
+                bits.push(bit);
+                ones = if bit { ones + 1 } else { 0 };
+                if ones == 5 {
+                    bits.push(false);
+                    ones = 0;
+                }
+            }
+        }
+        for _ in 0..2 { flag(&mut bits); }
+        bits
+    }
+
+    #[test]
+    fn crc_of_frame_with_fcs() {
+        let data = with_fcs(&frame(&[], ">test"));
+        assert!(crc_ok(&data));
+        let mut corrupted = data.clone();
+        corrupted[16] ^= 0x04;
+        assert!(!crc_ok(&corrupted));
+        assert_eq!(crc16(b"123456789"), !0x906E);     // CRC-16/X-25のチェック値は0x906E
+    }
+
+    #[test]
+    fn hdlc_removes_stuffing_and_checks_crc() {
+        // 0xFFや0x7Eを含む情報フィールドでも、スタッフィングを除いて元のフレームに戻る。
+        let original = frame(&[], ">\x7f~\x7f~");
+        let mut hdlc = create_hdlc_receiver();
+        let frames: Vec<Vec<u8>> = hdlc_bits(&with_fcs(&original)).into_iter().filter_map(&mut hdlc).collect();
+        assert_eq!(frames, vec![original.clone()]);
+
+        // CRCが誤っているフレームは返さない。
+        let mut corrupted = with_fcs(&original);
+        corrupted[3] ^= 0x02;
+        assert!(hdlc_bits(&corrupted).into_iter().filter_map(&mut hdlc).next().is_none());
+    }
+
+    #[test]
+    fn tnc2_without_path() {
+        // 宛先のCビット(0x80)を中継済みとみなさない。
+        assert_eq!(frame_to_tnc2(&frame(&[], ">status")).as_deref(), Some("JA1XYZ-7>APRS:>status"));
+    }
+
+    #[test]
+    fn tnc2_marks_last_used_digipeater() {
+        let partly = frame(&[("WIDE1", 1, true), ("WIDE2", 1, false)], "!");
+        assert_eq!(frame_to_tnc2(&partly).as_deref(), Some("JA1XYZ-7>APRS,WIDE1-1*,WIDE2-1:!"));
+        let unused = frame(&[("WIDE1", 1, false), ("WIDE2", 2, false)], "!");
+        assert_eq!(frame_to_tnc2(&unused).as_deref(), Some("JA1XYZ-7>APRS,WIDE1-1,WIDE2-2:!"));
+        let all = frame(&[("JA1DGI", 0, true), ("WIDE2", 1, true)], "!");
+        assert_eq!(frame_to_tnc2(&all).as_deref(), Some("JA1XYZ-7>APRS,JA1DGI,WIDE2-1*:!"));
+    }
+
+    #[test]
+    fn parses_positions() {
+        assert_eq!(parse_aprs("JA1XYZ>APRS:!3540.00N/13945.00E-Test").as_deref(), Some("position 35.6667 139.7500 Test"));
+        assert_eq!(parse_aprs("JA1XYZ>APRS:@092345z3540.00S/13945.00W>").as_deref(), Some("position -35.6667 -139.7500"));
+        // 圧縮形式(APRS仕様書の例)
+        assert_eq!(parse_aprs("JA1XYZ>APRS:=/5L!!<*e7>{?!").as_deref(), Some("position 49.5000 -72.7500"));
+    }
+
+    #[test]
+    fn parses_mic_e() {
+        // APRS仕様書の例: 宛先S32U6T、北緯33度25.64分、速度20ノット、進路251度
+        assert_eq!(parse_aprs("JA1XYZ>S32U6T:`(_fn\"Oj/").as_deref(), Some("position 33.4273 -12.1290 speed 20 kt course 251"));
+    }
+
+    #[test]
+    fn parses_messages() {
+        assert_eq!(parse_aprs("JA1XYZ>APRS::JA1ABC   :hello{1").as_deref(), Some("message to JA1ABC: hello{1"));
+        assert_eq!(parse_aprs("JA1XYZ>APRS:>on the air").as_deref(), Some("status: on the air"));
+    }
+
+    #[test]
+    fn decodes_afsk1200() {
+        // NRZI(0で変化)にしてAFSK1200で変調し、デコーダに通す。
+        let original = frame(&[("WIDE1", 1, true), ("WIDE2", 1, false)], "!3540.00N/13945.00E-Test");
+        let mut level = true;
+        let mut phase = 0.0f32;
+        let mut samples = vec![0.0f32; 1000];
+        for bit in hdlc_bits(&with_fcs(&original)) {
+            if !bit { level = !level; }
+            let freq = if level { MARK_FREQ } else { SPACE_FREQ };
+            for _ in 0..BIT_SAMPLES {
+                phase = (phase + 2.0 * PI * freq / SAMPLING_FREQ) % (2.0 * PI);
+                samples.push(0.5 * phase.sin());
+            }
+        }
+        samples.resize(samples.len() + 1000, 0.0);
+
+        let (frame_tx, frame_rx) = channel();
+        let mut decoder = create_aprs_decoder(frame_tx);
+        let text: String = samples.chunks(1024).map(&mut decoder).collect();
+        assert_eq!(text, "JA1XYZ-7>APRS,WIDE1-1*,WIDE2-1:!3540.00N/13945.00E-Test\n  position 35.6667 139.7500 Test\n");
+        assert_eq!(frame_rx.try_recv().ok(), Some(original));       // KISSで送るフレーム
+    }
+}
@@ -0,0 +1,258 @@
+// サウンドカードとのIF(入力)と音声(出力)のやり取り
+use crate::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+use cpal::traits::DeviceTrait;
+use crate::spsc::{ChunkConsumer, ChunkProducer};
+use crate::resampler::{create_drift_controller, create_resampler};
+
+// キューの大きさと音声の出力関連の定数の定義
+pub const IF_QUEUE_SIZE: usize = 16;                // IFのキュー(約0.34秒分)
+pub const AUDIO_QUEUE_SIZE: usize = 8;              // 音声のキュー(約0.17秒分)
+pub const PREFILL: usize = 2;                       // 出力を始める(再開する)前に溜めるチャンク数
+const FADE_STEP: f32 = 1.0 / CHUNK_SIZE as f32;     // 1チャンクかけて音量を上げ下げする。
+const DRIFT_TARGET: f64 = (PREFILL as f64 + 0.5) * CHUNK_SIZE as f64;   // チャンクを取り出した時に溜まっているサンプル数の目標
+
+/// 左右の音声のチャンク([左, 右])
+pub type StereoChunk = [[f32; CHUNK_SIZE]; 2];
+
+/// IFの入力  デバイスの最初の2チャンネルを左右のチャンクにまとめてif_txに送る。
+/// モノラルのデバイスでは、左右に同じ信号を送る。IFとして使うチャンネルはiq::create_input_converterで選ぶ。
+/// 処理が間に合わずにキューが一杯の時は、チャンクを捨てて溢れた数を数える。
+///
+/// ```no_run
+/// use cpal::traits::{HostTrait, StreamTrait};
+/// use thsdr::spsc::chunk_queue;
+///
+/// let device = cpal::default_host().default_input_device().unwrap();
+/// let (if_tx, mut if_rx) = chunk_queue(16);
+/// let stream = thsdr::audio::create_input_stream(&device, if_tx)?;
+/// stream.play()?;
+/// let [left, right] = if_rx.wait_pop().unwrap();
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn create_input_stream(
+    device: &cpal::Device,
+    mut if_tx: ChunkProducer,
+) -> Result<cpal::Stream, anyhow::Error> {
+    let mut config: cpal::StreamConfig = device.default_input_config()?.into();
+    config.sample_rate = cpal::SampleRate( SAMPLING_FREQ as u32 );     // 処理はSAMPLING_FREQで行うので、デバイスの標準の値は使わない。
+
+    #[cfg(debug_assertions)]
+    println!( "Input channels = {}", config.channels );
+
+    let mut if_data: StereoChunk = [[0.0; CHUNK_SIZE]; 2];
+    let mut count = 0;
+    let right = if config.channels >= 2 { 1 } else { 0 };
+
+    let input_stream = device.build_input_stream(
+        &config,
+        move |data: &[f32], _: &cpal::InputCallbackInfo| {
+
+            for i in (0..data.len()).step_by(config.channels as usize) {   // 2チャンネルの信号のみを取り出す
+                if_data[0][count] = data[i];
+                if_data[1][count] = data[i + right];
+
+                count += 1;
+                if count >= CHUNK_SIZE {
+                    if_tx.push( &if_data );
+                    count = 0;
+                }
+            }
+        },
+        |err| eprintln!("Input error: {:?}", err),
+    )?;
+    Ok(input_stream)
+}
+
+/// 音声の出力  audio_rxから受け取ったチャンクの左を偶数番目、右を奇数番目のチャンネルに出力する。
+/// モノラルのデバイスには左右の平均を出力する。
+///
+/// ```no_run
+/// use cpal::traits::{HostTrait, StreamTrait};
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::spsc::chunk_queue;
+///
+/// let device = cpal::default_host().default_output_device().unwrap();
+/// let (mut audio_tx, audio_rx) = chunk_queue(8);
+/// let stream = thsdr::audio::create_output_stream(&device, audio_rx)?;
+/// stream.play()?;
+/// audio_tx.push(&[[0.0; CHUNK_SIZE]; 2]);      // 無音を1チャンク出力する。
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn create_output_stream(
+    device: &cpal::Device,
+    audio_rx: ChunkConsumer,
+) -> Result<cpal::Stream, anyhow::Error> {
+    let mut config: cpal::StreamConfig = device.default_output_config()?.into();
+    config.sample_rate = cpal::SampleRate( SAMPLING_FREQ as u32 );
+    let mut next_frame = create_frame_reader( audio_rx );
+
+    let output_stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+
+            for frame in data.chunks_mut(config.channels as usize) {
+                let (l, r) = next_frame();
+                if config.channels == 1 {
+                    frame[0] = (l + r) / 2.0;
+                } else {
+                    for (j, d) in frame.iter_mut().enumerate() {
+                        *d = if j % 2 == 0 { l } else { r };
+                    }
+                }
+            }
+        },
+        |err| eprintln!("Output error: {:?}", err),
+    )?;
+    Ok(output_stream)
+}
+
+/// キューから1サンプルずつ(左、右)を取り出す。出力のコールバックから呼ぶので、待たずに必ず値を返す。
+/// キューが空になった時(アンダーラン)は、最後のチャンクを繰り返しながら音量を下げて無音にし、途切れた回数を数える。
+/// その後、PREFILLチャンク溜まってから音量を上げて再開する。
+/// 入力と出力のサウンドカードのクロックのずれは、キューに溜まっているサンプル数が一定になるようにリサンプルして補正する。
+///
+/// ```
+/// use thsdr::audio::{create_frame_reader, PREFILL};
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::spsc::chunk_queue;
+///
+/// let (mut tx, rx) = chunk_queue(8);
+/// let mut next_frame = create_frame_reader(rx);
+/// assert!((0..CHUNK_SIZE).all(|_| next_frame() == (0.0, 0.0)));     // 溜まるまでは無音
+/// for _ in 0..PREFILL {
+///     tx.push(&[[1.0; CHUNK_SIZE]; 2]);
+/// }
+/// let frames: Vec<(f32, f32)> = (0..4 * CHUNK_SIZE).map(|_| next_frame()).collect();
+/// assert!(frames.contains(&(1.0, 1.0)));                  // 1チャンクかけて音量を上げる。
+/// assert_eq!(tx.stats().underruns, 1);                     // 次のチャンクが無いので途切れた。
+/// assert_eq!(frames[4 * CHUNK_SIZE - 1], (0.0, 0.0));      // 最後のチャンクを繰り返しながら音量を下げる。
+/// ```
+pub fn create_frame_reader( mut audio_rx: ChunkConsumer ) -> impl FnMut() -> (f32, f32) {
+    let mut current: StereoChunk = [[0.0; CHUNK_SIZE]; 2];
+    let mut pos = CHUNK_SIZE;
+    let mut playing = false;
+    let mut gain: f32 = 0.0;
+    let mut controller = create_drift_controller( DRIFT_TARGET );
+    let mut resampler = create_resampler();
+    let mut ratio: f64 = 1.0;
+
+    move || -> (f32, f32) {
+        resampler( ratio, &mut || {
+            if pos >= CHUNK_SIZE {
+                pos = 0;
+                if !playing && audio_rx.stats().depth >= PREFILL {
+                    playing = true;
+                }
+                match if playing { audio_rx.pop() } else { None } {
+                    Some(chunk) => {
+                        current = chunk;
+                        // 取り出したチャンクとキューに残っているサンプル数から、クロックのずれを補正する比率を求める。
+                        ratio = controller( ((audio_rx.stats().depth + 1) * CHUNK_SIZE) as f64 );
+                    },
+                    None => {
+                        if playing {
+                            audio_rx.count_underrun();
+                            playing = false;
+                        }
+                        // 消音し終わっていれば無音にする。
+                        if gain == 0.0 {
+                            current = [[0.0; CHUNK_SIZE]; 2];
+                        }
+                    },
+                }
+            }
+            gain = if playing { (gain + FADE_STEP).min(1.0) } else { (gain - FADE_STEP).max(0.0) };
+            let frame = (current[0][pos] * gain, current[1][pos] * gain);
+            pos += 1;
+            frame
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spsc::chunk_queue;
+
+    const INPUT_BLOCK: usize = 512;         // 入力のコールバックのサンプル数
+    const OUTPUT_BLOCK: usize = 480;        // 出力のコールバックのサンプル数
+    const SETTLE_TIME: f64 = 600.0;         // 追従するまでの時間[秒]
+    const MAX_LATENCY_SPREAD: f64 = 0.25;   // 追従した後の遅延の平均の変化の許容値[チャンク]
+
+    // 入力のクロックを出力よりppmずらして、入力と出力のコールバックを模擬し、キューと出力側のリサンプラをhours時間分動かす。
+    // 追従した後、report秒ごとのキューに溜まっているチャンク数の平均と、途切れと溢れの回数を返す。
+    fn simulate(ppm: f64, hours: f64, report: f64) -> (Vec<f64>, u64) {
+        let input_rate = SAMPLING_FREQ as f64 * (1.0 + ppm * 1e-6);
+        let (mut tx, rx) = chunk_queue(AUDIO_QUEUE_SIZE);
+        let mut next_frame = create_frame_reader(rx);
+
+        let mut input_samples: u64 = 0;     // 入力のサンプル数(入力のクロック)
+        let mut output_samples: u64 = 0;    // 出力のサンプル数(出力のクロック、時刻の基準)
+        let mut chunk: StereoChunk = [[0.0; CHUNK_SIZE]; 2];
+        let mut count = 0;
+        let mut depth_sum: f64 = 0.0;
+        let mut depth_n: u64 = 0;
+        let mut settled: Option<(u64, u64)> = None;    // 追従した時の途切れと溢れの回数
+        let mut latencies = Vec::new();
+
+        let end = (hours * 3600.0 * SAMPLING_FREQ as f64) as u64;
+        while output_samples < end {
+            // この時刻までに入力のコールバックが受け取ったサンプルをチャンクにしてキューに送る(処理は遅延無しとする)。
+            let time = output_samples as f64 / SAMPLING_FREQ as f64;
+            while (input_samples + INPUT_BLOCK as u64) as f64 <= time * input_rate {
+                for _ in 0..INPUT_BLOCK {
+                    chunk[0][count] = 0.5;
+                    chunk[1][count] = 0.5;
+                    input_samples += 1;
+                    count += 1;
+                    if count >= CHUNK_SIZE {
+                        tx.push(&chunk);
+                        count = 0;
+                    }
+                }
+            }
+
+            // 出力のコールバック
+            for _ in 0..OUTPUT_BLOCK {
+                next_frame();
+            }
+            output_samples += OUTPUT_BLOCK as u64;
+            depth_sum += tx.stats().depth as f64;
+            depth_n += 1;
+
+            let time = output_samples as f64 / SAMPLING_FREQ as f64;
+            if settled.is_none() && time >= SETTLE_TIME {
+                settled = Some((tx.stats().underruns, tx.stats().overruns));
+                depth_sum = 0.0;
+                depth_n = 0;
+            }
+            if settled.is_some() && depth_n as f64 * OUTPUT_BLOCK as f64 >= report * SAMPLING_FREQ as f64 {
+                latencies.push(depth_sum / depth_n as f64);
+                depth_sum = 0.0;
+                depth_n = 0;
+            }
+        }
+        let (underruns, overruns) = settled.unwrap_or_default();
+        (latencies, tx.stats().underruns - underruns + tx.stats().overruns - overruns)
+    }
+
+    // 遅延の平均が2回以上求まり、その変化が許容値以内で、途切れも溢れも無いことを確認する。
+    fn check_drift(ppm: f64, hours: f64, report: f64) {
+        let (latencies, xruns) = simulate(ppm, hours, report);
+        assert!(latencies.len() >= 2, "{:+}ppm: 遅延の平均が{}回しか求まらない", ppm, latencies.len());
+        let max = latencies.iter().cloned().fold(f64::MIN, f64::max);
+        let min = latencies.iter().cloned().fold(f64::MAX, f64::min);
+        assert!(max - min <= MAX_LATENCY_SPREAD, "{:+}ppm: 遅延の平均が{:.3}～{:.3}チャンクで変化した", ppm, min, max);
+        assert_eq!(xruns, 0, "{:+}ppm: 途切れか溢れが{}回あった", ppm, xruns);
+    }
+
+    // 時間がかかるので、cargo test --release -- --ignored で実行する。
+    #[test]
+    #[ignore]
+    fn latency_stays_constant_for_hours() {
+        // 2つのクロックが数十ppmずれている時と、±100ppmずれている時(4時間、30分ごと)
+        for ppm in [-100.0, -50.0, 0.0, 50.0, 100.0] {
+            check_drift(ppm, 4.0, 1800.0);
+        }
+    }
+}
@@ -1,22 +1,21 @@
-use crate::constants::{CHUNK_SIZE, SAMPLING_FREQ};
-use core::f32::consts::PI;
+use crate::constants::CHUNK_SIZE;
+use crate::nco::create_nco;
 
+/// BFO
+/// NCOの正弦波を出力する。周波数を変えても位相は連続する。
+///
+/// ```
+/// use thsdr::bfo::create_bfo;
+///
+/// let mut bfo = create_bfo();
+/// let output = bfo(12000.0);
+/// assert!(output.iter().all(|x| x.abs() <= 1.0));
+/// ```
 pub fn create_bfo() -> impl FnMut(f32) -> [f32; CHUNK_SIZE] {
 
-    let mut phase:f32 = 0.0;
+    let mut nco = create_nco();
 
     move |freq: f32| -> [f32; CHUNK_SIZE] {
-        let mut result: [f32; CHUNK_SIZE]=[0.0; CHUNK_SIZE];
-
-        let phase_delta = 2.0*PI*freq/SAMPLING_FREQ;
-
-        for i in 0..CHUNK_SIZE {
-            result[i] = phase.sin();
-            phase = phase+phase_delta;
-            if phase > 2.0*PI {
-                phase -= 2.0*PI;
-            }
-        }
-        result
+        nco( freq, 1.0 ).0
     }
 }
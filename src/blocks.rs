@@ -0,0 +1,484 @@
+// 受信の各段を処理ブロックにしたもの
+// 新しい段を加える時は、ここにブロックを作る関数を書いてcreate_blockに名前を登録する。
+use crate::constants::CHUNK_SIZE;
+use crate::pipeline::{closure_block, Context, DspBlock, Pipeline};
+use crate::firfilter::{create_filter, create_variable_filter, FilterType, N, VN};
+use crate::agc::{create_agc, AGCType};
+use crate::bfo::create_bfo;
+use crate::fmdemod::{create_fm_demod, FM_DEMOD_LATENCY};
+use crate::tonedetect::create_tone_detector;
+use crate::squelch::{create_squelch, SquelchMode, SquelchParams};
+use crate::notch::{create_anf, create_notch, create_peak};
+use crate::decoder::{create_decoder, DecoderType};
+use crate::fileout::FileOutput;
+use std::sync::mpsc::Sender;
+
+/// 標準の構成
+/// IFフィルタ → AGC → BFO → 検波 → トーン検出 → スケルチ → AFフィルタ → CWピーク → デコーダ → ANF → ノッチ → ミュート
+/// デコーダはANFとノッチの前に置く。ANFは持続する音を消すので、後に置くとデコードするトーンまで消してしまう。
+pub const DEFAULT_CHAIN: [&str; 12] = ["IF", "AGC", "BFO", "DET", "TONE", "SQL", "AF", "PEAK", "DEC", "ANF", "NOTCH", "MUTE"];
+
+/// 名前を並べた構成から処理をつくる。各ブロックは初期の設定になる。
+///
+/// ```
+/// use thsdr::blocks::{build_pipeline, DEFAULT_CHAIN};
+/// use std::sync::mpsc::channel;
+///
+/// let (frame_tx, _frame_rx) = channel();
+/// let (file_tx, _file_rx) = channel();
+/// assert!(build_pipeline(&DEFAULT_CHAIN, frame_tx.clone(), file_tx.clone()).is_ok());
+/// assert!(build_pipeline(&["IF", "FOO"], frame_tx.clone(), file_tx.clone()).is_err());
+/// assert!(build_pipeline(&["AGC", "AGC"], frame_tx, file_tx).is_err());
+/// ```
+pub fn build_pipeline(names: &[&str], frame_tx: Sender<Vec<u8>>, file_tx: Sender<FileOutput>) -> Result<Pipeline, anyhow::Error> {
+    let mut blocks: Vec<Box<dyn DspBlock>> = Vec::new();
+    for name in names {
+        let block = create_block(name, frame_tx.clone(), file_tx.clone())
+            .ok_or_else(|| anyhow::anyhow!("{}というブロックはありません。", name))?;
+        if blocks.iter().any(|b| b.name() == block.name()) {
+            anyhow::bail!("{}のブロックが重複しています。", name);
+        }
+        blocks.push(block);
+    }
+    Ok(Pipeline::new(blocks))
+}
+
+/// 名前から初期の設定のブロックをつくる。
+///
+/// ```
+/// use thsdr::blocks::create_block;
+/// use std::sync::mpsc::channel;
+///
+/// let (frame_tx, _frame_rx) = channel();
+/// let (file_tx, _file_rx) = channel();
+/// assert_eq!(create_block("NOTCH", frame_tx.clone(), file_tx.clone()).unwrap().name(), "NOTCH");
+/// assert!(create_block("FOO", frame_tx, file_tx).is_none());
+/// ```
+pub fn create_block(name: &str, frame_tx: Sender<Vec<u8>>, file_tx: Sender<FileOutput>) -> Option<Box<dyn DspBlock>> {
+    match name {
+        "IF" => Some(if_block()),
+        "AGC" => Some(agc_block(AGCType::AGC05)),
+        "BFO" => Some(bfo_block()),
+        "DET" => Some(detector_block()),
+        "TONE" => Some(tone_block()),
+        "SQL" => Some(squelch_block(SquelchParams::OFF)),
+        "AF" => Some(af_block(FilterType::AF11K)),
+        "PEAK" => Some(peak_block(700.0, 800.0)),
+        "ANF" => Some(anf_block(false)),
+        "NOTCH" => Some(notch_block(0.0, 0.0)),
+        "DEC" => Some(decoder_block(DecoderType::None, 700.0, frame_tx, file_tx)),
+        "MUTE" => Some(mute_block()),
+        _ => None,
+    }
+}
+
+/// IFフィルタ  通過域はモード、キャリア、シフト、帯域幅から求める。
+///
+/// ```
+/// use core::f32::consts::PI;
+/// use thsdr::blocks::if_block;
+/// use thsdr::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+/// use thsdr::firfilter::FilterType;
+/// use thsdr::pipeline::Context;
+///
+/// // USBでは、キャリア(12kHz)より上の音を通し、下の音を除く。
+/// let power = |freq: f32| {
+///     let mut block = if_block();
+///     let mut context = Context { if_type: FilterType::USB3K, ..Context::default() };
+///     let mut output = [0.0; CHUNK_SIZE];
+///     for n in 0..8 {
+///         let input: [f32; CHUNK_SIZE] = std::array::from_fn(|i| (2.0 * PI * freq * (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ).sin());
+///         output = block.process(&input, &mut context);
+///     }
+///     output.iter().map(|x| x * x).sum::<f32>() / CHUNK_SIZE as f32
+/// };
+/// assert!(power(13000.0) > 0.4);
+/// assert!(power(10000.0) < 0.001);
+/// ```
+pub fn if_block() -> Box<dyn DspBlock> {
+    closure_block("IF", "可変バンドパスフィルタ".to_string(), VN / 2, || {
+        let mut if_filter = create_variable_filter();
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            if_filter(input, if_passband(context.if_type, context.carrier, context.if_shift, context.if_width))
+        }
+    })
+}
+
+/// IFフィルタの通過域(絶対周波数)を求める。
+/// モードで決まる通過域の中心をshiftだけ移動し、widthが正の時はその帯域幅にする。
+///
+/// ```
+/// use thsdr::blocks::if_passband;
+/// use thsdr::firfilter::FilterType;
+///
+/// assert_eq!(if_passband(FilterType::USB3K, 12000.0, 0.0, 0.0), Some((12200.0, 14900.0)));
+/// assert_eq!(if_passband(FilterType::USB3K, 12000.0, 100.0, 1000.0), Some((13150.0, 14150.0)));
+/// ```
+pub fn if_passband( ftype: FilterType, carrier: f32, shift: f32, width: f32 ) -> Option<(f32, f32)> {
+    ftype.passband().map( |(low, high)| {
+        let center = carrier + (low + high) / 2.0 + shift;
+        let width = if width > 0.0 { width } else { high - low };
+        (center - width / 2.0, center + width / 2.0)
+    })
+}
+
+/// AGC  RSSIをContextに書き込む。
+///
+/// ```
+/// use thsdr::agc::AGCType;
+/// use thsdr::blocks::agc_block;
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::pipeline::Context;
+///
+/// let mut agc = agc_block(AGCType::AGC05);
+/// let mut context = Context::default();
+/// agc.process(&[0.5; CHUNK_SIZE], &mut context);
+/// assert!(context.rssi > 0.0);
+/// ```
+pub fn agc_block(atype: AGCType) -> Box<dyn DspBlock> {
+    closure_block("AGC", format!("{:?}", atype), 0, move || {
+        let mut agc = create_agc(atype);
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            let (data, rssi) = agc(input);
+            context.rssi = rssi;
+            data
+        }
+    })
+}
+
+/// BFO  CWの時は、キャリアがピッチの周波数で聞こえるようにBFOを置く。FMの時は加えない。
+///
+/// ```
+/// use thsdr::blocks::bfo_block;
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::firfilter::FilterType;
+/// use thsdr::pipeline::Context;
+///
+/// let mut bfo = bfo_block();
+/// let mut context = Context::default();       // AMでBFOの周波数が0の時は加えない。
+/// assert!(bfo.process(&[0.0; CHUNK_SIZE], &mut context).iter().all(|&x| x == 0.0));
+/// context.if_type = FilterType::CW(500.0);
+/// assert!(bfo.process(&[0.0; CHUNK_SIZE], &mut context).iter().any(|&x| x != 0.0));
+/// context.fm_mode = true;
+/// assert!(bfo.process(&[0.0; CHUNK_SIZE], &mut context).iter().all(|&x| x == 0.0));
+/// ```
+pub fn bfo_block() -> Box<dyn DspBlock> {
+    closure_block("BFO", "BFOの加算".to_string(), 0, || {
+        let mut bfo = create_bfo();
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            let bfo_out = match context.if_type {
+                FilterType::CW(_) => context.carrier - context.cw_pitch,
+                _ => if context.bfo_freq != 0.0 { context.carrier } else { 0.0 },
+            };
+            let mut data = *input;
+            if bfo_out != 0.0 && !context.fm_mode {
+                let bfo_signal = bfo( bfo_out );
+                for (d, b) in data.iter_mut().zip(bfo_signal.iter()) {
+                    *d += b;
+                }
+            }
+            data
+        }
+    })
+}
+
+/// 検波  AMとSSBは絶対値による包絡線検波、FMは位相差分による復調
+///
+/// ```
+/// use thsdr::blocks::detector_block;
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::fmdemod::FM_DEMOD_LATENCY;
+/// use thsdr::pipeline::Context;
+///
+/// let mut detector = detector_block();
+/// let mut context = Context::default();
+/// assert_eq!(detector.process(&[-1.0; CHUNK_SIZE], &mut context)[0], 1.0);
+/// assert_eq!(detector.process(&[0.0; CHUNK_SIZE], &mut context)[0], -1.0);
+/// assert_eq!(detector.latency(), FM_DEMOD_LATENCY);
+/// ```
+pub fn detector_block() -> Box<dyn DspBlock> {
+    closure_block("DET", "包絡線検波/FM復調".to_string(), FM_DEMOD_LATENCY, || {
+        let mut fm_demod = create_fm_demod();
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            if context.fm_mode {
+                fm_demod( input, context.carrier )
+            } else {
+                input.map( |x| (x.abs()-0.5)*2.0 )
+            }
+        }
+    })
+}
+
+/// トーン検出  FMの時は、CTCSS、DCS、DTMFを検出してトーンスケルチを判定する。音声はそのまま通す。
+///
+/// ```
+/// use thsdr::blocks::tone_block;
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::pipeline::Context;
+///
+/// // FM以外の時は、トーンスケルチは常に開いている。
+/// let mut tone = tone_block();
+/// let mut context = Context { tone_open: false, ..Context::default() };
+/// assert_eq!(tone.process(&[0.5; CHUNK_SIZE], &mut context), [0.5; CHUNK_SIZE]);
+/// assert!(context.tone_open);
+/// ```
+pub fn tone_block() -> Box<dyn DspBlock> {
+    closure_block("TONE", "CTCSS/DCS/DTMF検出".to_string(), 0, || {
+        let mut tone_detector = create_tone_detector();
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            context.tone_open = if context.fm_mode {
+                let status = tone_detector( input );
+                if context.tone_display {
+                    context.text.push_str(&status.text);
+                }
+                context.tone_squelch.is_open( &status )
+            } else {
+                true
+            };
+            *input
+        }
+    })
+}
+
+/// スケルチ  開いているかどうかをContextに書き込む。音声はそのまま通す。
+/// RSSIはsmeter_offsetで補正してから判定するので、SQL LEVELのしきい値はIFのRSSIの表示と同じ値になる。
+///
+/// ```
+/// use thsdr::blocks::squelch_block;
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::pipeline::Context;
+/// use thsdr::squelch::{SquelchMode, SquelchParams};
+///
+/// // RSSIが-20dBを超えると開く。
+/// let params = SquelchParams { mode: SquelchMode::Level, level: -20.0, hysteresis: 2.0, tail: 0.0 };
+/// let mut context = Context { rssi: 0.001, ..Context::default() };      // -60dB
+/// assert_eq!(squelch_block(params).process(&[0.5; CHUNK_SIZE], &mut context), [0.5; CHUNK_SIZE]);
+/// assert!(!context.squelch_open);
+/// context.rssi = 0.5;
+/// squelch_block(params).process(&[0.5; CHUNK_SIZE], &mut context);
+/// assert!(context.squelch_open);
+///
+/// // 補正値を加えた-60+50=-10dBで判定する。
+/// let mut context = Context { rssi: 0.001, smeter_offset: 50.0, ..Context::default() };
+/// squelch_block(params).process(&[0.5; CHUNK_SIZE], &mut context);
+/// assert!(context.squelch_open);
+/// ```
+pub fn squelch_block(params: SquelchParams) -> Box<dyn DspBlock> {
+    let description = match params.mode {
+        SquelchMode::Level => format!("LEVEL {}dB", params.level),
+        SquelchMode::Noise => format!("NOISE {}dB", params.level),
+        SquelchMode::SNR => format!("SNR {}dB", params.level),
+        SquelchMode::None => "OFF".to_string(),
+    };
+    closure_block("SQL", description, 0, move || {
+        let mut squelch = create_squelch(params);
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            let rssi = context.rssi * 10.0_f32.powf( context.smeter_offset / 20.0 );
+            context.squelch_open = squelch( rssi, input );
+            *input
+        }
+    })
+}
+
+/// AF出力用フィルタ
+///
+/// ```
+/// use core::f32::consts::PI;
+/// use thsdr::blocks::af_block;
+/// use thsdr::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+/// use thsdr::firfilter::FilterType;
+/// use thsdr::pipeline::Context;
+///
+/// let power = |freq: f32| {
+///     let mut block = af_block(FilterType::AF3K);
+///     let mut context = Context::default();
+///     let mut output = [0.0; CHUNK_SIZE];
+///     for n in 0..8 {
+///         let input: [f32; CHUNK_SIZE] = std::array::from_fn(|i| (2.0 * PI * freq * (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ).sin());
+///         output = block.process(&input, &mut context);
+///     }
+///     output.iter().map(|x| x * x).sum::<f32>() / CHUNK_SIZE as f32
+/// };
+/// assert!(power(1000.0) > 0.4);
+/// assert!(power(8000.0) < 0.001);
+/// ```
+pub fn af_block(ftype: FilterType) -> Box<dyn DspBlock> {
+    closure_block("AF", format!("{:?}", ftype), N / 2, move || {
+        let mut af_filter = create_filter(ftype);
+        move |input: &[f32; CHUNK_SIZE], _: &mut Context| af_filter( input )
+    })
+}
+
+/// CWのピークフィルタ  CWの時だけ動作する。
+///
+/// ```
+/// use core::f32::consts::PI;
+/// use thsdr::blocks::peak_block;
+/// use thsdr::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+/// use thsdr::firfilter::FilterType;
+/// use thsdr::pipeline::Context;
+///
+/// let power = |freq: f32| {
+///     let mut block = peak_block(700.0, 100.0);
+///     let mut context = Context { if_type: FilterType::CW(500.0), ..Context::default() };
+///     let mut output = [0.0; CHUNK_SIZE];
+///     for n in 0..8 {
+///         let input: [f32; CHUNK_SIZE] = std::array::from_fn(|i| (2.0 * PI * freq * (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ).sin());
+///         output = block.process(&input, &mut context);
+///     }
+///     output.iter().map(|x| x * x).sum::<f32>() / CHUNK_SIZE as f32
+/// };
+/// assert!(power(700.0) > 0.4);
+/// assert!(power(2000.0) < 0.01);
+///
+/// // CW以外の時はそのまま通す。
+/// let mut block = peak_block(700.0, 100.0);
+/// assert_eq!(block.process(&[0.5; CHUNK_SIZE], &mut Context::default()), [0.5; CHUNK_SIZE]);
+/// ```
+pub fn peak_block(pitch: f32, width: f32) -> Box<dyn DspBlock> {
+    closure_block("PEAK", format!("{}Hz 幅{}Hz", pitch, width), 0, move || {
+        let mut peak = create_peak(pitch, width);
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            match context.if_type {
+                FilterType::CW(_) => peak( input ),
+                _ => *input,
+            }
+        }
+    })
+}
+
+/// 自動ノッチ(ビート音の除去)
+///
+/// ```
+/// use core::f32::consts::PI;
+/// use thsdr::blocks::anf_block;
+/// use thsdr::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+/// use thsdr::pipeline::Context;
+///
+/// // 続いているビート音は、適応して除く。
+/// let power = |freq: f32| {
+///     let mut block = anf_block(true);
+///     let mut context = Context::default();
+///     let mut output = [0.0; CHUNK_SIZE];
+///     for n in 0..100 {
+///         let input: [f32; CHUNK_SIZE] = std::array::from_fn(|i| (2.0 * PI * freq * (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ).sin());
+///         output = block.process(&input, &mut context);
+///     }
+///     output.iter().map(|x| x * x).sum::<f32>() / CHUNK_SIZE as f32
+/// };
+/// assert!(power(1000.0) < 0.001);
+///
+/// let mut block = anf_block(false);
+/// assert_eq!(block.process(&[0.5; CHUNK_SIZE], &mut Context::default()), [0.5; CHUNK_SIZE]);
+/// ```
+pub fn anf_block(on: bool) -> Box<dyn DspBlock> {
+    closure_block("ANF", if on { "ON" } else { "OFF" }.to_string(), 0, move || {
+        let mut anf = create_anf();
+        move |input: &[f32; CHUNK_SIZE], _: &mut Context| {
+            if on { anf( input ) } else { *input }
+        }
+    })
+}
+
+/// 手動ノッチ
+///
+/// ```
+/// use core::f32::consts::PI;
+/// use thsdr::blocks::notch_block;
+/// use thsdr::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+/// use thsdr::pipeline::Context;
+///
+/// let power = |freq: f32| {
+///     let mut block = notch_block(1000.0, 100.0);
+///     let mut context = Context::default();
+///     let mut output = [0.0; CHUNK_SIZE];
+///     for n in 0..20 {
+///         let input: [f32; CHUNK_SIZE] = std::array::from_fn(|i| (2.0 * PI * freq * (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ).sin());
+///         output = block.process(&input, &mut context);
+///     }
+///     output.iter().map(|x| x * x).sum::<f32>() / CHUNK_SIZE as f32
+/// };
+/// assert!(power(1000.0) < 0.001);
+/// assert!(power(2000.0) > 0.4);
+/// ```
+pub fn notch_block(freq: f32, width: f32) -> Box<dyn DspBlock> {
+    let description = if freq > 0.0 { format!("{}Hz 幅{}Hz", freq, width) } else { "OFF".to_string() };
+    closure_block("NOTCH", description, 0, move || {
+        let mut notch = create_notch(freq, width);
+        move |input: &[f32; CHUNK_SIZE], _: &mut Context| notch( input )
+    })
+}
+
+/// デコーダ  デコードした文字列をContextに加える。スケルチが閉じている間は無音を渡す。音声はそのまま通す。
+///
+/// ```
+/// use thsdr::blocks::decoder_block;
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::decoder::DecoderType;
+/// use thsdr::pipeline::Context;
+/// use std::sync::mpsc::channel;
+///
+/// let (frame_tx, _frame_rx) = channel();
+/// let (file_tx, _file_rx) = channel();
+/// let mut decoder = decoder_block(DecoderType::CW, 700.0, frame_tx, file_tx);
+/// let mut context = Context::default();
+/// assert_eq!(decoder.process(&[0.5; CHUNK_SIZE], &mut context), [0.5; CHUNK_SIZE]);
+/// assert!(context.text.is_empty());
+/// assert_eq!(decoder.describe(), "CW");
+/// ```
+pub fn decoder_block(dtype: DecoderType, pitch: f32, frame_tx: Sender<Vec<u8>>, file_tx: Sender<FileOutput>) -> Box<dyn DspBlock> {
+    closure_block("DEC", format!("{:?}", dtype), 0, move || {
+        let mut decoder = create_decoder(dtype, pitch, frame_tx.clone(), file_tx.clone());
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            let text = decoder( if context.squelch_open { input } else { &[0.0; CHUNK_SIZE] } );
+            context.text.push_str(&text);
+            *input
+        }
+    })
+}
+
+/// ミュート  スケルチかトーンスケルチが閉じている時は音声を出さない。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::blocks::mute_block;
+/// use thsdr::pipeline::Context;
+///
+/// let mut mute = mute_block();
+/// let mut context = Context::default();
+/// assert_eq!(mute.process(&[0.5; CHUNK_SIZE], &mut context)[0], 0.5);
+/// context.squelch_open = false;
+/// assert_eq!(mute.process(&[0.5; CHUNK_SIZE], &mut context)[0], 0.0);
+/// ```
+pub fn mute_block() -> Box<dyn DspBlock> {
+    closure_block("MUTE", "スケルチによる消音".to_string(), 0, || {
+        move |input: &[f32; CHUNK_SIZE], context: &mut Context| {
+            if context.squelch_open && context.tone_open { *input } else { [0.0; CHUNK_SIZE] }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testsignal::keyed_signal;
+    use std::sync::mpsc::channel;
+
+    // ANFをONにしても、デコーダにはANFを通す前の音が渡ってCWをデコードできる。
+    #[test]
+    fn decodes_cw_with_anf_on() {
+        let (frame_tx, _frame_rx) = channel();
+        let (file_tx, _file_rx) = channel();
+        let names: Vec<&str> = DEFAULT_CHAIN.iter().skip_while(|name| **name != "PEAK").cloned().collect();
+        let mut pipeline = build_pipeline(&names, frame_tx.clone(), file_tx.clone()).unwrap();
+        pipeline.replace(anf_block(true));
+        pipeline.replace(decoder_block(DecoderType::CW, 700.0, frame_tx, file_tx));
+
+        let text = "CQ CQ DE JA1XYZ K";
+        let mut context = Context::default();
+        for chunk in keyed_signal(text, 700.0, 20.0, 0.0).chunks_exact(CHUNK_SIZE) {
+            pipeline.process(chunk.try_into().unwrap(), &mut context);
+        }
+        assert_eq!(context.text.trim(), text);
+    }
+}
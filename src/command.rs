@@ -0,0 +1,473 @@
+// キーボードから入力するコマンドと、処理スレッドに送るコマンドの定義
+use crate::firfilter::FilterType;
+use crate::agc::AGCType;
+use crate::tonedetect::{ToneSquelch, CTCSS_TONES, DCS_CODES};
+use crate::squelch::{SquelchMode, SquelchParams};
+use crate::decoder::DecoderType;
+use crate::receiver::{AudioRoute, MAX_RECEIVERS};
+use crate::iq::InputMode;
+use std::str::FromStr;
+
+/// キーボードからの入力コマンドを表すEnum
+pub enum UiCommand {
+    AM(i32),
+    USB(i32),
+    LSB(i32),
+    AMUSB(i32),
+    AMLSB(i32),
+    AGC(i32),
+    AF(i32),
+    RSSI(String),
+    IFOUT(String),
+    BFO(f32),
+    ANF(bool),
+    NOTCH(f32, f32),
+    SHIFT(f32),
+    WIDTH(f32),
+    CW(i32),
+    PITCH(f32),
+    DECODE(DecoderType),
+    TEXT(String),
+    FM,
+    KISS(u16),
+    TONE(bool),
+    TSQL(ToneSquelch),
+    SQL(SquelchParams),
+    AFC(bool),
+    CAL,
+    CHAIN(Vec<String>),
+    RESET,
+    RX(Option<usize>),
+    RXOFF(usize),
+    ROUTE(AudioRoute),
+    OFFSET(f32),
+    INPUT(InputMode),
+    STATS,
+    SAVE(Option<String>),
+    STORE(String),
+    RECALL(String),
+    LIST,
+    IF(Condition, Box<UiCommand>),
+    EXIT,
+}
+
+/// IFコマンドの条件
+/// RSSIは、RXコマンドで選んだ受信機のRSSI\[dB\](設定ファイルのsmeter_offsetで補正した値)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Condition {
+    RssiAbove(f32),
+    RssiBelow(f32),
+}
+
+impl Condition {
+    /// RSSI\[dB\]が条件を満たすかどうか
+    ///
+    /// ```
+    /// use thsdr::command::{Condition, UiCommand};
+    ///
+    /// let Ok(UiCommand::IF(condition, command)) = "IF RSSI > -40 AF 3".parse::<UiCommand>() else { panic!() };
+    /// assert_eq!(condition, Condition::RssiAbove(-40.0));
+    /// assert!(matches!(*command, UiCommand::AF(3)));
+    /// assert!(condition.holds(-30.0));
+    /// assert!(!condition.holds(-50.0));
+    /// ```
+    pub fn holds( &self, rssi_db: f32 ) -> bool {
+        match *self {
+            Condition::RssiAbove(level) => rssi_db > level,
+            Condition::RssiBelow(level) => rssi_db < level,
+        }
+    }
+}
+
+pub enum InternalCommand {
+    AM(FilterType),
+    USB(FilterType),
+    LSB(FilterType),
+    AMUSB(FilterType),
+    AMLSB(FilterType),
+    AGC(AGCType),
+    None,
+    AF(FilterType),
+    RSSI(String),
+    IFOUT(String),
+    BFO(f32),
+    ANF(bool),
+    NOTCH(f32, f32),
+    SHIFT(f32),
+    WIDTH(f32),
+    CW(FilterType),
+    PITCH(f32),
+    DECODE(DecoderType),
+    TEXT(String),
+    FM,
+    KISS(u16),
+    TONE(bool),
+    TSQL(ToneSquelch),
+    SQL(SquelchParams),
+    AFC(bool),
+    CAL,
+    CHAIN(Vec<String>),
+    RESET,
+    RX(Option<usize>),
+    RXOFF(usize),
+    ROUTE(AudioRoute),
+    OFFSET(f32),
+    INPUT(InputMode),
+    STATS,
+    SAVE(Option<String>),
+    STORE(String),
+    RECALL(String),
+    LIST,
+    IF(Condition, Box<InternalCommand>),
+    EXIT,
+}
+
+/// キーボードから入力した1行をコマンドにする。
+///
+/// ```
+/// use thsdr::command::UiCommand;
+///
+/// assert!(matches!("USB 3".parse::<UiCommand>(), Ok(UiCommand::USB(3))));
+/// assert!("FOO".parse::<UiCommand>().is_err());
+/// ```
+impl FromStr for UiCommand {
+    type Err = anyhow::Error;
+
+    // 文字列からCommandを生成する関数
+    fn from_str(input: &str) -> Result<UiCommand, Self::Err> {
+        let parts: Vec<&str> = input.split_whitespace().collect();
+        let command = match parts.as_slice() {
+            ["AM", param] => param.parse().ok().map(UiCommand::AM),
+            ["USB", param] => param.parse().ok().map(UiCommand::USB),
+            ["LSB", param] => param.parse().ok().map(UiCommand::LSB),
+            ["AMUSB", param] => param.parse().ok().map(UiCommand::AMUSB),
+            ["AMLSB", param] => param.parse().ok().map(UiCommand::AMLSB),
+            ["AGC", param] => param.parse().ok().map(UiCommand::AGC),
+            ["AF", param] => param.parse().ok().map(UiCommand::AF),
+            ["RSSI", param] => param.parse().ok().map(UiCommand::RSSI),
+            ["IFOUT", param] => param.parse().ok().map(UiCommand::IFOUT),
+            ["BFO",param] => param.parse().ok().map(UiCommand::BFO),
+            ["ANF", "ON"] => Some(UiCommand::ANF(true)),
+            ["ANF", "OFF"] => Some(UiCommand::ANF(false)),
+            ["NOTCH", "OFF"] => Some(UiCommand::NOTCH(0.0, 0.0)),
+            ["NOTCH", freq, width] => match (freq.parse(), width.parse()) {
+                (Ok(freq), Ok(width)) => Some(UiCommand::NOTCH(freq, width)),
+                _ => None,
+            },
+            ["SHIFT", param] => param.parse().ok().map(UiCommand::SHIFT),
+            ["WIDTH", param] => param.parse().ok().map(UiCommand::WIDTH),
+            ["CW", param] => param.parse().ok().map(UiCommand::CW),
+            ["PITCH", param] => param.parse().ok().map(UiCommand::PITCH),
+            ["DECODE", "CW"] => Some(UiCommand::DECODE(DecoderType::CW)),
+            ["DECODE", "APRS"] => Some(UiCommand::DECODE(DecoderType::APRS)),
+            ["DECODE", "RTTY", params @ ..] => rtty_params(params).map(UiCommand::DECODE),
+            ["DECODE", "PSK31"] => Some(UiCommand::DECODE(DecoderType::PSK31)),
+            ["DECODE", "PSK63"] => Some(UiCommand::DECODE(DecoderType::PSK63)),
+            ["DECODE", "WEFAX", params @ ..] => wefax_params(params).map(UiCommand::DECODE),
+            ["DECODE", "NAVTEX"] => Some(UiCommand::DECODE(DecoderType::NAVTEX { center: 1000.0 })),
+            ["DECODE", "NAVTEX", param] => param.parse().ok().filter(|f: &f32| *f > 100.0)
+                .map(|center| UiCommand::DECODE(DecoderType::NAVTEX { center })),
+            ["DECODE", "SSTV"] => Some(UiCommand::DECODE(DecoderType::SSTV)),
+            ["DECODE", "FT8"] => Some(UiCommand::DECODE(DecoderType::FT8)),
+            ["DECODE", "FT4"] => Some(UiCommand::DECODE(DecoderType::FT4)),
+            ["DECODE", "POCSAG"] => Some(UiCommand::DECODE(DecoderType::POCSAG)),
+            ["DECODE", "OFF"] => Some(UiCommand::DECODE(DecoderType::None)),
+            ["FM"] => Some(UiCommand::FM),
+            ["KISS", param] => param.parse().ok().map(UiCommand::KISS),
+            ["TONE", "ON"] => Some(UiCommand::TONE(true)),
+            ["TONE", "OFF"] => Some(UiCommand::TONE(false)),
+            ["TSQL", "OFF"] => Some(UiCommand::TSQL(ToneSquelch::None)),
+            ["TSQL", param] => tsql_param(param).map(UiCommand::TSQL),
+            ["AFC", "ON"] => Some(UiCommand::AFC(true)),
+            ["AFC", "OFF"] => Some(UiCommand::AFC(false)),
+            ["CAL"] => Some(UiCommand::CAL),
+            ["CHAIN", "RESET"] => Some(UiCommand::RESET),
+            ["CHAIN", names @ ..] => Some(UiCommand::CHAIN(names.iter().map(|name| name.to_string()).collect())),
+            ["SQL", "OFF"] => Some(UiCommand::SQL(SquelchParams::OFF)),
+            ["SQL", mode, params @ ..] => sql_params(mode, params).map(UiCommand::SQL),
+            ["TEXT", param] => param.parse().ok().map(UiCommand::TEXT),
+            ["RX"] => Some(UiCommand::RX(None)),
+            ["RX", param] => rx_param(param).map(|n| UiCommand::RX(Some(n))),
+            ["RX", param, "OFF"] => rx_param(param).map(UiCommand::RXOFF),
+            ["ROUTE", "L"] => Some(UiCommand::ROUTE(AudioRoute::Left)),
+            ["ROUTE", "R"] => Some(UiCommand::ROUTE(AudioRoute::Right)),
+            ["ROUTE", "LR"] => Some(UiCommand::ROUTE(AudioRoute::Both)),
+            ["ROUTE", "OFF"] => Some(UiCommand::ROUTE(AudioRoute::Off)),
+            ["ROUTE", "PIPE", name] => Some(UiCommand::ROUTE(AudioRoute::Pipe(name.to_string()))),
+            ["OFFSET", param] => param.parse().ok().map(UiCommand::OFFSET),
+            ["INPUT", "L"] => Some(UiCommand::INPUT(InputMode::Left)),
+            ["INPUT", "R"] => Some(UiCommand::INPUT(InputMode::Right)),
+            ["INPUT", "IQ"] => Some(UiCommand::INPUT(InputMode::IQ)),
+            ["INPUT", "QI"] => Some(UiCommand::INPUT(InputMode::QI)),
+            ["STATS"] => Some(UiCommand::STATS),
+            ["SAVE"] => Some(UiCommand::SAVE(None)),
+            ["SAVE", path] => Some(UiCommand::SAVE(Some(path.to_string()))),
+            ["STORE", name] => Some(UiCommand::STORE(name.to_string())),
+            ["RECALL", name] => Some(UiCommand::RECALL(name.to_string())),
+            ["LIST"] => Some(UiCommand::LIST),
+            ["IF", "RSSI", op, level, command @ ..] => if_params(op, level, command),
+            ["EXIT"] => Some(UiCommand::EXIT),
+            _ => None,
+        };
+        command.ok_or_else(|| anyhow::anyhow!("Invalid command: {}", input))
+    }
+}
+
+// RTTYのパラメータ(シフト、ボーレート、NORM/REV)。省略した時は170Hz、45.45Bd、NORMになる。
+fn rtty_params(params: &[&str]) -> Option<DecoderType> {
+    let (shift, baud, reverse) = match params {
+        [] => ("170", "45.45", "NORM"),
+        [shift] => (*shift, "45.45", "NORM"),
+        [shift, baud] => (*shift, *baud, "NORM"),
+        [shift, baud, polarity] => (*shift, *baud, *polarity),
+        _ => return None,
+    };
+    let reverse = match reverse {
+        "NORM" => false,
+        "REV" => true,
+        _ => return None,
+    };
+    match (shift.parse::<f32>(), baud.parse::<f32>()) {
+        (Ok(shift), Ok(baud)) if shift > 0.0 && baud > 0.0 => Some(DecoderType::RTTY { shift, baud, reverse }),
+        _ => None,
+    }
+}
+
+// WEFAXのパラメータ(LPM、傾きの補正[ppm])。省略した時は120LPM、補正なしになる。
+fn wefax_params(params: &[&str]) -> Option<DecoderType> {
+    let (lpm, slant) = match params {
+        [] => ("120", "0"),
+        [lpm] => (*lpm, "0"),
+        [lpm, slant] => (*lpm, *slant),
+        _ => return None,
+    };
+    match (lpm.parse::<f32>(), slant.parse::<f32>()) {
+        (Ok(lpm), Ok(slant)) if lpm > 0.0 => Some(DecoderType::WEFAX { lpm, slant }),
+        _ => None,
+    }
+}
+
+// IFコマンドの条件(RSSI > レベル、RSSI < レベル)と、条件を満たした時に実行するコマンド
+fn if_params(op: &str, level: &str, command: &[&str]) -> Option<UiCommand> {
+    let level: f32 = level.parse().ok()?;
+    let condition = match op {
+        ">" => Condition::RssiAbove(level),
+        "<" => Condition::RssiBelow(level),
+        _ => return None,
+    };
+    let command: UiCommand = command.join(" ").parse().ok()?;
+    Some(UiCommand::IF(condition, Box::new(command)))
+}
+
+// 受信機の番号(1～MAX_RECEIVERS)
+fn rx_param(param: &str) -> Option<usize> {
+    param.parse().ok().filter(|n| (1..=MAX_RECEIVERS).contains(n))
+}
+
+// トーンスケルチのパラメータ。CTCSSは周波数(88.5等)、DCSはD023、D023Iのようにコードと反転の有無を指定する。
+fn tsql_param(param: &str) -> Option<ToneSquelch> {
+    if let Some(code) = param.strip_prefix('D') {
+        let (digits, inverted) = match code.strip_suffix('I') {
+            Some(digits) => (digits, true),
+            None => (code.strip_suffix('N').unwrap_or(code), false),
+        };
+        let code = u16::from_str_radix(digits, 8).ok()?;
+        DCS_CODES.contains(&code).then_some(ToneSquelch::DCS { code, inverted })
+    } else {
+        let freq: f32 = param.parse().ok()?;
+        CTCSS_TONES.iter().find(|&&tone| (tone - freq).abs() < 0.05).map(|&tone| ToneSquelch::CTCSS(tone))
+    }
+}
+
+// スケルチのパラメータ(判定方法、レベル[dB]、ヒステリシス[dB]、テール[秒])。省略した時は2dB、0.5秒になる。
+fn sql_params(mode: &str, params: &[&str]) -> Option<SquelchParams> {
+    let mode = match mode {
+        "LEVEL" => SquelchMode::Level,
+        "NOISE" => SquelchMode::Noise,
+        "SNR" => SquelchMode::SNR,
+        _ => return None,
+    };
+    let (level, hysteresis, tail) = match params {
+        [level] => (*level, "2", "0.5"),
+        [level, hysteresis] => (*level, *hysteresis, "0.5"),
+        [level, hysteresis, tail] => (*level, *hysteresis, *tail),
+        _ => return None,
+    };
+    match (level.parse::<f32>(), hysteresis.parse::<f32>(), tail.parse::<f32>()) {
+        (Ok(level), Ok(hysteresis), Ok(tail)) if hysteresis >= 0.0 && tail >= 0.0 =>
+            Some(SquelchParams { mode, level, hysteresis, tail }),
+        _ => None,
+    }
+}
+
+/// 入力されたコマンドを、処理スレッドに送るコマンドに変換する。
+///
+/// ```
+/// use thsdr::command::{command_decode, InternalCommand, UiCommand};
+/// use thsdr::firfilter::FilterType;
+///
+/// assert!(matches!(command_decode(UiCommand::USB(3)), InternalCommand::USB(FilterType::USB3K)));
+/// ```
+pub fn command_decode( comm: UiCommand ) -> InternalCommand {
+
+    match comm {
+        UiCommand::AM(param) => {
+            InternalCommand::AM(
+                match param {
+                    i32::MIN..=4 => FilterType::AM3K,
+                    5..=8 => FilterType::AM6K,
+                    9..=13 => FilterType::AM11K,
+                    14..=i32::MAX => FilterType::None,
+                })
+        },
+        UiCommand::USB(param) => {
+            InternalCommand::USB(
+                match param {
+                    i32::MIN..=2 => FilterType::USB2K,
+                    3..=4 => FilterType::USB3K,
+                    5..=i32::MAX => FilterType::None,
+                })
+        },
+        UiCommand::LSB(param) => {
+            InternalCommand::LSB(
+                match param {
+                    i32::MIN..=2 => FilterType::LSB2K,
+                    3..=4 => FilterType::LSB3K,
+                    5..=i32::MAX => FilterType::None,
+                })
+        },
+        UiCommand::AMUSB(param) => {
+            InternalCommand::AMUSB(
+                match param {
+                    i32::MIN..=4 => FilterType::AMUSB3K,
+                    5..=6 => FilterType::AMUSB6K,
+                    7..=8 => FilterType::AMUSB7K,
+                    9..=i32::MAX => FilterType::None,
+                })
+        },
+        UiCommand::AMLSB(param) => {
+            InternalCommand::AMLSB(
+                match param {
+                    i32::MIN..=4 => FilterType::AMLSB3K,
+                    5..=6 => FilterType::AMLSB6K,
+                    7..=8 => FilterType::AMLSB7K,
+                    9..=i32::MAX => FilterType::None,
+                })
+        },
+        UiCommand::AGC(param) => {
+            InternalCommand::AGC(
+                match param {
+                    i32::MIN..=-1 => AGCType::None,
+                    0 => AGCType::AGC05,
+                    1 => AGCType::AGC15,
+                    2 => AGCType::AGC20,
+                    3 => AGCType::AGC30,
+                    4..=i32::MAX => AGCType::AGC50,
+                })
+        },
+        UiCommand::AF(param) => {
+            InternalCommand::AF(
+                match param {
+                    i32::MIN..=4 => FilterType::AF3K,
+                    5..=8 => FilterType::AF6K,
+                    9..=i32::MAX => FilterType::AF11K,
+                })
+        },
+        UiCommand::RSSI(param) => {
+            InternalCommand::RSSI(param)
+        },
+        UiCommand::IFOUT(param) => {
+            InternalCommand::IFOUT(param)
+        },
+        UiCommand::BFO(param) => {
+            InternalCommand::BFO(param)
+        },
+        UiCommand::ANF(param) => {
+            InternalCommand::ANF(param)
+        },
+        UiCommand::NOTCH(freq, width) => {
+            InternalCommand::NOTCH(freq, width)
+        },
+        UiCommand::SHIFT(param) => {
+            InternalCommand::SHIFT(param)
+        },
+        UiCommand::WIDTH(param) => {
+            InternalCommand::WIDTH(param)
+        },
+        UiCommand::CW(param) => {
+            InternalCommand::CW( FilterType::CW( param.clamp(50, 800) as f32 ) )
+        },
+        UiCommand::PITCH(param) => {
+            InternalCommand::PITCH( param.clamp(200.0, 1500.0) )
+        },
+        UiCommand::DECODE(param) => {
+            InternalCommand::DECODE(param)
+        },
+        UiCommand::TEXT(param) => {
+            InternalCommand::TEXT(param)
+        },
+        UiCommand::FM => {
+            InternalCommand::FM
+        },
+        UiCommand::KISS(param) => {
+            InternalCommand::KISS(param)
+        },
+        UiCommand::TONE(param) => {
+            InternalCommand::TONE(param)
+        },
+        UiCommand::TSQL(param) => {
+            InternalCommand::TSQL(param)
+        },
+        UiCommand::SQL(param) => {
+            InternalCommand::SQL(param)
+        },
+        UiCommand::AFC(param) => {
+            InternalCommand::AFC(param)
+        },
+        UiCommand::CAL => {
+            InternalCommand::CAL
+        },
+        UiCommand::CHAIN(param) => {
+            InternalCommand::CHAIN(param)
+        },
+        UiCommand::RESET => {
+            InternalCommand::RESET
+        },
+        UiCommand::RX(param) => {
+            InternalCommand::RX( param.map(|n| n - 1) )     // 受信機の番号を0からの添字にする。
+        },
+        UiCommand::RXOFF(param) => {
+            InternalCommand::RXOFF( param - 1 )
+        },
+        UiCommand::ROUTE(param) => {
+            InternalCommand::ROUTE(param)
+        },
+        UiCommand::OFFSET(param) => {
+            InternalCommand::OFFSET(param)
+        },
+        UiCommand::INPUT(param) => {
+            InternalCommand::INPUT(param)
+        },
+        UiCommand::STATS => {
+            InternalCommand::STATS
+        },
+        UiCommand::SAVE(param) => {
+            InternalCommand::SAVE(param)
+        },
+        UiCommand::STORE(param) => {
+            InternalCommand::STORE(param)
+        },
+        UiCommand::RECALL(param) => {
+            InternalCommand::RECALL(param)
+        },
+        UiCommand::LIST => {
+            InternalCommand::LIST
+        },
+        UiCommand::IF(condition, command) => {
+            InternalCommand::IF( condition, Box::new( command_decode(*command) ) )
+        },
+        UiCommand::EXIT => {
+            InternalCommand::EXIT
+        },
+    }
+}
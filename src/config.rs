@@ -0,0 +1,402 @@
+// 設定ファイル(TOML)
+// 起動時に読み込み、コマンドラインの指定(--名前 値)で上書きする。SAVEコマンドで現在の状態を書き戻す。
+// STOREコマンドで名前を付けて覚えたモードの設定(プリセット)も、設定ファイルに保存する。
+// 受信機の設定はキーボードから入力するのと同じコマンドにして適用するので、値の範囲等はコマンドと同じになる。
+use crate::constants::SAMPLING_FREQ;
+use crate::command::{command_decode, InternalCommand, UiCommand};
+use crate::iq::InputMode;
+use crate::receiver::MAX_RECEIVERS;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 設定ファイルの標準の場所(カレントディレクトリ)
+pub const DEFAULT_CONFIG_PATH: &str = "thsdr.toml";
+
+/// 設定全体
+/// 受信機の設定は`[[receiver]]`の表として、RX1から順に並べる。プリセットは`[preset.名前]`の表にする。
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub input_device: String,       // 入力デバイスの名前(空の時は標準のデバイス)
+    pub output_device: String,      // 出力デバイスの名前(空の時は標準のデバイス)
+    pub sample_rate: u32,           // サンプリング周波数[Hz](SAMPLING_FREQのみ)
+    pub input: String,              // INPUTコマンドの値(L、R、IQ、QI)
+    pub if_offset: f32,             // CALコマンドで測定したIFのキャリアの位置のずれ[Hz]
+    pub smeter_offset: f32,         // RSSIの出力の補正[dB](Sメータの校正)
+    pub ifout: String,              // IFOUTコマンドの名前付きパイプ(空の時は出力しない)
+    pub kiss: u16,                  // KISSコマンドのポート(0の時は開かない)
+    pub receiver: Vec<ReceiverConfig>,
+    pub preset: BTreeMap<String, Preset>,
+}
+
+/// プリセット(モード、フィルタ、AGC、BFO、ANF、ノッチ、スケルチ、デコーダの設定)
+/// 値はキーボードから入力するコマンドの値と同じ。
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub mode: String,               // AM、USB、LSB、AMUSB、AMLSB、CW、FM
+    pub filter: i32,                // モードのコマンドの値(CWは帯域幅[Hz]、FMでは使わない。)
+    pub shift: f32,
+    pub width: f32,
+    pub pitch: f32,
+    pub agc: i32,
+    pub af: i32,
+    pub bfo: f32,
+    pub anf: bool,
+    pub notch: String,              // NOTCHコマンドの値(OFF、周波数 帯域幅)
+    pub squelch: String,            // SQLコマンドの値(OFF、LEVEL -20等)
+    pub decode: String,             // DECODEコマンドの値(OFF、APRS、RTTY 170 45.45等)
+}
+
+/// 受信機ごとの設定(プリセットの設定と、受信機の出力先等)
+/// 文字列が空の時は出力しない。
+/// プリセットの設定を同じ表に書けるように平らにするので、serdeでは知らない名前を見つけられない。
+/// 知らない名前はConfig::loadでエラーにする。
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReceiverConfig {
+    #[serde(flatten)]
+    pub preset: Preset,
+    pub offset: f32,
+    pub afc: bool,
+    pub route: String,              // ROUTEコマンドの値(LR、L、R、OFF、PIPE 名前)
+    pub rssi: String,
+    pub text: String,
+    pub chain: Vec<String>,         // 処理ブロックの構成(空の時は標準の構成)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            input_device: String::new(),
+            output_device: String::new(),
+            sample_rate: SAMPLING_FREQ as u32,
+            input: "L".to_string(),
+            if_offset: 0.0,
+            smeter_offset: 0.0,
+            ifout: String::new(),
+            kiss: 0,
+            receiver: vec![ReceiverConfig::default()],
+            preset: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset {
+            mode: "AM".to_string(),
+            filter: 11,
+            shift: 0.0,
+            width: 0.0,
+            pitch: 700.0,
+            agc: 0,
+            af: 11,
+            bfo: 0.0,
+            anf: false,
+            notch: "OFF".to_string(),
+            squelch: "OFF".to_string(),
+            decode: "OFF".to_string(),
+        }
+    }
+}
+
+impl Default for ReceiverConfig {
+    fn default() -> Self {
+        ReceiverConfig {
+            preset: Preset::default(),
+            offset: 0.0,
+            afc: false,
+            route: "LR".to_string(),
+            rssi: String::new(),
+            text: String::new(),
+            chain: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// 設定ファイルを読み込み、コマンドラインの指定(名前と値)で上書きする。ファイルが無い時は標準の設定を使う。
+    /// 受信機の設定の名前はRX1の設定を上書きする。値はTOMLとして読めなければ文字列として扱う。
+    ///
+    /// ```
+    /// use thsdr::config::Config;
+    ///
+    /// let overrides = [("mode".to_string(), "USB".to_string()), ("filter".to_string(), "3".to_string()),
+    ///                  ("smeter_offset".to_string(), "-6.5".to_string())];
+    /// let config = Config::load("/nonexistent/thsdr.toml".as_ref(), &overrides).unwrap();
+    /// assert_eq!(config.receiver[0].preset.mode, "USB");
+    /// assert_eq!(config.receiver[0].preset.filter, 3);
+    /// assert_eq!(config.smeter_offset, -6.5);
+    ///
+    /// // 知らない名前や使えない値はエラーにする。
+    /// assert!(Config::load("/nonexistent/thsdr.toml".as_ref(), &[("foo".to_string(), "1".to_string())]).is_err());
+    /// assert!(Config::load("/nonexistent/thsdr.toml".as_ref(), &[("mode".to_string(), "XYZ".to_string())]).is_err());
+    ///
+    /// // 受信機やプリセットの表の書き間違いもエラーにする。
+    /// let path = std::env::temp_dir().join("thsdr_config_keys_doctest.toml");
+    /// std::fs::write(&path, "[[receiver]]\nmode = \"USB\"\nfliter = 3\n").unwrap();
+    /// assert!(Config::load(&path, &[]).unwrap_err().to_string().contains("fliter"));
+    /// std::fs::write(&path, "[preset.usb]\nmode = \"USB\"\nroute = \"L\"\n").unwrap();
+    /// assert!(Config::load(&path, &[]).unwrap_err().to_string().contains("route"));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn load( path: &Path, overrides: &[(String, String)] ) -> Result<Config, anyhow::Error> {
+        let mut table: toml::Table = if path.exists() {
+            fs::read_to_string(path)?.parse()
+                .map_err(|e| anyhow::anyhow!("{}を読み込めません: {}", path.display(), e))?
+        } else {
+            toml::Table::new()
+        };
+
+        let receiver_keys = toml::Table::try_from( ReceiverConfig::default() )?;
+        for (key, value) in overrides {
+            let key = key.replace('-', "_");
+            let value = override_value( value );
+            if receiver_keys.contains_key( &key ) {
+                let receivers = table.entry("receiver").or_insert( toml::Value::Array(Vec::new()) );
+                let toml::Value::Array(receivers) = receivers else { anyhow::bail!("receiverは表の配列にしてください。") };
+                if receivers.is_empty() {
+                    receivers.push( toml::Value::Table(toml::Table::new()) );
+                }
+                let toml::Value::Table(rx1) = &mut receivers[0] else { anyhow::bail!("receiverは表の配列にしてください。") };
+                rx1.insert( key, value );
+            } else {
+                table.insert( key, value );
+            }
+        }
+
+        check_keys( &table, &receiver_keys )?;
+        let config: Config = table.try_into()?;
+        config.check()?;
+        Ok(config)
+    }
+
+    /// 設定ファイルに書き出す。
+    ///
+    /// ```
+    /// use thsdr::config::Config;
+    ///
+    /// let path = std::env::temp_dir().join("thsdr_config_doctest.toml");
+    /// let mut config = Config::default();
+    /// config.if_offset = -12.5;
+    /// config.save(&path).unwrap();
+    /// assert_eq!(Config::load(&path, &[]).unwrap(), config);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn save( &self, path: &Path ) -> Result<(), anyhow::Error> {
+        fs::write( path, toml::to_string(self)? )?;
+        Ok(())
+    }
+
+    /// INPUTの設定
+    ///
+    /// ```
+    /// use thsdr::config::Config;
+    /// use thsdr::iq::InputMode;
+    ///
+    /// let mut config = Config::default();
+    /// assert_eq!(config.input_mode().unwrap(), InputMode::Left);
+    /// config.input = "QI".to_string();
+    /// assert_eq!(config.input_mode().unwrap(), InputMode::QI);
+    /// config.input = "STEREO".to_string();
+    /// assert!(config.input_mode().is_err());
+    /// ```
+    pub fn input_mode( &self ) -> Result<InputMode, anyhow::Error> {
+        match command_decode( parse_command( &format!("INPUT {}", self.input) )? ) {
+            InternalCommand::INPUT(mode) => Ok(mode),
+            _ => anyhow::bail!("inputの値が正しくありません: {}", self.input),
+        }
+    }
+
+    // 値が使えるかどうかを確かめる。
+    fn check( &self ) -> Result<(), anyhow::Error> {
+        if self.sample_rate != SAMPLING_FREQ as u32 {
+            anyhow::bail!("sample_rateは{}のみ使えます: {}", SAMPLING_FREQ as u32, self.sample_rate);
+        }
+        if self.receiver.is_empty() || self.receiver.len() > MAX_RECEIVERS {
+            anyhow::bail!("receiverは1～{}個にしてください。", MAX_RECEIVERS);
+        }
+        self.input_mode()?;
+        for receiver in &self.receiver {
+            receiver.commands()?;
+        }
+        for (name, preset) in &self.preset {
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                anyhow::bail!("プリセットの名前に空白は使えません: {}", name);
+            }
+            preset.commands().map_err(|e| anyhow::anyhow!("プリセット{}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+// 受信機とプリセットの表に、知らない名前が無いかを確かめる。
+// 使える名前は、標準の設定をTOMLの表にした時の名前にする。
+fn check_keys( table: &toml::Table, receiver_keys: &toml::Table ) -> Result<(), anyhow::Error> {
+    let preset_keys = toml::Table::try_from( Preset::default() )?;
+    let unknown = |entry: &toml::Value, keys: &toml::Table| -> Option<String> {
+        entry.as_table()?.keys().find(|key| !keys.contains_key(*key)).cloned()
+    };
+    if let Some(receivers) = table.get("receiver").and_then(|r| r.as_array()) {
+        for (n, receiver) in receivers.iter().enumerate() {
+            if let Some(key) = unknown( receiver, receiver_keys ) {
+                anyhow::bail!("receiverの{}番目の{}は使えない名前です。", n + 1, key);
+            }
+        }
+    }
+    if let Some(presets) = table.get("preset").and_then(|p| p.as_table()) {
+        for (name, preset) in presets {
+            if let Some(key) = unknown( preset, &preset_keys ) {
+                anyhow::bail!("プリセット{}の{}は使えない名前です。", name, key);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Preset {
+    /// プリセットを設定するコマンド(キーボードから入力するのと同じ形)
+    /// モードを変えるとシフトと帯域幅が初期値に戻るので、その後にSHIFTとWIDTHを置く。
+    ///
+    /// ```
+    /// use thsdr::config::Preset;
+    ///
+    /// let preset = Preset { mode: "FM".to_string(), decode: "APRS".to_string(), ..Default::default() };
+    /// let lines = preset.command_lines();
+    /// assert_eq!(lines[1], "FM");
+    /// assert!(lines.contains(&"DECODE APRS".to_string()));
+    /// ```
+    pub fn command_lines( &self ) -> Vec<String> {
+        vec![
+            format!("PITCH {}", self.pitch),
+            if self.mode == "FM" { "FM".to_string() } else { format!("{} {}", self.mode, self.filter) },
+            format!("SHIFT {}", self.shift),
+            format!("WIDTH {}", self.width),
+            format!("AGC {}", self.agc),
+            format!("AF {}", self.af),
+            format!("BFO {}", self.bfo),
+            format!("ANF {}", if self.anf { "ON" } else { "OFF" }),
+            format!("NOTCH {}", self.notch),
+            format!("SQL {}", self.squelch),
+            format!("DECODE {}", self.decode),
+        ]
+    }
+
+    /// プリセットを設定するコマンドを、処理スレッドに送るコマンドにする。読めないコマンドがあればエラーにする。
+    ///
+    /// ```
+    /// use thsdr::config::Preset;
+    /// use thsdr::command::InternalCommand;
+    ///
+    /// let preset = Preset { mode: "CW".to_string(), filter: 500, ..Default::default() };
+    /// assert!(preset.commands().unwrap().iter().any(|c| matches!(c, InternalCommand::CW(_))));
+    /// assert!(Preset { squelch: "LOUD".to_string(), ..Default::default() }.commands().is_err());
+    /// ```
+    pub fn commands( &self ) -> Result<Vec<InternalCommand>, anyhow::Error> {
+        let commands = decode_lines( &self.command_lines() )?;
+        if !commands.iter().any(is_mode_command) {
+            anyhow::bail!("modeの値が正しくありません: {}", self.mode);
+        }
+        Ok(commands)
+    }
+}
+
+impl ReceiverConfig {
+    /// 受信機を設定するコマンド(キーボードから入力するのと同じ形)
+    /// 構成を変えると処理ブロックが作り直されるので、CHAINを最初にして、その後にプリセットの設定を置く。
+    ///
+    /// ```
+    /// use thsdr::config::ReceiverConfig;
+    ///
+    /// let lines = ReceiverConfig::default().command_lines();
+    /// assert_eq!(lines[1], "AM 11");
+    /// ```
+    pub fn command_lines( &self ) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.chain.is_empty() {
+            lines.push( format!("CHAIN {}", self.chain.join(" ")) );
+        }
+        lines.extend( self.preset.command_lines() );
+        lines.push( format!("OFFSET {}", self.offset) );
+        lines.push( format!("AFC {}", if self.afc { "ON" } else { "OFF" }) );
+        lines.push( format!("ROUTE {}", self.route) );
+        lines.push( format!("RSSI {}", if self.rssi.is_empty() { "None" } else { &self.rssi }) );
+        lines.push( format!("TEXT {}", if self.text.is_empty() { "None" } else { &self.text }) );
+        lines
+    }
+
+    /// 受信機を設定するコマンドを、処理スレッドに送るコマンドにする。読めないコマンドがあればエラーにする。
+    ///
+    /// ```
+    /// use thsdr::config::ReceiverConfig;
+    /// use thsdr::command::InternalCommand;
+    ///
+    /// let receiver = ReceiverConfig { offset: 1.5, ..Default::default() };
+    /// let commands = receiver.commands().unwrap();
+    /// assert!(commands.iter().any(|c| matches!(c, InternalCommand::OFFSET(x) if *x == 1.5)));
+    /// let receiver = ReceiverConfig { route: "BOTH".to_string(), ..Default::default() };
+    /// assert!(receiver.commands().is_err());
+    /// ```
+    pub fn commands( &self ) -> Result<Vec<InternalCommand>, anyhow::Error> {
+        self.preset.commands()?;
+        decode_lines( &self.command_lines() )
+    }
+}
+
+/// コマンドラインの指定
+pub struct Args {
+    pub config_path: PathBuf,                   // 設定ファイル
+    pub script: Option<PathBuf>,                // 起動時に実行するスクリプト
+    pub overrides: Vec<(String, String)>,       // 上書きする設定(名前と値)
+}
+
+/// コマンドライン引数を読む。
+/// 「--config ファイル」で設定ファイル、「--script ファイル」で起動時に実行するスクリプトを指定し、それ以外は「--名前 値」で設定を上書きする。
+///
+/// ```
+/// use thsdr::config::parse_args;
+///
+/// let args = ["--config", "my.toml", "--agc", "2", "--script", "startup.txt"].map(String::from);
+/// let args = parse_args(args).unwrap();
+/// assert_eq!(args.config_path, std::path::PathBuf::from("my.toml"));
+/// assert_eq!(args.script, Some("startup.txt".into()));
+/// assert_eq!(args.overrides, vec![("agc".to_string(), "2".to_string())]);
+/// assert!(parse_args(["agc".to_string()]).is_err());
+/// ```
+pub fn parse_args( args: impl IntoIterator<Item = String> ) -> Result<Args, anyhow::Error> {
+    let mut parsed = Args { config_path: PathBuf::from( DEFAULT_CONFIG_PATH ), script: None, overrides: Vec::new() };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(key) = arg.strip_prefix("--") else { anyhow::bail!("「--名前 値」で指定してください: {}", arg) };
+        let Some(value) = args.next() else { anyhow::bail!("{}の値がありません。", arg) };
+        match key {
+            "config" => parsed.config_path = PathBuf::from( value ),
+            "script" => parsed.script = Some( PathBuf::from( value ) ),
+            _ => parsed.overrides.push( (key.to_string(), value) ),
+        }
+    }
+    Ok(parsed)
+}
+
+// コマンドラインで指定した値  TOMLの値(数値、真偽値、配列等)として読めなければ文字列にする。
+fn override_value( value: &str ) -> toml::Value {
+    format!("value = {}", value).parse::<toml::Table>().ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+fn decode_lines( lines: &[String] ) -> Result<Vec<InternalCommand>, anyhow::Error> {
+    lines.iter().map(|line| parse_command(line).map(command_decode)).collect()
+}
+
+fn parse_command( line: &str ) -> Result<UiCommand, anyhow::Error> {
+    line.parse::<UiCommand>().map_err(|_| anyhow::anyhow!("設定の値が正しくありません: {}", line))
+}
+
+fn is_mode_command( command: &InternalCommand ) -> bool {
+    matches!(command, InternalCommand::AM(_) | InternalCommand::USB(_) | InternalCommand::LSB(_) |
+                      InternalCommand::AMUSB(_) | InternalCommand::AMLSB(_) | InternalCommand::CW(_) | InternalCommand::FM)
+}
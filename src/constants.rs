@@ -1,3 +1,4 @@
 
 pub const CHUNK_SIZE: usize = 1024;
 pub const SAMPLING_FREQ: f32 = 48.0e3;
+pub const IF_FREQ: f32 = 12.0e3;
@@ -0,0 +1,189 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use core::f32::consts::PI;
+
+
+// CWデコーダ関連の定数の定義
+const BLOCK: usize = 192;           // Goertzelの1ブロックのサンプル数(4[ms])
+const BLOCK_MS: f32 = BLOCK as f32 * 1000.0 / SAMPLING_FREQ;
+const INITIAL_DIT_MS: f32 = 60.0;   // 起動時の短点の長さ(20WPM)
+const MIN_DIT_MS: f32 = 20.0;       // 60WPM
+const MAX_DIT_MS: f32 = 240.0;      // 5WPM
+
+// モールス符号表(短点を'.'、長点を'-'で表す)
+pub(crate) const MORSE_TABLE: [(&str, char); 54] = [
+    (".-", 'A'), ("-...", 'B'), ("-.-.", 'C'), ("-..", 'D'), (".", 'E'),
+    ("..-.", 'F'), ("--.", 'G'), ("....", 'H'), ("..", 'I'), (".---", 'J'),
+    ("-.-", 'K'), (".-..", 'L'), ("--", 'M'), ("-.", 'N'), ("---", 'O'),
+    (".--.", 'P'), ("--.-", 'Q'), (".-.", 'R'), ("...", 'S'), ("-", 'T'),
+    ("..-", 'U'), ("...-", 'V'), (".--", 'W'), ("-..-", 'X'), ("-.--", 'Y'),
+    ("--..", 'Z'),
+    ("-----", '0'), (".----", '1'), ("..---", '2'), ("...--", '3'), ("....-", '4'),
+    (".....", '5'), ("-....", '6'), ("--...", '7'), ("---..", '8'), ("----.", '9'),
+    (".-.-.-", '.'), ("--..--", ','), ("..--..", '?'), ("-..-.", '/'), ("-...-", '='),
+    (".-.-.", '+'), ("-....-", '-'), ("-.--.", '('), ("-.--.-", ')'), (".----.", '\''),
+    ("---...", ':'), ("-.-.-.", ';'), (".-..-.", '"'), (".--.-.", '@'), ("-.-.--", '!'),
+    ("..--.-", '_'), ("...-..-", '$'), (".-...", '&'),
+];
+
+// CWデコーダ
+// ピッチの周波数の音をGoertzelで検出し、しきい値と短点の長さを追従させながら文字に変換する。
+// 戻り値は、この呼び出しで確定した文字列。
+pub fn create_cw_decoder(pitch: f32) -> impl FnMut(&[f32]) -> String {
+
+    let coeff = 2.0 * (2.0 * PI * pitch / SAMPLING_FREQ).cos();
+    let mut s1: f32 = 0.0;
+    let mut s2: f32 = 0.0;
+    let mut count = 0;
+
+    let mut signal_level: f32 = 0.0;    // マークのレベル
+    let mut noise_level: f32 = 0.0;     // スペースのレベル
+    let mut warmup = 50;                // ノイズのレベルを測るブロック数
+    let mut key_down = false;
+    let mut pending = 0;                // 状態変化が続いているブロック数(グリッチ除去用)
+    let mut duration_ms: f32 = 0.0;     // 現在の状態が続いている時間
+    let mut dit_ms = INITIAL_DIT_MS;
+    let mut marks: Vec<f32> = Vec::new();   // 受信中の文字のマークの長さ[ms]
+    let mut word_done = true;           // 語間のスペースを出力済み
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+
+        for &x in input {
+            // Goertzelアルゴリズム
+            let s0 = x + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+            count += 1;
+            if count < BLOCK { continue; }
+
+            let magnitude = (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0).sqrt() / BLOCK as f32;
+            s1 = 0.0;
+            s2 = 0.0;
+            count = 0;
+
+            // しきい値の追従
+            // ノイズはキーアップ中の平均、信号はキーダウン中の平均で求める。
+            if !key_down || magnitude < noise_level {
+                noise_level += (magnitude - noise_level) * if magnitude < noise_level { 0.3 } else { 0.05 };
+            }
+            if magnitude > signal_level {
+                signal_level += (magnitude - signal_level) * 0.5;
+            } else if key_down {
+                signal_level += (magnitude - signal_level) * 0.1;
+            } else {
+                signal_level += (magnitude - signal_level) * 0.002;
+            }
+            // 信号とノイズの中間をしきい値にする。ノイズの3倍未満はマークとみなさない。
+            let middle = (signal_level + noise_level) / 2.0;
+            let threshold = if key_down {
+                (middle * 0.8).max(noise_level * 2.5)     // ヒステリシスを持たせる。
+            } else {
+                (middle * 1.2).max(noise_level * 3.0)
+            };
+            // 起動直後はノイズのレベルが決まるまで判定しない。
+            if warmup > 0 { warmup -= 1; }
+            let detected = warmup == 0 && magnitude > threshold;
+
+            duration_ms += BLOCK_MS;
+
+            // 状態が2ブロック続いたら変化とみなす。
+            if detected != key_down {
+                pending += 1;
+                if pending < 2 { continue; }
+                pending = 0;
+                let length = duration_ms - BLOCK_MS;
+                duration_ms = BLOCK_MS;
+                key_down = detected;
+
+                if !key_down {
+                    // マークが終わった。短点か長点かを仮に判定し、短点の長さを更新する。
+                    // 短点か長点かは、文字が終わった時に文字の中のマークの長さを比べて決める。
+                    marks.push(length);
+                    if length < 2.0 * dit_ms {
+                        let weight = if length < 0.6 * dit_ms { 0.5 } else { 0.2 };
+                        dit_ms += (length - dit_ms) * weight;
+                    } else {
+                        dit_ms += (length / 3.0 - dit_ms) * 0.2;
+                    }
+                    if marks.len() > 8 { marks.clear(); }     // 符号として長すぎるものは捨てる。
+                } else if length < 2.0 * dit_ms {
+                    // 符号内のスペースは短点と同じ長さなので、速度の推定にも使う。
+                    dit_ms += (length - dit_ms) * 0.3;
+                }
+                dit_ms = dit_ms.clamp(MIN_DIT_MS, MAX_DIT_MS);
+            } else {
+                pending = 0;
+            }
+
+            // スペースが続いている時に文字と語の区切りを判定する。
+            if !key_down {
+                if !marks.is_empty() && duration_ms > 2.0 * dit_ms {
+                    text.push(morse_to_char(&marks_to_symbol(&marks, dit_ms)));
+                    marks.clear();
+                    word_done = false;
+                }
+                if !word_done && duration_ms > 5.0 * dit_ms {
+                    text.push(' ');
+                    word_done = true;
+                }
+            }
+        }
+
+        text
+    }
+}
+
+// 文字のマークの長さを符号にする。
+// 短いマークと3倍程度長いマークが混ざっている時はその中間、揃っている時は短点の長さの2倍で分ける。
+// 受信を始めた直後で短点の長さの推定がずれていても、文字の中の長さの比から判定できる。
+fn marks_to_symbol(marks: &[f32], dit_ms: f32) -> String {
+    let shortest = marks.iter().cloned().fold(f32::INFINITY, f32::min);
+    let longest = marks.iter().cloned().fold(0.0, f32::max);
+    let threshold = if longest > 2.0 * shortest { (shortest + longest) / 2.0 } else { 2.0 * dit_ms };
+    marks.iter().map(|&length| if length < threshold { '.' } else { '-' }).collect()
+}
+
+// 符号を文字に変換する。表に無い符号は'*'にする。
+fn morse_to_char(symbol: &str) -> char {
+    MORSE_TABLE.iter()
+        .find(|(code, _)| *code == symbol)
+        .map(|(_, c)| *c)
+        .unwrap_or('*')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CHUNK_SIZE;
+    use crate::testsignal::keyed_signal;
+
+    const PITCH: f32 = 700.0;
+
+    fn decode(signal: &[f32]) -> String {
+        let mut decoder = create_cw_decoder(PITCH);
+        signal.chunks(CHUNK_SIZE).map(&mut decoder).collect::<String>().trim().to_string()
+    }
+
+    const TEXT: &str = "CQ CQ DE JA1XYZ JA1XYZ K";
+    const SNR_DB: f32 = -5.0;       // Goertzelの1ブロック(約250Hz)の帯域では約+15dB
+
+    #[test]
+    fn decodes_10_wpm() {
+        assert_eq!(decode(&keyed_signal(TEXT, PITCH, 10.0, SNR_DB)), TEXT);
+    }
+
+    #[test]
+    fn decodes_20_wpm() {
+        assert_eq!(decode(&keyed_signal(TEXT, PITCH, 20.0, SNR_DB)), TEXT);
+    }
+
+    // 起動時の短点の長さ(20WPM)の2倍より短い長点でも、最初の文字から正しく判定する。
+    #[test]
+    fn decodes_40_wpm_from_first_character() {
+        let decoded = decode(&keyed_signal(TEXT, PITCH, 40.0, SNR_DB));
+        assert!(decoded.starts_with("CQ "), "{}", decoded);
+        assert_eq!(decoded, TEXT);
+    }
+}
@@ -0,0 +1,84 @@
+// 復調後の音声から文字列を取り出すデコーダの選択
+use crate::cwdecoder::create_cw_decoder;
+use crate::aprs::create_aprs_decoder;
+use crate::rtty::create_rtty_decoder;
+use crate::psk::create_psk_decoder;
+use crate::wefax::create_wefax_decoder;
+use crate::navtex::create_navtex_decoder;
+use crate::sstv::create_sstv_decoder;
+use crate::ft8::{create_ft8_decoder, FT8, FT4};
+use crate::pocsag::create_pocsag_decoder;
+use crate::fileout::FileOutput;
+use std::sync::mpsc::Sender;
+
+/// デコーダは音声のチャンクを受け取り、確定した文字列を返す。
+pub type Decoder = Box<dyn FnMut(&[f32]) -> String>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DecoderType {
+    CW,
+    APRS,
+    RTTY { shift: f32, baud: f32, reverse: bool },
+    PSK31,
+    PSK63,
+    WEFAX { lpm: f32, slant: f32 },
+    NAVTEX { center: f32 },
+    SSTV,
+    FT8,
+    FT4,
+    POCSAG,
+    None,
+}
+
+/// デコーダを生成する。pitchはCWのビート音の周波数。
+/// frame_txには、APRSのように受信したフレームをバイナリで他に渡すデコーダがフレームを送る。
+/// file_txには、WEFAX、NAVTEX、SSTVのように受信した画像やメッセージを保存するデコーダが、保存するファイルを送る。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::decoder::{create_decoder, DecoderType};
+/// use std::sync::mpsc::channel;
+///
+/// let (frame_tx, _frame_rx) = channel();
+/// let (file_tx, _file_rx) = channel();
+/// let mut decoder = create_decoder(DecoderType::CW, 700.0, frame_tx, file_tx);
+/// assert!(decoder(&[0.0; CHUNK_SIZE]).is_empty());
+/// ```
+pub fn create_decoder(dselect: DecoderType, pitch: f32, frame_tx: Sender<Vec<u8>>, file_tx: Sender<FileOutput>) -> Decoder {
+
+    #[cfg(debug_assertions)]
+    debug_decodertype_print( &dselect );
+
+    match dselect {
+        DecoderType::CW => Box::new( create_cw_decoder( pitch ) ),
+        DecoderType::APRS => Box::new( create_aprs_decoder( frame_tx ) ),
+        DecoderType::RTTY { shift, baud, reverse } => Box::new( create_rtty_decoder( shift, baud, reverse ) ),
+        DecoderType::PSK31 => Box::new( create_psk_decoder( 31.25 ) ),
+        DecoderType::PSK63 => Box::new( create_psk_decoder( 62.5 ) ),
+        DecoderType::WEFAX { lpm, slant } => Box::new( create_wefax_decoder( lpm, slant, file_tx ) ),
+        DecoderType::NAVTEX { center } => Box::new( create_navtex_decoder( center, file_tx ) ),
+        DecoderType::SSTV => Box::new( create_sstv_decoder( file_tx ) ),
+        DecoderType::FT8 => Box::new( create_ft8_decoder( &FT8 ) ),
+        DecoderType::FT4 => Box::new( create_ft8_decoder( &FT4 ) ),
+        DecoderType::POCSAG => Box::new( create_pocsag_decoder() ),
+        DecoderType::None => Box::new( |_: &[f32]| String::new() ),
+    }
+}
+
+#[cfg(debug_assertions)]
+fn debug_decodertype_print( dselect: &DecoderType ) {
+    match dselect {
+        DecoderType::CW => println!("Decoder CW"),
+        DecoderType::APRS => println!("Decoder APRS"),
+        DecoderType::RTTY { shift, baud, reverse } => println!("Decoder RTTY {}Hz {}Bd{}", shift, baud, if *reverse { " REV" } else { "" }),
+        DecoderType::PSK31 => println!("Decoder PSK31"),
+        DecoderType::PSK63 => println!("Decoder PSK63"),
+        DecoderType::WEFAX { lpm, slant } => println!("Decoder WEFAX {}LPM slant {}ppm", lpm, slant),
+        DecoderType::NAVTEX { center } => println!("Decoder NAVTEX center {}Hz", center),
+        DecoderType::SSTV => println!("Decoder SSTV"),
+        DecoderType::FT8 => println!("Decoder FT8"),
+        DecoderType::FT4 => println!("Decoder FT4"),
+        DecoderType::POCSAG => println!("Decoder POCSAG"),
+        DecoderType::None => println!("Decoder None"),
+    };
+}
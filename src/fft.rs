@@ -0,0 +1,83 @@
+use core::f32::consts::PI;
+
+/// 基数2のFFT(時間間引き、インプレース)
+/// re、imの長さは同じ2のべき乗にする。
+/// 回転因子は漸化式で求めるので、長いFFTでも誤差が溜まらないように倍精度で計算する。
+///
+/// ```
+/// use thsdr::fft::fft;
+///
+/// // インパルスのスペクトルは平坦になる。
+/// let mut re = vec![0.0; 16];
+/// let mut im = vec![0.0; 16];
+/// re[0] = 1.0;
+/// fft(&mut re, &mut im);
+/// assert!(re.iter().all(|&x| (x - 1.0).abs() < 1e-6));
+/// assert!(im.iter().all(|&x| x.abs() < 1e-6));
+/// ```
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    assert!(n.is_power_of_two() && im.len() == n);
+
+    // ビット反転の並べ替え
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    // バタフライ演算
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * core::f64::consts::PI / length as f64;
+        let (w_im, w_re) = angle.sin_cos();
+        for start in (0..n).step_by(length) {
+            let mut u_re: f64 = 1.0;
+            let mut u_im: f64 = 0.0;
+            for k in 0..length / 2 {
+                let a = start + k;
+                let b = a + length / 2;
+                let (c, s) = (u_re as f32, u_im as f32);
+                let t_re = re[b] * c - im[b] * s;
+                let t_im = re[b] * s + im[b] * c;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+                let next = u_re * w_re - u_im * w_im;
+                u_im = u_re * w_im + u_im * w_re;
+                u_re = next;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+/// 実数の信号にハン窓をかけてFFTし、各ビンの電力を返す。(長さはn/2)
+///
+/// ```
+/// use thsdr::fft::power_spectrum;
+///
+/// let input: Vec<f32> = (0..64).map(|i| (2.0 * std::f32::consts::PI * 4.0 * i as f32 / 64.0).sin()).collect();
+/// let spectrum = power_spectrum(&input);
+/// let peak = spectrum.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap().0;
+/// assert_eq!(spectrum.len(), 32);
+/// assert_eq!(peak, 4);
+/// ```
+pub fn power_spectrum(input: &[f32]) -> Vec<f32> {
+    let n = input.len();
+    let mut re: Vec<f32> = input.iter().enumerate()
+        .map(|(i, &x)| x * (0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos()))
+        .collect();
+    let mut im = vec![0.0; n];
+    fft(&mut re, &mut im);
+    re.iter().zip(im.iter()).take(n / 2).map(|(r, i)| r * r + i * i).collect()
+}
@@ -0,0 +1,63 @@
+// 受信した画像とメッセージをファイルに保存するスレッド
+// PNGの符号化やファイルの書き込みで受信処理が止まらないように、デコーダは保存するものをチャンネルで送る。
+use crate::imageout::{write_png_gray, write_png_rgb};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+/// 保存するファイル
+pub enum FileOutput {
+    GrayImage { path: String, width: usize, height: usize, data: Vec<u8> },    // グレースケール(1画素1バイト)のPNG
+    RgbImage { path: String, width: usize, height: usize, data: Vec<u8> },     // RGB(1画素3バイト)のPNG
+    Text { path: String, text: String },                                        // テキスト(ディレクトリが無ければ作る。)
+}
+
+/// ファイルを保存するスレッドを起動する。保存するファイルを送るチャンネルと、スレッドのハンドルを返す。
+/// 送る側が全て無くなると、受け取った分を保存し終えてから終了する。保存できなかった時はエラーを表示する。
+///
+/// ```
+/// use thsdr::fileout::{start_file_writer, FileOutput};
+///
+/// let path = std::env::temp_dir().join("thsdr_fileout_doctest.png");
+/// let (file_tx, writer) = start_file_writer();
+/// file_tx.send(FileOutput::GrayImage { path: path.display().to_string(), width: 2, height: 1, data: vec![0, 255] }).unwrap();
+/// drop(file_tx);
+/// writer.join().unwrap();                 // 送った画像を保存し終えてから終了する。
+/// assert!(std::fs::read(&path).unwrap().starts_with(b"\x89PNG"));
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn start_file_writer() -> (Sender<FileOutput>, thread::JoinHandle<()>) {
+    let (file_tx, file_rx) = channel::<FileOutput>();
+    let writer = thread::spawn(move || {
+        for output in file_rx {
+            let (path, result) = match output {
+                FileOutput::GrayImage { path, width, height, data } => {
+                    let result = write_png_gray( &path, width, height, &data );
+                    (path, result)
+                },
+                FileOutput::RgbImage { path, width, height, data } => {
+                    let result = write_png_rgb( &path, width, height, &data );
+                    (path, result)
+                },
+                FileOutput::Text { path, text } => {
+                    let result = write_text( &path, &text );
+                    (path, result)
+                },
+            };
+            if let Err(e) = result {
+                eprintln!("{}を保存できません: {}", path, e);
+            }
+        }
+    });
+    (file_tx, writer)
+}
+
+// テキストのファイルを書き込む。
+fn write_text( path: &str, text: &str ) -> Result<(), anyhow::Error> {
+    if let Some(dir) = Path::new( path ).parent() {
+        fs::create_dir_all( dir )?;
+    }
+    fs::write( path, text )?;
+    Ok(())
+}
@@ -1,9 +1,13 @@
 // システムで使う定数の読み込み
-use crate::constants::CHUNK_SIZE;
+use crate::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+use core::f32::consts::PI;
 
 
 // フィルター関連の定数の定義
 pub const N: usize = 512;
+pub const VN: usize = 1023;     // 実行時に設計するフィルタのタップ数。直線位相にするため奇数にする。
+const KAISER_BETA: f32 = 7.0;   // 阻止域減衰量70[dB]程度のカイザー窓
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum FilterType {
     AM3K,
     AM6K,
@@ -21,9 +25,20 @@ pub enum FilterType {
     AMLSB3K,
     AMLSB6K,
     AMLSB7K,
+    CW(f32),    // CW用の狭帯域フィルタ。値は帯域幅[Hz]
     None,
 }
 
+/// 係数表のFIRフィルタ
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::firfilter::{create_filter, FilterType};
+///
+/// let mut filter = create_filter(FilterType::AF3K);
+/// let output = filter(&[0.0; CHUNK_SIZE]);
+/// assert!(output.iter().all(|&x| x == 0.0));
+/// ```
 pub fn create_filter(fselect: FilterType) -> impl FnMut(&[f32]) -> [f32; CHUNK_SIZE] {
 
     #[cfg(debug_assertions)]
@@ -46,7 +61,7 @@ pub fn create_filter(fselect: FilterType) -> impl FnMut(&[f32]) -> [f32; CHUNK_S
         FilterType::AMLSB3K => create_fir_filter( AMLSB3K ),
         FilterType::AMLSB6K => create_fir_filter( AMLSB6K ),
         FilterType::AMLSB7K => create_fir_filter( AMLSB7K ),
-        FilterType::None => {
+        FilterType::CW(_) | FilterType::None => {
             let mut none: [f32;N]=[0.0;N];
             none[0]=1.0;
             create_fir_filter( none )
@@ -54,6 +69,39 @@ pub fn create_filter(fselect: FilterType) -> impl FnMut(&[f32]) -> [f32; CHUNK_S
     }
 }
 
+impl FilterType {
+    /// キャリアを基準にした通過域の下端と上端の周波数\[Hz\]
+    /// 係数表のフィルタの通過域を実測した値にしている。Noneはフィルタ無しを表す。
+    ///
+    /// ```
+    /// use thsdr::firfilter::FilterType;
+    ///
+    /// assert_eq!(FilterType::USB3K.passband(), Some((200.0, 2900.0)));
+    /// assert_eq!(FilterType::None.passband(), None);
+    /// ```
+    pub fn passband(&self) -> Option<(f32, f32)> {
+        match self {
+            FilterType::AM3K => Some((-1400.0, 1400.0)),
+            FilterType::AM6K => Some((-2900.0, 2900.0)),
+            FilterType::AM11K => Some((-5400.0, 5400.0)),
+            FilterType::USB2K => Some((200.0, 2300.0)),
+            FilterType::USB3K => Some((200.0, 2900.0)),
+            FilterType::LSB2K => Some((-2300.0, -200.0)),
+            FilterType::LSB3K => Some((-2900.0, -200.0)),
+            FilterType::AMUSB3K => Some((-100.0, 2900.0)),
+            FilterType::AMUSB6K => Some((-100.0, 5900.0)),
+            FilterType::AMUSB7K => Some((-100.0, 6900.0)),
+            FilterType::AMLSB3K => Some((-2900.0, 100.0)),
+            FilterType::AMLSB6K => Some((-5900.0, 100.0)),
+            FilterType::AMLSB7K => Some((-6900.0, 100.0)),
+            FilterType::CW(bw) => Some((-bw / 2.0, bw / 2.0)),
+            FilterType::AF3K => Some((20.0, 2900.0)),
+            FilterType::AF6K | FilterType::AF11K => Some((20.0, 5900.0)),
+            FilterType::None => None,
+        }
+    }
+}
+
 #[cfg(debug_assertions)]
 fn debug_filtertype_print( fselect: &FilterType) {
     match fselect {
@@ -73,6 +121,7 @@ fn debug_filtertype_print( fselect: &FilterType) {
         FilterType::AMLSB3K => println!("AMLSB3K"),
         FilterType::AMLSB6K => println!("AMLSB6K"),
         FilterType::AMLSB7K => println!("AMLSB7K"),
+        FilterType::CW(bw) => println!("CW {}Hz", bw),
         FilterType::None => println!("Filter None"),
     };
 }
@@ -101,6 +150,136 @@ fn create_fir_filter(coefficients: [f32;N]) -> impl FnMut(&[f32]) -> [f32; CHUNK
 }
 
 
+/// 通過域を実行時に変更できるフィルタ
+/// 引数の通過域(絶対周波数\[Hz\])が前回と異なる時に係数を設計し直す。Noneはフィルタ無し。
+/// 係数を変更したチャンクでは、古い係数と新しい係数の出力をクロスフェードしてクリック音を防ぐ。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::firfilter::create_variable_filter;
+///
+/// let mut filter = create_variable_filter();
+/// let output = filter(&[0.0; CHUNK_SIZE], Some((12200.0, 14900.0)));
+/// assert!(output.iter().all(|&x| x == 0.0));
+/// ```
+pub fn create_variable_filter() -> impl FnMut(&[f32], Option<(f32, f32)>) -> [f32; CHUNK_SIZE] {
+    let mut current: Option<(f32, f32)> = None;
+    let mut coefficients = design_bandpass(None);
+    let mut history: Vec<f32> = vec![0.0; 2 * VN];    // 同じデータを2回書き込み、連続したスライスで畳み込む。
+    let mut pos = 0;
+
+    move |input: &[f32], passband: Option<(f32, f32)>| -> [f32; CHUNK_SIZE] {
+        let mut output: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+
+        let previous = if passband != current {
+            #[cfg(debug_assertions)]
+            match passband {
+                Some((low, high)) => println!("IF filter {:.0}-{:.0} Hz", low, high),
+                None => println!("IF filter None"),
+            }
+            current = passband;
+            Some(std::mem::replace(&mut coefficients, design_bandpass(passband)))
+        } else {
+            None
+        };
+
+        for (idx, &x) in input.iter().enumerate() {
+            pos = if pos == 0 { VN - 1 } else { pos - 1 };
+            history[pos] = x;
+            history[pos + VN] = x;
+            let window = &history[pos..pos + VN];
+
+            let y: f32 = window.iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+            output[idx] = match &previous {
+                Some(old) => {
+                    let y_old: f32 = window.iter().zip(old.iter()).map(|(a, b)| a * b).sum();
+                    let fade = idx as f32 / input.len() as f32;
+                    y_old * (1.0 - fade) + y * fade
+                },
+                None => y,
+            };
+        }
+
+        output
+    }
+}
+
+/// 窓関数法(カイザー窓)によるバンドパスフィルタの設計
+/// 下端が0\[Hz\]以下ならローパス、Noneなら遅延のみのフィルタになる。
+///
+/// ```
+/// use thsdr::firfilter::{design_bandpass, VN};
+///
+/// // 通過域が無い時は、中央のタップだけが1の遅延のみのフィルタになる。
+/// let coefficients = design_bandpass(None);
+/// assert_eq!(coefficients.len(), VN);
+/// assert_eq!(coefficients[VN / 2], 1.0);
+/// ```
+pub fn design_bandpass(passband: Option<(f32, f32)>) -> Vec<f32> {
+    match passband {
+        Some((low, high)) => design_kaiser(low, high, VN),
+        None => {
+            let mut coefficients = vec![0.0; VN];
+            coefficients[VN / 2] = 1.0;
+            coefficients
+        },
+    }
+}
+
+/// 任意のタップ数のローパスフィルタの設計。タップ数は奇数にする。
+///
+/// ```
+/// use thsdr::firfilter::design_lowpass;
+///
+/// // 直流の利得は1になる。
+/// let coefficients = design_lowpass(1000.0, 127);
+/// let gain: f32 = coefficients.iter().sum();
+/// assert!((gain - 1.0).abs() < 0.01);
+/// ```
+pub fn design_lowpass(cutoff: f32, taps: usize) -> Vec<f32> {
+    design_kaiser(0.0, cutoff, taps)
+}
+
+fn design_kaiser(low: f32, high: f32, taps: usize) -> Vec<f32> {
+    let mut coefficients = vec![0.0; taps];
+    let m = (taps / 2) as f32;
+
+    let low = low.max(0.0);
+    let high = high.min(SAMPLING_FREQ / 2.0);
+    if high <= low {
+        return coefficients;    // 通過域が無い時は何も出力しない。
+    }
+
+    let f1 = low / SAMPLING_FREQ;
+    let f2 = high / SAMPLING_FREQ;
+    let i0_beta = bessel_i0(KAISER_BETA);
+    for (i, c) in coefficients.iter_mut().enumerate() {
+        let n = i as f32 - m;
+        let ideal = if n == 0.0 {
+            2.0 * (f2 - f1)
+        } else {
+            ((2.0 * PI * f2 * n).sin() - (2.0 * PI * f1 * n).sin()) / (PI * n)
+        };
+        let r = n / m;
+        let window = bessel_i0(KAISER_BETA * (1.0 - r * r).max(0.0).sqrt()) / i0_beta;
+        *c = ideal * window;
+    }
+    coefficients
+}
+
+// 第1種変形ベッセル関数(0次)。カイザー窓の計算に使う。
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..32 {
+        term *= half / k as f32;
+        sum += term * term;
+        if term * term < sum * 1e-9 { break; }
+    }
+    sum
+}
+
 
 // ここからフィルター係数の定義
 
@@ -0,0 +1,126 @@
+// システムで使う定数の読み込み
+use crate::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+use crate::firfilter::design_lowpass;
+use crate::nco::create_nco;
+use std::f64::consts::PI;
+
+
+// FM復調関連の定数の定義
+const LPF_TAPS: usize = 31;         // ミキサ出力のローパスフィルタのタップ数
+pub const FM_DEMOD_LATENCY: usize = LPF_TAPS / 2;
+const LPF_CUTOFF: f32 = 8000.0;     // IFフィルタで帯域制限済みなので、イメージを除去できれば良い。
+const DEVIATION: f32 = 5000.0;      // この周波数偏移で出力が±1になる。
+
+/// FM復調(位相差分方式)
+/// IFの信号を複素ミキサでベースバンドに変換し、隣り合うサンプルの位相差から瞬時周波数を求める。
+/// carrierはキャリアの周波数\[Hz\]で、チャンクごとに変更できる。
+///
+/// ```
+/// use thsdr::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+/// use thsdr::fmdemod::create_fm_demod;
+///
+/// // キャリアより1[kHz]高い信号は、偏移5[kHz]に対する比の0.2になる。
+/// let mut fm_demod = create_fm_demod();
+/// let mut output = [0.0; CHUNK_SIZE];
+/// for n in 0..4 {
+///     let input: Vec<f32> = (0..CHUNK_SIZE)
+///         .map(|i| (2.0 * std::f32::consts::PI * 13000.0 * ((n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ)).sin())
+///         .collect();
+///     output = fm_demod(&input, 12000.0);
+/// }
+/// assert!((output[CHUNK_SIZE / 2] - 0.2).abs() < 0.01);
+/// ```
+pub fn create_fm_demod() -> impl FnMut(&[f32], f32) -> [f32; CHUNK_SIZE] {
+
+    let coefficients = design_lowpass(LPF_CUTOFF, LPF_TAPS);
+    let mut history_i: [f32; 2 * LPF_TAPS] = [0.0; 2 * LPF_TAPS];
+    let mut history_q: [f32; 2 * LPF_TAPS] = [0.0; 2 * LPF_TAPS];
+    let mut pos = 0;
+    let mut nco = create_nco();
+    let mut last: (f32, f32) = (0.0, 0.0);
+
+    move |input: &[f32], carrier: f32| -> [f32; CHUNK_SIZE] {
+        let mut output: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+        let (sin, cos) = nco(carrier, 1.0);
+
+        for (idx, &x) in input.iter().enumerate() {
+            // 複素ミキサ
+            pos = if pos == 0 { LPF_TAPS - 1 } else { pos - 1 };
+            history_i[pos] = x * cos[idx];
+            history_i[pos + LPF_TAPS] = history_i[pos];
+            history_q[pos] = -x * sin[idx];
+            history_q[pos + LPF_TAPS] = history_q[pos];
+
+            // ローパスフィルタ
+            let i: f32 = history_i[pos..pos + LPF_TAPS].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+            let q: f32 = history_q[pos..pos + LPF_TAPS].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+
+            // 位相差 arg(z[n] * conj(z[n-1]))
+            let re = i * last.0 + q * last.1;
+            let im = q * last.0 - i * last.1;
+            last = (i, q);
+            let freq = im.atan2(re) * SAMPLING_FREQ / (2.0 * core::f32::consts::PI);
+            output[idx] = freq / DEVIATION;
+        }
+
+        output
+    }
+}
+
+const LPF_TAPS_TONE: usize = 127;
+
+/// 音声の瞬時周波数の測定(WEFAXやSSTVのように、音の高さで値を送るモードの復調用)
+/// centerで複素ミキシングしてcutoffのローパスフィルタを通し、decimationサンプルごとに周波数\[Hz\]を返す。
+/// 出力はフィルタの分(LPF_TAPS_TONE / 2サンプル)だけ遅れる。
+///
+/// ```
+/// use thsdr::constants::SAMPLING_FREQ;
+/// use thsdr::fmdemod::create_tone_meter;
+///
+/// let mut meter = create_tone_meter(1900.0, 600.0, 1);
+/// let mut freq = None;
+/// for i in 0..4800 {
+///     let x = (2.0 * std::f32::consts::PI * 1700.0 * i as f32 / SAMPLING_FREQ).sin();
+///     freq = meter(x).or(freq);
+/// }
+/// assert!((freq.unwrap() - 1700.0).abs() < 5.0);
+/// ```
+pub fn create_tone_meter(center: f32, cutoff: f32, decimation: usize) -> impl FnMut(f32) -> Option<f32> {
+
+    let coefficients = design_lowpass(cutoff, LPF_TAPS_TONE);
+    let mut history_i: [f32; 2 * LPF_TAPS_TONE] = [0.0; 2 * LPF_TAPS_TONE];
+    let mut history_q: [f32; 2 * LPF_TAPS_TONE] = [0.0; 2 * LPF_TAPS_TONE];
+    let mut pos = 0;
+    let mut phase: f64 = 0.0;
+    let phase_delta = 2.0 * PI * center as f64 / SAMPLING_FREQ as f64;
+    let mut count = 0;
+    let mut last: (f32, f32) = (1.0, 0.0);
+    let rate = SAMPLING_FREQ / decimation as f32;
+
+    move |x: f32| -> Option<f32> {
+        // 複素ミキサ
+        let (sin, cos) = phase.sin_cos();
+        phase = (phase + phase_delta) % (2.0 * PI);
+        pos = if pos == 0 { LPF_TAPS_TONE - 1 } else { pos - 1 };
+        history_i[pos] = x * cos as f32;
+        history_i[pos + LPF_TAPS_TONE] = history_i[pos];
+        history_q[pos] = -x * sin as f32;
+        history_q[pos + LPF_TAPS_TONE] = history_q[pos];
+
+        // 間引くサンプルはフィルタの計算を省く。
+        count += 1;
+        if count < decimation {
+            return None;
+        }
+        count = 0;
+
+        let i: f32 = history_i[pos..pos + LPF_TAPS_TONE].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+        let q: f32 = history_q[pos..pos + LPF_TAPS_TONE].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+
+        // 位相差 arg(z[n] * conj(z[n-1]))
+        let re = i * last.0 + q * last.1;
+        let im = q * last.0 - i * last.1;
+        last = (i, q);
+        Some(center + im.atan2(re) * rate / (2.0 * core::f32::consts::PI))
+    }
+}
@@ -0,0 +1,54 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use core::f32::consts::PI;
+
+/// 2トーンのFSK復調器
+/// マークとスペースの周波数で1ビット分の相関を取り、その強さの差を-1.0～1.0で返す。
+/// 正ならマーク、負ならスペース。相関は1ビットの移動和なので、出力はビットの長さの半分だけ遅れる。
+///
+/// ```
+/// use thsdr::constants::SAMPLING_FREQ;
+/// use thsdr::fsk::create_fsk_demod;
+///
+/// // マークの周波数の信号を入力すると正になる。
+/// let mut fsk = create_fsk_demod(2125.0, 2295.0, 45.45);
+/// let mut output = 0.0;
+/// for i in 0..4800 {
+///     output = fsk((2.0 * std::f32::consts::PI * 2125.0 * i as f32 / SAMPLING_FREQ).sin());
+/// }
+/// assert!(output > 0.5);
+/// ```
+pub fn create_fsk_demod(mark: f32, space: f32, baud: f32) -> impl FnMut(f32) -> f32 {
+    let length = (SAMPLING_FREQ / baud).round() as usize;
+    let mark_delta = 2.0 * PI * mark / SAMPLING_FREQ;
+    let space_delta = 2.0 * PI * space / SAMPLING_FREQ;
+
+    let mut mark_phase: f32 = 0.0;
+    let mut space_phase: f32 = 0.0;
+    let mut history: Vec<[f32; 4]> = vec![[0.0; 4]; length];
+    let mut sums: [f64; 4] = [0.0; 4];     // 長い移動和で誤差が溜まらないようにf64にする。
+    let mut pos = 0;
+
+    move |x: f32| -> f32 {
+        // 相関の計算 (mark_i, mark_q, space_i, space_q)
+        let values = [
+            x * mark_phase.cos(), x * mark_phase.sin(),
+            x * space_phase.cos(), x * space_phase.sin(),
+        ];
+        mark_phase = (mark_phase + mark_delta) % (2.0 * PI);
+        space_phase = (space_phase + space_delta) % (2.0 * PI);
+        for k in 0..4 {
+            sums[k] += (values[k] - history[pos][k]) as f64;
+        }
+        history[pos] = values;
+        pos = (pos + 1) % length;
+
+        let mark = (sums[0] * sums[0] + sums[1] * sums[1]) as f32;
+        let space = (sums[2] * sums[2] + sums[3] * sums[3]) as f32;
+        if mark + space > 0.0 {
+            (mark - space) / (mark + space)
+        } else {
+            0.0
+        }
+    }
+}
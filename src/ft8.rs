@@ -0,0 +1,490 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use crate::fft::fft;
+use crate::firfilter::design_lowpass;
+use crate::ft8msg::{unpack77, CallHashes};
+use crate::ldpc::{ldpc_decode, crc_ok, CODEWORD_BITS};
+use crate::utc::{unix_time, civil_time};
+use core::f32::consts::PI;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+
+// FT8/FT4関連の定数の定義
+const DECIMATION: usize = 4;                // 48[kHz]から12[kHz]に間引いて処理する。
+const RATE: f32 = SAMPLING_FREQ / DECIMATION as f32;
+const LPF_TAPS: usize = 63;
+const LOWPASS_CUTOFF: f32 = 4000.0;
+const START_OFFSET: f32 = 0.5;              // スロットの始まりから送信の始まりまでの時間[秒]
+const MAX_LATE: f64 = 1.0;                  // スロットの途中から受信を始めた時、これ以上遅れていたらそのスロットは捨てる。
+const RESYNC_ERROR: f64 = 0.05;             // サンプル数で数えた時刻とシステムの時刻がこれ以上ずれたら合わせ直す。
+const FREQ_MIN: f32 = 200.0;                // 信号を探す範囲[Hz]
+const FREQ_MAX: f32 = 3000.0;
+const SYNC_THRESHOLD: f32 = 1.5;            // 同期信号の強さ(他の音との比)がこれ以上なら候補にする。
+const MAX_CANDIDATES: usize = 100;
+const BASEBAND_SIZE: usize = 4096;          // 候補ごとに取り出すベースバンドの信号の長さ
+const DF_STEPS: i32 = 8;                    // 周波数の微調整の範囲(音の間隔の±1/2を16分割)
+const LDPC_ITERATIONS: usize = 30;
+const LLR_SCALE: f32 = 2.83;            // 最大値の差で求めた対数尤度比を正規化する時の標準偏差
+
+// FT4でメッセージの77ビットにかけるスクランブル
+const FT4_SCRAMBLE: [u8; 10] = [0x4A, 0x5E, 0x89, 0xB4, 0xB0, 0x8A, 0x79, 0x55, 0xBE, 0x28];
+
+// モードごとの信号の形式
+pub struct Mode {
+    marker: char,                               // デコード結果の表示でモードを示す文字(WSJT-Xと同じ)
+    slot: f64,                                  // スロットの長さ[秒]
+    symbol_samples: usize,                      // 1シンボルのサンプル数(12[kHz])
+    symbols: usize,
+    gray: &'static [usize],                     // ビットの値から音の番号への変換
+    sync_positions: &'static [usize],           // 同期信号(コスタス配列)の位置
+    sync_tones: &'static [&'static [usize]],
+    fft_size: usize,                            // スロット全体のFFTの長さ
+    scramble: bool,
+}
+
+const FT8_COSTAS: &[usize] = &[3, 1, 4, 0, 6, 5, 2];
+
+pub const FT8: Mode = Mode {
+    marker: '~',
+    slot: 15.0,
+    symbol_samples: 1920,
+    symbols: 79,
+    gray: &[0, 1, 3, 2, 5, 6, 4, 7],
+    sync_positions: &[0, 36, 72],
+    sync_tones: &[FT8_COSTAS, FT8_COSTAS, FT8_COSTAS],
+    fft_size: 262144,
+    scramble: false,
+};
+
+pub const FT4: Mode = Mode {
+    marker: '+',
+    slot: 7.5,
+    symbol_samples: 576,
+    symbols: 103,
+    gray: &[0, 1, 3, 2],
+    sync_positions: &[0, 33, 66, 99],
+    sync_tones: &[&[0, 1, 3, 2], &[1, 0, 2, 3], &[2, 3, 1, 0], &[3, 2, 0, 1]],
+    fft_size: 131072,
+    scramble: true,
+};
+
+impl Mode {
+    fn tones(&self) -> usize {
+        self.gray.len()
+    }
+
+    fn spacing(&self) -> f32 {
+        RATE / self.symbol_samples as f32
+    }
+
+    // 同期信号の(シンボルの位置, 音の番号)
+    fn sync_symbols(&self) -> Vec<(usize, usize)> {
+        self.sync_positions.iter().zip(self.sync_tones.iter())
+            .flat_map(|(&p, tones)| tones.iter().enumerate().map(move |(k, &t)| (p + k, t)))
+            .collect()
+    }
+
+    // データのシンボルの位置
+    fn data_symbols(&self) -> Vec<usize> {
+        let sync = self.sync_symbols();
+        (0..self.symbols).filter(|s| !sync.iter().any(|(p, _)| p == s)).collect()
+    }
+}
+
+// 1つの信号のデコード結果
+struct Decode {
+    snr: i32,
+    dt: f32,                    // 送信の始まりの時刻のずれ[秒]
+    freq: f32,                  // 最も低い音の周波数[Hz]
+    message: String,
+}
+
+// FT8/FT4デコーダ
+// USBで受信した音声をUTCのスロットごとに集め、スロットが終わったら別のスレッドでデコードする。
+// デコード結果は、後のチャンクの処理の時にWSJT-Xと同じ形式の文字列で返す。
+pub fn create_ft8_decoder(mode: &'static Mode) -> impl FnMut(&[f32]) -> String {
+
+    // 間引きのためのローパスフィルタ
+    let coefficients = design_lowpass(LOWPASS_CUTOFF, LPF_TAPS);
+    let mut history: [f32; 2 * LPF_TAPS] = [0.0; 2 * LPF_TAPS];
+    let mut pos = 0;
+    let mut count = 0;
+
+    let slot_samples = (mode.slot * RATE as f64) as usize;
+    let mut sample_time: f64 = 0.0;         // 次のサンプルの時刻(UNIX時間)
+    let mut slot_number: i64 = -1;
+    let mut buffer: Vec<f32> = Vec::with_capacity(slot_samples);
+    let mut collecting = false;
+
+    let hashes = Arc::new(Mutex::new(CallHashes::new()));
+    let (result_tx, result_rx) = mpsc::channel::<String>();
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+        while let Ok(result) = result_rx.try_recv() {
+            text.push_str(&result);
+        }
+
+        // チャンクの先頭の時刻をシステムの時刻から求める。
+        let chunk_start = unix_time() - input.len() as f64 / SAMPLING_FREQ as f64;
+        if (sample_time - chunk_start).abs() > RESYNC_ERROR {
+            sample_time = chunk_start;
+        }
+
+        for &x in input {
+            pos = if pos == 0 { LPF_TAPS - 1 } else { pos - 1 };
+            history[pos] = x;
+            history[pos + LPF_TAPS] = x;
+            count += 1;
+            if count < DECIMATION {
+                continue;
+            }
+            count = 0;
+            let y: f32 = history[pos..pos + LPF_TAPS].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+
+            // サンプルの時刻からスロットとスロット内の位置を決める。
+            let number = (sample_time / mode.slot).floor() as i64;
+            let index = ((sample_time - number as f64 * mode.slot) * RATE as f64) as usize;
+            sample_time += 1.0 / RATE as f64;
+
+            if number != slot_number {
+                if collecting {
+                    let mut samples = std::mem::replace(&mut buffer, Vec::with_capacity(slot_samples));
+                    samples.resize(slot_samples, 0.0);
+                    let start = slot_number as f64 * mode.slot;
+                    let hashes = hashes.clone();
+                    let result_tx = result_tx.clone();
+                    thread::spawn(move || {
+                        let decodes = decode_slot(mode, &samples, &mut hashes.lock().unwrap());
+                        let _ = result_tx.send(format_decodes(mode, start, &decodes));
+                    });
+                }
+                slot_number = number;
+                buffer.clear();
+                collecting = index as f64 <= MAX_LATE * RATE as f64;
+            }
+            if !collecting {
+                continue;
+            }
+            // 途中から始めた時や、時刻を合わせ直した時は、位置に合わせて0で埋める(または上書きする)。
+            if index >= buffer.len() {
+                buffer.resize(index, 0.0);
+                buffer.push(y);
+            } else {
+                buffer[index] = y;
+            }
+        }
+
+        text
+    }
+}
+
+// デコード結果をWSJT-Xと同じ形式(時刻 SNR DT 周波数 ~ メッセージ)にする。
+fn format_decodes(mode: &Mode, start: f64, decodes: &[Decode]) -> String {
+    let (_, _, _, hour, minute, second) = civil_time(start);
+    decodes.iter()
+        .map(|d| format!("{:02}{:02}{:02} {:>3} {:>4.1} {:>4.0} {}  {}\n",
+            hour, minute, second, d.snr, d.dt, d.freq, mode.marker, d.message))
+        .collect()
+}
+
+// 1スロット分(12[kHz])の信号から、すべての信号をデコードする。
+fn decode_slot(mode: &Mode, samples: &[f32], hashes: &mut CallHashes) -> Vec<Decode> {
+    let candidates = find_candidates(mode, samples);
+
+    // スロット全体のFFTから、候補ごとに周波数領域でベースバンドの信号を取り出す。
+    let mut re = vec![0.0; mode.fft_size];
+    let mut im = vec![0.0; mode.fft_size];
+    let length = samples.len().min(mode.fft_size);
+    re[..length].copy_from_slice(&samples[..length]);
+    fft(&mut re, &mut im);
+
+    let mut decodes: Vec<Decode> = Vec::new();
+    for (freq, time) in candidates {
+        if let Some(decode) = decode_candidate(mode, &re, &im, freq, time, hashes) {
+            if !decodes.iter().any(|d| d.message == decode.message) {
+                decodes.push(decode);
+            }
+        }
+    }
+    decodes
+}
+
+// 半シンボルごとのスペクトルで同期信号を探し、候補の(最も低い音の周波数[Hz], 始まりの時刻[秒])を返す。
+fn find_candidates(mode: &Mode, samples: &[f32]) -> Vec<(f32, f32)> {
+    let window = mode.symbol_samples;
+    let size = (2 * window).next_power_of_two();
+    let hop = window / 2;
+    if samples.len() < mode.symbols * window {
+        return Vec::new();
+    }
+
+    let hann: Vec<f32> = (0..window).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / window as f32).cos()).collect();
+    let spectra: Vec<Vec<f32>> = (0..=(samples.len() - window) / hop).map(|step| {
+        let mut re = vec![0.0; size];
+        let mut im = vec![0.0; size];
+        for (i, r) in re.iter_mut().take(window).enumerate() {
+            *r = samples[step * hop + i] * hann[i];
+        }
+        fft(&mut re, &mut im);
+        re.iter().zip(im.iter()).take(size / 2).map(|(r, i)| r * r + i * i).collect()
+    }).collect();
+
+    let bin_width = RATE / size as f32;
+    let spacing = mode.spacing();
+    let tones = mode.tones();
+    let sync = mode.sync_symbols();
+    let max_start = (samples.len() - mode.symbols * window) / hop;
+
+    let mut found: Vec<(f32, f32, f32)> = Vec::new();
+    let mut freq = FREQ_MIN;
+    while freq + (tones - 1) as f32 * spacing < FREQ_MAX {
+        let bins: Vec<usize> = (0..tones).map(|t| ((freq + t as f32 * spacing) / bin_width).round() as usize).collect();
+        let mut best = (0.0, 0);
+        for start in 0..=max_start {
+            // 同期信号の音の電力と、同じシンボルの他の音の電力を比べる。
+            let mut signal = 0.0;
+            let mut total = 0.0;
+            for &(symbol, tone) in &sync {
+                let spectrum = &spectra[start + 2 * symbol];
+                signal += spectrum[bins[tone]];
+                total += bins.iter().map(|&b| spectrum[b]).sum::<f32>();
+            }
+            let score = signal / ((total - signal) / (tones - 1) as f32).max(1e-12);
+            if score > best.0 {
+                best = (score, start);
+            }
+        }
+        if best.0 > SYNC_THRESHOLD {
+            found.push((best.0, freq, (best.1 * hop) as f32 / RATE));
+        }
+        freq += spacing / 2.0;
+    }
+
+    // 強い順に、近い周波数の重複を除いて選ぶ。
+    found.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut candidates: Vec<(f32, f32)> = Vec::new();
+    for (_, freq, time) in found {
+        if candidates.len() < MAX_CANDIDATES && !candidates.iter().any(|c| (c.0 - freq).abs() < spacing) {
+            candidates.push((freq, time));
+        }
+    }
+    candidates
+}
+
+// 1つの候補について、周波数と時刻を微調整し、LDPCの復号、CRCの確認、メッセージの展開を行う。
+fn decode_candidate(mode: &Mode, re: &[f32], im: &[f32], freq: f32, time: f32, hashes: &mut CallHashes) -> Option<Decode> {
+    let n = mode.fft_size;
+    let m = BASEBAND_SIZE;
+    let tones = mode.tones();
+    let spacing = mode.spacing();
+    let bin_width = RATE / n as f32;
+
+    // 信号の中心のまわりのビンを取り出して逆FFTし、間引いたベースバンドの信号にする。
+    let center = freq + (tones - 1) as f32 / 2.0 * spacing;
+    let center_bin = (center / bin_width).round() as isize;
+    let mut zr = vec![0.0; m];
+    let mut zi = vec![0.0; m];
+    for i in 0..m {
+        let offset = i as isize - (m / 2) as isize;
+        let k = center_bin + offset;
+        if k < 0 || k >= (n / 2) as isize {
+            continue;
+        }
+        let d = offset.rem_euclid(m as isize) as usize;
+        zr[d] = re[k as usize];
+        zi[d] = -im[k as usize];        // 共役をとってFFTすると逆FFTになる。
+    }
+    fft(&mut zr, &mut zi);
+    zi.iter_mut().for_each(|v| *v = -*v);
+
+    let rate = bin_width * m as f32;
+    let sps = (mode.symbol_samples as f32 * rate / RATE).round() as usize;
+    let base = freq - center_bin as f32 * bin_width;     // ベースバンドでの最も低い音の周波数
+
+    // 各音のシンボル1つ分の回転因子
+    let tone_table = |df: f32| -> Vec<Vec<(f32, f32)>> {
+        (0..tones).map(|t| {
+            let omega = -2.0 * PI * (base + df + t as f32 * spacing) / rate;
+            (0..sps).map(|i| { let (s, c) = (omega * i as f32).sin_cos(); (c, s) }).collect()
+        }).collect()
+    };
+    // startサンプルから始まるシンボルの、音の電力
+    let power = |table: &[Vec<(f32, f32)>], start: isize, tone: usize| -> f32 {
+        let mut sum = (0.0, 0.0);
+        for (i, &(c, s)) in table[tone].iter().enumerate() {
+            let k = start + i as isize;
+            if k < 0 || k >= m as isize { continue; }
+            let (a, b) = (zr[k as usize], zi[k as usize]);
+            sum.0 += a * c - b * s;
+            sum.1 += a * s + b * c;
+        }
+        sum.0 * sum.0 + sum.1 * sum.1
+    };
+
+    // 同期信号の電力が最大になる周波数と時刻を探す。
+    let sync = mode.sync_symbols();
+    let coarse = (time * rate).round() as isize;
+    let mut best = (f32::MIN, 0.0, 0);
+    for step in -DF_STEPS..=DF_STEPS {
+        let df = step as f32 * spacing / (2 * DF_STEPS) as f32;
+        let table = tone_table(df);
+        for start in coarse - sps as isize..=coarse + sps as isize {
+            let score: f32 = sync.iter().map(|&(symbol, tone)| power(&table, start + (symbol * sps) as isize, tone)).sum();
+            if score > best.0 {
+                best = (score, df, start);
+            }
+        }
+    }
+    let (_, df, start) = best;
+    let table = tone_table(df);
+    let magnitudes: Vec<Vec<f32>> = (0..mode.symbols).map(|symbol| {
+        (0..tones).map(|t| power(&table, start + (symbol * sps) as isize, t).sqrt()).collect()
+    }).collect();
+
+    // 同期信号の音がほとんど合っていなければ、LDPCの復号を省く。
+    let argmax = |values: &[f32]| (0..values.len()).max_by(|&a, &b| values[a].total_cmp(&values[b])).unwrap_or(0);
+    let sync_ok = sync.iter().filter(|&&(symbol, tone)| argmax(&magnitudes[symbol]) == tone).count();
+    if sync_ok < sync.len() / 3 {
+        return None;
+    }
+
+    // 対数尤度比の求め方を変えて、LDPCの復号を2回まで試す。
+    let data = mode.data_symbols();
+    let codeword = [LlrMethod::Likelihood, LlrMethod::MaxLog].iter()
+        .filter_map(|&method| ldpc_decode(&soft_bits(mode, &magnitudes, &data, method), LDPC_ITERATIONS))
+        .find(|codeword| crc_ok(codeword))?;
+    let mut message = [0u8; 77];
+    message.copy_from_slice(&codeword[..77]);
+    if mode.scramble {
+        for (i, bit) in message.iter_mut().enumerate() {
+            *bit ^= (FT4_SCRAMBLE[i / 8] >> (7 - i % 8)) & 1;
+        }
+    }
+    let text = unpack77(&message, hashes)?;
+
+    // SNRは同期信号の音と他の音の電力の比から求め、2500[Hz]の帯域での値にする。
+    let mut signal = 0.0;
+    let mut noise = 0.0;
+    for &(symbol, tone) in &sync {
+        let mags = &magnitudes[symbol];
+        signal += mags[tone] * mags[tone];
+        noise += mags.iter().enumerate().filter(|&(t, _)| t != tone).map(|(_, v)| v * v).sum::<f32>() / (tones - 1) as f32;
+    }
+    let ratio = (signal / noise.max(1e-12) - 1.0).max(1e-3);
+    let snr = (10.0 * ratio.log10() + 10.0 * (spacing / 2500.0).log10()).round().max(-30.0) as i32;
+
+    Some(Decode {
+        snr,
+        dt: start as f32 / rate - START_OFFSET,
+        freq: freq + df,
+        message: text,
+    })
+}
+
+#[derive(Clone, Copy)]
+enum LlrMethod {
+    Likelihood,         // 信号の振幅と雑音から、すべての音の尤度を足し合わせる。
+    MaxLog,             // ビットの値ごとに最大の振幅の差をとり、標準偏差で正規化する。
+}
+
+// データのシンボルの各音の振幅から、各ビットの対数尤度比(正ならビットが1)を求める。
+fn soft_bits(mode: &Mode, magnitudes: &[Vec<f32>], data: &[usize], method: LlrMethod) -> Vec<f32> {
+    let bits_per_symbol = mode.tones().trailing_zeros() as usize;
+    let mut llr: Vec<f32> = Vec::with_capacity(CODEWORD_BITS);
+
+    match method {
+        LlrMethod::Likelihood => {
+            // 雑音の電力は電力の中央値から、信号の振幅は各シンボルの最大の電力から推定する。
+            // 音ごとの尤度はI0(2As/σ^2)をexp(2As/σ^2)で近似する。
+            let mut powers: Vec<f32> = data.iter().flat_map(|&s| magnitudes[s].iter().map(|m| m * m)).collect();
+            powers.sort_by(|a, b| a.total_cmp(b));
+            let noise = powers[powers.len() / 2].max(1e-12) / core::f32::consts::LN_2;
+            let peak = data.iter().map(|&s| magnitudes[s].iter().fold(0.0f32, |a, &m| a.max(m * m))).sum::<f32>() / data.len() as f32;
+            let amplitude = (peak - noise).max(0.0).sqrt();
+            for &symbol in data {
+                let metric: Vec<f32> = magnitudes[symbol].iter().map(|m| 2.0 * amplitude * m / noise).collect();
+                let top = metric.iter().fold(f32::MIN, |a, &b| a.max(b));
+                for j in (0..bits_per_symbol).rev() {
+                    let mut sum = [1e-30f32; 2];
+                    for (value, &tone) in mode.gray.iter().enumerate() {
+                        sum[(value >> j) & 1] += (metric[tone] - top).exp();
+                    }
+                    llr.push((sum[1] / sum[0]).ln());
+                }
+            }
+        },
+        LlrMethod::MaxLog => {
+            for &symbol in data {
+                let mags = &magnitudes[symbol];
+                for j in (0..bits_per_symbol).rev() {
+                    let mut max = [f32::MIN; 2];
+                    for (value, &tone) in mode.gray.iter().enumerate() {
+                        let bit = (value >> j) & 1;
+                        max[bit] = max[bit].max(mags[tone]);
+                    }
+                    llr.push(max[1] - max[0]);
+                }
+            }
+            let mean = llr.iter().sum::<f32>() / llr.len() as f32;
+            let variance = llr.iter().map(|l| l * l).sum::<f32>() / llr.len() as f32 - mean * mean;
+            let scale = LLR_SCALE / variance.max(1e-12).sqrt();
+            llr.iter_mut().for_each(|l| *l *= scale);
+        },
+    }
+    llr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ft8msg::pack_standard;
+    use crate::ldpc::ldpc_encode;
+    use crate::testsignal::gaussian_noise;
+
+    // 符号語をFT8の79シンボル(コスタス配列7 + データ29 + コスタス配列7 + データ29 + コスタス配列7)の音の番号にする。
+    fn ft8_tones(codeword: &[u8]) -> Vec<usize> {
+        let mut tones = vec![0; FT8.symbols];
+        for (symbol, tone) in FT8.sync_symbols() {
+            tones[symbol] = tone;
+        }
+        for (symbol, bits) in FT8.data_symbols().into_iter().zip(codeword.chunks(3)) {
+            tones[symbol] = FT8.gray[(bits[0] << 2 | bits[1] << 1 | bits[2]) as usize];
+        }
+        tones
+    }
+
+    // 最も低い音の周波数freq[Hz]の信号を、スロットの始まりから0.5秒+dt[秒]に加える(12[kHz]、位相連続)。
+    fn add_signal(slot: &mut [f32], message: &[u8], freq: f32, dt: f32, amplitude: f32) {
+        let start = ((START_OFFSET + dt) * RATE) as usize;
+        let mut phase = 0.0f32;
+        for (n, tone) in ft8_tones(&ldpc_encode(message)).into_iter().enumerate() {
+            let step = 2.0 * PI * (freq + tone as f32 * FT8.spacing()) / RATE;
+            for i in 0..FT8.symbol_samples {
+                slot[start + n * FT8.symbol_samples + i] += amplitude * phase.sin();
+                phase = (phase + step) % (2.0 * PI);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_synthesised_slot() {
+        let mut slot = vec![0.0f32; (FT8.slot * RATE as f64) as usize];
+        let mut noise = gaussian_noise(2024);
+        slot.iter_mut().for_each(|x| *x = 0.1 * noise());
+        add_signal(&mut slot, &pack_standard("CQ", "K1ABC", "FN42"), 1000.0, 0.0, 0.02);
+        add_signal(&mut slot, &pack_standard("K1ABC", "JA1XYZ", "R-12"), 1512.5, 0.3, 0.02);
+
+        let mut decodes = decode_slot(&FT8, &slot, &mut CallHashes::new());
+        decodes.sort_by(|a, b| a.freq.total_cmp(&b.freq));
+        let messages: Vec<&str> = decodes.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["CQ K1ABC FN42", "K1ABC JA1XYZ R-12"]);
+        for (decode, (freq, dt)) in decodes.iter().zip([(1000.0, 0.0), (1512.5, 0.3)]) {
+            assert!((decode.freq - freq).abs() < 1.0, "{}", decode.freq);
+            assert!((decode.dt - dt).abs() < 0.05, "{}", decode.dt);
+        }
+        // 雑音は6kHzの帯域で電力0.01なので、2500Hzの帯域でのSNRは約-13dB
+        assert!(decodes.iter().all(|d| (-16..=-10).contains(&d.snr)), "{:?}", decodes.iter().map(|d| d.snr).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,263 @@
+// FT8/FT4の77ビットのメッセージを文字列にする。
+use std::collections::HashMap;
+
+
+// メッセージ関連の定数の定義
+const NTOKENS: u32 = 2063592;           // c28のうち、DE、QRZ、CQ等の特別な語に使う範囲
+const MAX22: u32 = 4194304;             // c28のうち、ハッシュのコールサインに使う範囲
+const MAXGRID4: u32 = 32400;            // g15のうち、4桁のグリッドロケータに使う範囲
+const HASH_MULTIPLIER: u64 = 47055833459;
+const C1: &[u8] = b" 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const C2: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const C3: &[u8] = b"0123456789";
+const C4: &[u8] = b" ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const C38: &[u8] = b" 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ/";
+const C42: &[u8] = b" 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ+-./?";
+
+// 受信したコールサインのハッシュ(22ビット)の表
+// 長いコールサインはハッシュだけで送られることがあるので、以前に受信したコールサインから探す。
+pub type CallHashes = HashMap<u32, String>;
+
+// 77ビットのメッセージを文字列にする。対応していない種類のメッセージはNoneになる。
+pub fn unpack77(bits: &[u8], hashes: &mut CallHashes) -> Option<String> {
+    let i3 = field(bits, 74, 3) as u32;
+    let n3 = field(bits, 71, 3) as u32;
+
+    match (i3, n3) {
+        // 自由文(13文字)
+        (0, 0) => {
+            let mut n = field(bits, 0, 71);
+            let mut text = [b' '; 13];
+            for c in text.iter_mut().rev() {
+                *c = C42[(n % 42) as usize];
+                n /= 42;
+            }
+            Some(String::from_utf8_lossy(&text).trim().to_string())
+        },
+        // DXペディション (K1ABC RR73; W9XYZ <KH1/KH7Z> -08)
+        (0, 1) => {
+            let call1 = unpack28(field(bits, 0, 28) as u32, hashes);
+            let call2 = unpack28(field(bits, 28, 28) as u32, hashes);
+            let hashed = lookup_hash(field(bits, 56, 10) as u32, 10, hashes);
+            let report = 2 * field(bits, 66, 5) as i32 - 30;
+            Some(format!("{} RR73; {} {} {:+03}", call1, call2, hashed, report))
+        },
+        // テレメトリ(16進数)
+        (0, 5) => Some(format!("{:X}", field(bits, 0, 71))),
+        // 標準のメッセージ (CQ JA1XYZ PM95、JA1XYZ K1ABC R-12等)
+        (1, _) | (2, _) => {
+            let suffix = if i3 == 1 { "/R" } else { "/P" };
+            let mut call1 = unpack28(field(bits, 0, 28) as u32, hashes);
+            let mut call2 = unpack28(field(bits, 29, 28) as u32, hashes);
+            if bits[28] == 1 { call1.push_str(suffix); }
+            if bits[57] == 1 { call2.push_str(suffix); }
+            let acknowledge = bits[58] == 1;
+            let g15 = field(bits, 59, 15) as u32;
+
+            let last = if g15 <= MAXGRID4 {
+                let grid = grid4(g15);
+                if acknowledge { format!("R {}", grid) } else { grid }
+            } else {
+                match g15 - MAXGRID4 {
+                    1 => String::new(),
+                    2 => "RRR".to_string(),
+                    3 => "RR73".to_string(),
+                    4 => "73".to_string(),
+                    report => {
+                        let mut snr = report as i32 - 35;
+                        if snr > 50 { snr -= 101; }
+                        if acknowledge { format!("R{:+03}", snr) } else { format!("{:+03}", snr) }
+                    },
+                }
+            };
+            Some(format!("{} {} {}", call1, call2, last).trim_end().to_string())
+        },
+        // 標準でないコールサインを含むメッセージ
+        (4, _) => {
+            let mut n = field(bits, 12, 58);
+            let mut text = [b' '; 11];
+            for c in text.iter_mut().rev() {
+                *c = C38[(n % 38) as usize];
+                n /= 38;
+            }
+            let call = String::from_utf8_lossy(&text).trim().to_string();
+            save_hash(&call, hashes);
+            if bits[73] == 1 {
+                return Some(format!("CQ {}", call));
+            }
+            let hashed = lookup_hash(field(bits, 0, 12) as u32, 12, hashes);
+            let (call1, call2) = if bits[70] == 0 { (hashed, call) } else { (call, hashed) };
+            let last = match field(bits, 71, 2) {
+                1 => "RRR",
+                2 => "RR73",
+                3 => "73",
+                _ => "",
+            };
+            Some(format!("{} {} {}", call1, call2, last).trim_end().to_string())
+        },
+        _ => None,
+    }
+}
+
+// startビット目からlengthビットを、先頭を上位として数値にする。
+fn field(bits: &[u8], start: usize, length: usize) -> u128 {
+    bits[start..start + length].iter().fold(0u128, |n, &b| (n << 1) | b as u128)
+}
+
+// 28ビットのコールサイン(または特別な語、ハッシュ)を文字列にする。
+fn unpack28(n28: u32, hashes: &mut CallHashes) -> String {
+    if n28 < NTOKENS {
+        return match n28 {
+            0 => "DE".to_string(),
+            1 => "QRZ".to_string(),
+            2 => "CQ".to_string(),
+            3..=1002 => format!("CQ {:03}", n28 - 3),
+            _ => {
+                // CQの後の4文字までの英字(CQ DX、CQ TEST等)
+                let mut n = n28 - 1003;
+                let mut text = [b' '; 4];
+                for c in text.iter_mut().rev() {
+                    *c = C4[(n % 27) as usize];
+                    n /= 27;
+                }
+                format!("CQ {}", String::from_utf8_lossy(&text).trim())
+            },
+        };
+    }
+    if n28 - NTOKENS < MAX22 {
+        return lookup_hash(n28 - NTOKENS, 22, hashes);
+    }
+
+    let mut n = n28 - NTOKENS - MAX22;
+    let mut text = [b' '; 6];
+    for (i, table) in [C1, C2, C3, C4, C4, C4].iter().enumerate().rev() {
+        let size = table.len() as u32;
+        text[i] = table[(n % size) as usize];
+        n /= size;
+    }
+    let call = String::from_utf8_lossy(&text).trim().to_string();
+    save_hash(&call, hashes);
+    call
+}
+
+// 15ビットを4桁のグリッドロケータ(AA00～RR99)にする。
+fn grid4(g15: u32) -> String {
+    let chars = [
+        b'A' + (g15 / 1800) as u8,
+        b'A' + (g15 / 100 % 18) as u8,
+        b'0' + (g15 / 10 % 10) as u8,
+        b'0' + (g15 % 10) as u8,
+    ];
+    String::from_utf8_lossy(&chars).to_string()
+}
+
+// コールサインの22ビットのハッシュ
+// 12ビット、10ビットのハッシュは、この上位のビットになる。
+fn hash22(call: &str) -> u32 {
+    let bytes = call.as_bytes();
+    let n = (0..11).fold(0u64, |n, i| {
+        let c = bytes.get(i).copied().unwrap_or(b' ');
+        38 * n + C38.iter().position(|&x| x == c).unwrap_or(0) as u64
+    });
+    (n.wrapping_mul(HASH_MULTIPLIER) >> (64 - 22)) as u32
+}
+
+fn save_hash(call: &str, hashes: &mut CallHashes) {
+    if call.len() >= 3 && !call.starts_with("CQ") {
+        hashes.insert(hash22(call), call.to_string());
+    }
+}
+
+// bitsビットのハッシュからコールサインを探す。見つからなければ<...>になる。
+fn lookup_hash(hash: u32, bits: u32, hashes: &CallHashes) -> String {
+    hashes.iter()
+        .find(|(&h, _)| h >> (22 - bits) == hash)
+        .map(|(_, call)| format!("<{}>", call))
+        .unwrap_or_else(|| "<...>".to_string())
+}
+
+// 試験用: 標準のメッセージ(i3=1)の77ビットをつくる。
+// call1はCQか標準のコールサイン、lastはグリッドロケータ、RRR、RR73、73、レポート(-12、R+05等)か空にする。
+#[cfg(test)]
+pub fn pack_standard(call1: &str, call2: &str, last: &str) -> Vec<u8> {
+    let pack28 = |call: &str| -> u32 {
+        if call == "CQ" {
+            return 2;
+        }
+        // 3文字目が数字になるように、2文字目が数字なら先頭に空白を加える。
+        let call = if call.as_bytes()[1].is_ascii_digit() { format!(" {:5}", call) } else { format!("{:6}", call) };
+        let n = call.bytes().zip([C1, C2, C3, C4, C4, C4]).fold(0u32, |n, (c, table)| {
+            n * table.len() as u32 + table.iter().position(|&x| x == c).unwrap() as u32
+        });
+        NTOKENS + MAX22 + n
+    };
+    let (acknowledge, g15) = match last {
+        "" => (false, MAXGRID4 + 1),
+        "RRR" => (false, MAXGRID4 + 2),
+        "RR73" => (false, MAXGRID4 + 3),
+        "73" => (false, MAXGRID4 + 4),
+        _ if last.starts_with(['+', '-', 'R']) => {
+            let acknowledge = last.starts_with('R');
+            let snr: i32 = last.trim_start_matches('R').parse().unwrap();
+            (acknowledge, MAXGRID4 + (if snr < -30 { snr + 101 } else { snr } + 35) as u32)
+        },
+        _ => {
+            let g = last.as_bytes();
+            (false, (g[0] - b'A') as u32 * 1800 + (g[1] - b'A') as u32 * 100 + (g[2] - b'0') as u32 * 10 + (g[3] - b'0') as u32)
+        },
+    };
+    let mut bits = Vec::with_capacity(77);
+    let mut push = |value: u32, length: usize| bits.extend((0..length).rev().map(|k| (value >> k & 1) as u8));
+    push(pack28(call1), 28);
+    push(0, 1);
+    push(pack28(call2), 28);
+    push(0, 1);
+    push(acknowledge as u32, 1);
+    push(g15, 15);
+    push(1, 3);
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unpack(bits: &[u8]) -> Option<String> {
+        unpack77(bits, &mut CallHashes::new())
+    }
+
+    #[test]
+    fn unpacks_standard_messages() {
+        // WSJT-Xと同じビット列になる。
+        let bits: String = pack_standard("CQ", "K1ABC", "FN42").iter().map(|b| (b'0' + b) as char).collect();
+        assert_eq!(bits, "00000000000000000000000000100000010011011110111100011010100010100001100110001");
+        assert_eq!(unpack(&pack_standard("CQ", "K1ABC", "FN42")).as_deref(), Some("CQ K1ABC FN42"));
+        assert_eq!(unpack(&pack_standard("K1ABC", "JA1XYZ", "-12")).as_deref(), Some("K1ABC JA1XYZ -12"));
+        assert_eq!(unpack(&pack_standard("JA1XYZ", "K1ABC", "R+05")).as_deref(), Some("JA1XYZ K1ABC R+05"));
+        assert_eq!(unpack(&pack_standard("K1ABC", "JA1XYZ", "RR73")).as_deref(), Some("K1ABC JA1XYZ RR73"));
+        assert_eq!(unpack(&pack_standard("K1ABC", "JA1XYZ", "")).as_deref(), Some("K1ABC JA1XYZ"));
+    }
+
+    #[test]
+    fn unpacks_free_text() {
+        // 13文字を42進数にして71ビットに入れ、n3=0、i3=0にする。
+        let n = "TNX BOB 73 GL".bytes().fold(0u128, |n, c| n * 42 + C42.iter().position(|&x| x == c).unwrap() as u128);
+        let bits: Vec<u8> = (0..71).rev().map(|k| (n >> k & 1) as u8).chain([0; 6]).collect();
+        assert_eq!(unpack(&bits).as_deref(), Some("TNX BOB 73 GL"));
+    }
+
+    #[test]
+    fn remembers_hashed_callsigns() {
+        let mut hashes = CallHashes::new();
+        unpack77(&pack_standard("CQ", "K1ABC", "FN42"), &mut hashes);
+        assert_eq!(lookup_hash(hash22("K1ABC") >> 10, 12, &hashes), "<K1ABC>");
+        assert_eq!(lookup_hash(hash22("JA1XYZ") >> 10, 12, &hashes), "<...>");
+    }
+
+    #[test]
+    fn rejects_unsupported_types() {
+        let mut bits = vec![0u8; 77];
+        bits[74..].copy_from_slice(&[1, 1, 1]);     // i3=7
+        assert_eq!(unpack(&bits), None);
+    }
+}
@@ -0,0 +1,24 @@
+// 受信した画像をPNGファイルに出力する関数
+// 既にあるファイルは上書きせず、エラーにする。
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+
+// グレースケール(1画素1バイト)の画像を出力する。
+pub fn write_png_gray( path: &str, width: usize, height: usize, data: &[u8] ) -> Result<(), anyhow::Error> {
+    write_png( path, width, height, data, png::ColorType::Grayscale )
+}
+
+// RGB(1画素3バイト)の画像を出力する。
+pub fn write_png_rgb( path: &str, width: usize, height: usize, data: &[u8] ) -> Result<(), anyhow::Error> {
+    write_png( path, width, height, data, png::ColorType::Rgb )
+}
+
+fn write_png( path: &str, width: usize, height: usize, data: &[u8], color: png::ColorType ) -> Result<(), anyhow::Error> {
+    let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    Ok(())
+}
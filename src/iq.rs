@@ -0,0 +1,119 @@
+// システムで使う定数の読み込み
+use crate::constants::{CHUNK_SIZE, IF_FREQ};
+use crate::audio::StereoChunk;
+use crate::firfilter::design_lowpass;
+use crate::nco::create_nco;
+
+
+// I/Q入力関連の定数の定義
+const LPF_TAPS: usize = 127;
+const LPF_CUTOFF: f32 = 11000.0;    // IFに移した時に折り返さないように、±IF_FREQより内側に制限する。
+const DC_SMOOTHING: f32 = 0.0001;   // DCオフセットの測定の平滑化(1サンプルあたり、時定数約0.2秒)
+const IQ_SMOOTHING: f32 = 0.02;     // 振幅と位相の誤差の測定の平滑化(1チャンクあたり、時定数約1秒)
+const MIN_POWER: f32 = 1e-12;       // これより小さい時は無信号として補正しない。
+
+/// IFの入力の選び方
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputMode {
+    Left,       // 左(1番目)のチャンネルを12kHzのIFとして使う。
+    Right,      // 右(2番目)のチャンネルを12kHzのIFとして使う。
+    IQ,         // 左をI、右をQとする直交ダウンコンバータの出力
+    QI,         // 左をQ、右をIとする直交ダウンコンバータの出力(スペクトルが反転する時)
+}
+
+/// サウンドカードの左右のチャンネルから、12kHzのIFのチャンクをつくる。
+/// I/Qの時は、DCオフセットと、IとQの振幅と位相の誤差を信号の統計から求めて補正し、
+/// ±LPF_CUTOFFに帯域を制限してからIF_FREQに周波数を移して実数の信号にする。
+/// 0\[Hz\]のI/Qの信号がIF_FREQになり、正の周波数はIF_FREQより上になる。
+///
+/// ```
+/// use thsdr::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+/// use thsdr::iq::{create_input_converter, InputMode};
+///
+/// let mut left = [0.0; CHUNK_SIZE];
+/// left[0] = 1.0;
+/// let mut converter = create_input_converter(InputMode::Left);
+/// assert_eq!(converter(&[left, [0.0; CHUNK_SIZE]]), left);
+///
+/// // 1[kHz]のI/Qの信号は、IFでは13[kHz]になる。
+/// let mut converter = create_input_converter(InputMode::IQ);
+/// let mut output = [0.0; CHUNK_SIZE];
+/// for n in 0..8 {
+///     let t = |i: usize| 2.0 * std::f32::consts::PI * 1000.0 * (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ;
+///     let i: [f32; CHUNK_SIZE] = std::array::from_fn(|k| 0.5 * t(k).cos());
+///     let q: [f32; CHUNK_SIZE] = std::array::from_fn(|k| 0.5 * t(k).sin());
+///     output = converter(&[i, q]);
+/// }
+/// let spectrum = thsdr::fft::power_spectrum(&output);
+/// let peak = spectrum.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap().0;
+/// assert_eq!(peak, 13000 * CHUNK_SIZE / SAMPLING_FREQ as usize);
+/// ```
+pub fn create_input_converter(mode: InputMode) -> impl FnMut(&StereoChunk) -> [f32; CHUNK_SIZE] {
+
+    #[cfg(debug_assertions)]
+    println!("Input {:?}", mode);
+
+    let coefficients = design_lowpass(LPF_CUTOFF, LPF_TAPS);
+    let mut history_i: [f32; 2 * LPF_TAPS] = [0.0; 2 * LPF_TAPS];
+    let mut history_q: [f32; 2 * LPF_TAPS] = [0.0; 2 * LPF_TAPS];
+    let mut pos = 0;
+    let mut nco = create_nco();
+    let mut dc: (f32, f32) = (0.0, 0.0);
+    let mut power: Option<(f32, f32, f32)> = None;     // IとQの電力と相関(E[I^2], E[Q^2], E[IQ])
+
+    move |input: &StereoChunk| -> [f32; CHUNK_SIZE] {
+        let [left, right] = input;
+        let (i_in, q_in) = match mode {
+            InputMode::Left => return *left,
+            InputMode::Right => return *right,
+            InputMode::IQ => (left, right),
+            InputMode::QI => (right, left),
+        };
+
+        // DCオフセットの除去
+        let mut i_data: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+        let mut q_data: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+        for k in 0..CHUNK_SIZE {
+            dc.0 += (i_in[k] - dc.0) * DC_SMOOTHING;
+            dc.1 += (q_in[k] - dc.1) * DC_SMOOTHING;
+            i_data[k] = i_in[k] - dc.0;
+            q_data[k] = q_in[k] - dc.1;
+        }
+
+        // 振幅と位相の誤差の測定
+        // 受信する信号は平均するとIとQが同じ電力で相関が無いので、ずれを誤差とする。
+        let n = CHUNK_SIZE as f32;
+        let measured = (
+            i_data.iter().map(|x| x * x).sum::<f32>() / n,
+            q_data.iter().map(|x| x * x).sum::<f32>() / n,
+            i_data.iter().zip(q_data.iter()).map(|(i, q)| i * q).sum::<f32>() / n,
+        );
+        let (p_ii, p_qq, p_iq) = match power {
+            Some((a, b, c)) => (a + (measured.0 - a) * IQ_SMOOTHING, b + (measured.1 - b) * IQ_SMOOTHING, c + (measured.2 - c) * IQ_SMOOTHING),
+            None => measured,
+        };
+        power = Some((p_ii, p_qq, p_iq));
+
+        // QからIと相関する成分を引いて直交させ(位相の補正)、Iと同じ電力にする(振幅の補正)。
+        let (leak, scale) = if p_ii > MIN_POWER && p_qq - p_iq * p_iq / p_ii > MIN_POWER {
+            (p_iq / p_ii, (p_ii / (p_qq - p_iq * p_iq / p_ii)).sqrt())
+        } else {
+            (0.0, 1.0)
+        };
+
+        // 帯域を制限して、IF_FREQに移す。
+        let mut output: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+        let (sin, cos) = nco(IF_FREQ, 1.0);
+        for k in 0..CHUNK_SIZE {
+            pos = if pos == 0 { LPF_TAPS - 1 } else { pos - 1 };
+            history_i[pos] = i_data[k];
+            history_i[pos + LPF_TAPS] = i_data[k];
+            history_q[pos] = (q_data[k] - leak * i_data[k]) * scale;
+            history_q[pos + LPF_TAPS] = history_q[pos];
+            let i: f32 = history_i[pos..pos + LPF_TAPS].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+            let q: f32 = history_q[pos..pos + LPF_TAPS].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+            output[k] = i * cos[k] - q * sin[k];
+        }
+        output
+    }
+}
@@ -0,0 +1,161 @@
+// KISSプロトコルでAX.25のフレームをTCPのクライアントに送るTNC
+// 受信処理のスレッドが止まらないように、クライアントごとに送信のスレッドを作り、フレームはキューで渡す。
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const FEND: u8 = 0xC0;
+const FESC: u8 = 0xDB;
+const TFEND: u8 = 0xDC;
+const TFESC: u8 = 0xDD;
+const CLIENT_QUEUE: usize = 64;     // クライアントごとに送信を待てるフレーム数
+
+/// 接続しているクライアント
+pub struct KissClient {
+    tx: SyncSender<Vec<u8>>,        // 送信のスレッドに渡すフレームのキュー
+    stream: TcpStream,              // 切断する時に使う。
+}
+
+/// 接続しているクライアントの一覧
+pub type KissClients = Arc<Mutex<Vec<KissClient>>>;
+
+/// 指定したポートで接続を待ち受けるスレッドを起動する。
+/// ローカルのAPRSクライアントから使うことを想定しているので、127.0.0.1で待ち受ける。
+///
+/// ```
+/// use thsdr::kiss::{kiss_output, start_kiss_server};
+///
+/// // ポート0は空いているポートを使う。クライアントが無い時は何も送らない。
+/// let clients = start_kiss_server(0)?;
+/// kiss_output(&[0x82, 0xa0], &clients);
+/// assert!(clients.lock().unwrap().is_empty());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn start_kiss_server( port: u16 ) -> io::Result<KissClients> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let clients: KissClients = Arc::new(Mutex::new(Vec::new()));
+    let accepted = clients.clone();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    #[cfg(debug_assertions)]
+                    println!("KISS client connected: {:?}", stream.peer_addr());
+                    match start_client_writer(stream) {
+                        Ok(client) => accepted.lock().unwrap().push(client),
+                        Err(e) => eprintln!("KISS client error: {:?}", e),
+                    }
+                },
+                Err(e) => eprintln!("KISS accept error: {:?}", e),
+            }
+        }
+    });
+
+    Ok(clients)
+}
+
+// クライアントに送信するスレッドを起動する。送信に失敗したらスレッドは終了する。
+fn start_client_writer( stream: TcpStream ) -> io::Result<KissClient> {
+    let (tx, rx) = sync_channel::<Vec<u8>>(CLIENT_QUEUE);
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        for data in rx {
+            if writer.write_all(&data).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(KissClient { tx, stream })
+}
+
+/// フレームをKISSでエンコードして全クライアントの送信のキューに入れる。待たずに戻る。
+/// 切断したクライアントと、受け取らずにキューが一杯になったクライアントは一覧から外して切断する。
+///
+/// ```
+/// use std::io::Read;
+/// use std::net::{TcpListener, TcpStream};
+/// use thsdr::kiss::{kiss_output, start_kiss_server};
+///
+/// // 空いているポートで待ち受けて、クライアントとして接続する。
+/// let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+/// let clients = start_kiss_server(port)?;
+/// let mut client = TcpStream::connect(("127.0.0.1", port))?;
+/// while clients.lock().unwrap().is_empty() {
+///     std::thread::sleep(std::time::Duration::from_millis(1));
+/// }
+/// kiss_output(&[0x82, 0xc0], &clients);
+/// let mut data = [0u8; 6];
+/// client.read_exact(&mut data)?;
+/// assert_eq!(data, [0xc0, 0x00, 0x82, 0xdb, 0xdc, 0xc0]);    // FENDはFESC TFENDにする。
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn kiss_output( frame: &[u8], clients: &KissClients ) {
+    let data = kiss_encode( frame );
+    clients.lock().unwrap().retain(|client| match client.tx.try_send(data.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+            let _ = client.stream.shutdown(Shutdown::Both);
+            false
+        },
+    });
+}
+
+// データフレーム(ポート0)としてエンコードする。
+fn kiss_encode( frame: &[u8] ) -> Vec<u8> {
+    let mut data = vec![FEND, 0x00];
+    for &b in frame {
+        match b {
+            FEND => data.extend_from_slice(&[FESC, TFEND]),
+            FESC => data.extend_from_slice(&[FESC, TFESC]),
+            _ => data.push(b),
+        }
+    }
+    data.push(FEND);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+
+    // 接続したクライアント側のソケットと、サーバー側の一覧を作る。
+    fn connect() -> (TcpStream, KissClients) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let clients: KissClients = Arc::new(Mutex::new(vec![start_client_writer(stream).unwrap()]));
+        (peer, clients)
+    }
+
+    #[test]
+    fn sends_encoded_frame() {
+        let (mut peer, clients) = connect();
+        kiss_output(&[0x82, FEND, FESC], &clients);
+        let mut data = [0u8; 8];
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        peer.read_exact(&mut data).unwrap();
+        assert_eq!(data, [FEND, 0x00, 0x82, FESC, TFEND, FESC, TFESC, FEND]);
+        assert_eq!(clients.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn drops_client_that_does_not_read() {
+        // 受け取らないクライアントに送り続けても待たされず、キューが一杯になったら切断される。
+        let (_peer, clients) = connect();
+        let frame = vec![0x55; 65536];
+        for _ in 0..10000 {
+            let start = Instant::now();
+            kiss_output(&frame, &clients);
+            assert!(start.elapsed() < Duration::from_millis(100), "送信で待たされた");
+            if clients.lock().unwrap().is_empty() {
+                return;
+            }
+        }
+        panic!("受け取らないクライアントが切断されない");
+    }
+}
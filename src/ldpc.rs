@@ -0,0 +1,252 @@
+// FT8/FT4の誤り訂正(LDPC(174,91))とCRC-14
+// 符号語は、メッセージ77ビット、CRC14ビット、パリティ83ビットの順に並ぶ。
+
+
+// LDPC関連の定数の定義
+pub const CODEWORD_BITS: usize = 174;
+pub const MESSAGE_BITS: usize = 91;         // メッセージ77ビット + CRC14ビット
+const CHECKS: usize = 83;
+const CRC_POLY: u16 = 0x2757;
+const CRC_BITS: usize = 14;
+
+// パリティ検査行列(各検査に含まれる符号語のビットの位置、1から数える。0は無し)
+const PARITY_CHECKS: [[u8; 7]; CHECKS] = [
+    [  4,  31,  59,  91,  92,  96, 153],
+    [  5,  32,  60,  93, 115, 146,   0],
+    [  6,  24,  61,  94, 122, 151,   0],
+    [  7,  33,  62,  95,  96, 143,   0],
+    [  8,  25,  63,  83,  93,  96, 148],
+    [  6,  32,  64,  97, 126, 138,   0],
+    [  5,  34,  65,  78,  98, 107, 154],
+    [  9,  35,  66,  99, 139, 146,   0],
+    [ 10,  36,  67, 100, 107, 126,   0],
+    [ 11,  37,  67,  87, 101, 139, 158],
+    [ 12,  38,  68, 102, 105, 155,   0],
+    [ 13,  39,  69, 103, 149, 162,   0],
+    [  8,  40,  70,  82, 104, 114, 145],
+    [ 14,  41,  71,  88, 102, 123, 156],
+    [ 15,  42,  59, 106, 123, 159,   0],
+    [  1,  33,  72, 106, 107, 157,   0],
+    [ 16,  43,  73, 108, 141, 160,   0],
+    [ 17,  37,  74,  81, 109, 131, 154],
+    [ 11,  44,  75, 110, 121, 166,   0],
+    [ 45,  55,  64, 111, 130, 161, 173],
+    [  8,  46,  71, 112, 119, 166,   0],
+    [ 18,  36,  76,  89, 113, 114, 143],
+    [ 19,  38,  77, 104, 116, 163,   0],
+    [ 20,  47,  70,  92, 138, 165,   0],
+    [  2,  48,  74, 113, 128, 160,   0],
+    [ 21,  45,  78,  83, 117, 121, 151],
+    [ 22,  47,  58, 118, 127, 164,   0],
+    [ 16,  39,  62, 112, 134, 158,   0],
+    [ 23,  43,  79, 120, 131, 145,   0],
+    [ 19,  35,  59,  73, 110, 125, 161],
+    [ 20,  36,  63,  94, 136, 161,   0],
+    [ 14,  31,  79,  98, 132, 164,   0],
+    [  3,  44,  80, 124, 127, 169,   0],
+    [ 19,  46,  81, 117, 135, 167,   0],
+    [  7,  49,  58,  90, 100, 105, 168],
+    [ 12,  50,  61, 118, 119, 144,   0],
+    [ 13,  51,  64, 114, 118, 157,   0],
+    [ 24,  52,  76, 129, 148, 149,   0],
+    [ 25,  53,  69,  90, 101, 130, 156],
+    [ 20,  46,  65,  80, 120, 140, 170],
+    [ 21,  54,  77, 100, 140, 171,   0],
+    [ 35,  82, 133, 142, 171, 174,   0],
+    [ 14,  30,  83, 113, 125, 170,   0],
+    [  4,  29,  68, 120, 134, 173,   0],
+    [  1,   4,  52,  57,  86, 136, 152],
+    [ 26,  51,  56,  91, 122, 137, 168],
+    [ 52,  84, 110, 115, 145, 168,   0],
+    [  7,  50,  81,  99, 132, 173,   0],
+    [ 23,  55,  67,  95, 172, 174,   0],
+    [ 26,  41,  77, 109, 141, 148,   0],
+    [  2,  27,  41,  61,  62, 115, 133],
+    [ 27,  40,  56, 124, 125, 126,   0],
+    [ 18,  49,  55, 124, 141, 167,   0],
+    [  6,  33,  85, 108, 116, 156,   0],
+    [ 28,  48,  70,  85, 105, 129, 158],
+    [  9,  54,  63, 131, 147, 155,   0],
+    [ 22,  53,  68, 109, 121, 174,   0],
+    [  3,  13,  48,  78,  95, 123,   0],
+    [ 31,  69, 133, 150, 155, 169,   0],
+    [ 12,  43,  66,  89,  97, 135, 159],
+    [  5,  39,  75, 102, 136, 167,   0],
+    [  2,  54,  86, 101, 135, 164,   0],
+    [ 15,  56,  87, 108, 119, 171,   0],
+    [ 10,  44,  82,  91, 111, 144, 149],
+    [ 23,  34,  71,  94, 127, 153,   0],
+    [ 11,  49,  88,  92, 142, 157,   0],
+    [ 29,  34,  87,  97, 147, 162,   0],
+    [ 30,  50,  60,  86, 137, 142, 162],
+    [ 10,  53,  66,  84, 112, 128, 165],
+    [ 22,  57,  85,  93, 140, 159,   0],
+    [ 28,  32,  72, 103, 132, 166,   0],
+    [ 28,  29,  84,  88, 117, 143, 150],
+    [  1,  26,  45,  80, 128, 147,   0],
+    [ 17,  27,  89, 103, 116, 153,   0],
+    [ 51,  57,  98, 163, 165, 172,   0],
+    [ 21,  37,  73, 138, 152, 169,   0],
+    [ 16,  47,  76, 130, 137, 154,   0],
+    [  3,  24,  30,  72, 104, 139,   0],
+    [  9,  40,  90, 106, 134, 151,   0],
+    [ 15,  58,  60,  74, 111, 150, 163],
+    [ 18,  42,  79, 144, 146, 152,   0],
+    [ 25,  38,  65,  99, 122, 160,   0],
+    [ 17,  42,  75, 129, 170, 172,   0],
+];
+
+// 対数尤度比(正ならビットが1)から、確率伝搬法(sum-product)で符号語を復号する。
+// パリティ検査をすべて満たした時は符号語のビット(0/1)を返し、満たさなければNoneを返す。
+pub fn ldpc_decode(llr: &[f32], max_iterations: usize) -> Option<[u8; CODEWORD_BITS]> {
+    // 内部では、正ならビットが0の対数尤度比で計算する。
+    let prior: Vec<f32> = llr.iter().map(|&l| -l).collect();
+    let mut to_bit = vec![[0.0f32; 7]; CHECKS];     // 検査からビットへのメッセージ
+    let mut bits = [0u8; CODEWORD_BITS];
+
+    for _ in 0..max_iterations {
+        // 各ビットの事後の対数尤度比と硬判定
+        let mut posterior = prior.clone();
+        for (c, check) in PARITY_CHECKS.iter().enumerate() {
+            for (k, &v) in check.iter().enumerate().filter(|(_, &v)| v > 0) {
+                posterior[v as usize - 1] += to_bit[c][k];
+            }
+        }
+        for (b, &p) in bits.iter_mut().zip(posterior.iter()) {
+            *b = (p < 0.0) as u8;
+        }
+        if PARITY_CHECKS.iter().all(|check| {
+            check.iter().filter(|&&v| v > 0).map(|&v| bits[v as usize - 1]).sum::<u8>() % 2 == 0
+        }) {
+            return Some(bits);
+        }
+
+        // 検査ごとに、他のビットからのメッセージでビットへのメッセージを更新する。
+        for (c, check) in PARITY_CHECKS.iter().enumerate() {
+            let mut t = [0.0f32; 7];
+            for (k, &v) in check.iter().enumerate().filter(|(_, &v)| v > 0) {
+                t[k] = ((posterior[v as usize - 1] - to_bit[c][k]) / 2.0).tanh();
+            }
+            for (k, &v) in check.iter().enumerate() {
+                if v == 0 { continue; }
+                let product: f32 = check.iter().enumerate()
+                    .filter(|&(j, &w)| w > 0 && j != k)
+                    .map(|(j, _)| t[j])
+                    .product();
+                to_bit[c][k] = 2.0 * product.clamp(-0.999_999, 0.999_999).atanh();
+            }
+        }
+    }
+    None
+}
+
+// 先頭の91ビット(メッセージ77ビット + CRC)のCRCが正しいかを調べる。
+// CRCはメッセージに0を5ビット加えた82ビットから計算する。すべて0のメッセージは無効とする。
+pub fn crc_ok(bits: &[u8]) -> bool {
+    let message = &bits[..MESSAGE_BITS - CRC_BITS];
+    let received = bits[MESSAGE_BITS - CRC_BITS..MESSAGE_BITS].iter().fold(0u16, |crc, &b| (crc << 1) | b as u16);
+    message.iter().any(|&b| b != 0) && crc14(message) == received
+}
+
+// CRC-14(生成多項式0x2757)
+pub fn crc14(message: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &bit in message.iter().chain([0u8; 5].iter()) {
+        let feedback = ((crc >> (CRC_BITS - 1)) & 1) ^ bit as u16;
+        crc = (crc << 1) & ((1 << CRC_BITS) - 1);
+        if feedback != 0 {
+            crc ^= CRC_POLY;
+        }
+    }
+    crc
+}
+
+// 試験用の符号化
+// メッセージ77ビットにCRCを付け、パリティ検査をすべて満たすパリティ83ビットを加えた符号語をつくる。
+#[cfg(test)]
+pub fn ldpc_encode(message: &[u8]) -> [u8; CODEWORD_BITS] {
+    let mut codeword = [0u8; CODEWORD_BITS];
+    codeword[..77].copy_from_slice(&message[..77]);
+    let crc = crc14(&message[..77]);
+    for (i, bit) in codeword[77..MESSAGE_BITS].iter_mut().enumerate() {
+        *bit = (crc >> (CRC_BITS - 1 - i) & 1) as u8;
+    }
+
+    // 検査ごとに、パリティの部分の係数(ビットの位置)と、メッセージの部分の和を求めてGF(2)の連立方程式を解く。
+    let mut rows: Vec<(u128, u8)> = PARITY_CHECKS.iter().map(|check| {
+        let mut mask = 0u128;
+        let mut sum = 0u8;
+        for &v in check.iter().filter(|&&v| v > 0) {
+            let position = v as usize - 1;
+            if position < MESSAGE_BITS { sum ^= codeword[position]; } else { mask |= 1 << (position - MESSAGE_BITS); }
+        }
+        (mask, sum)
+    }).collect();
+    for column in 0..CHECKS {
+        let pivot = (column..CHECKS).find(|&r| rows[r].0 >> column & 1 != 0).expect("パリティの部分が正則ではありません。");
+        rows.swap(column, pivot);
+        let (mask, sum) = rows[column];
+        for (r, row) in rows.iter_mut().enumerate() {
+            if r != column && row.0 >> column & 1 != 0 {
+                row.0 ^= mask;
+                row.1 ^= sum;
+            }
+        }
+    }
+    for (bit, row) in codeword[MESSAGE_BITS..].iter_mut().zip(rows.iter()) {
+        *bit = row.1;
+    }
+    codeword
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CQ K1ABC FN42のメッセージ77ビット
+    const CQ_K1ABC_FN42: &str = "00000000000000000000000000100000010011011110111100011010100010100001100110001";
+
+    fn message() -> Vec<u8> {
+        CQ_K1ABC_FN42.bytes().map(|b| b - b'0').collect()
+    }
+
+    // 受信したビットに対応する対数尤度比(正ならビットが1)
+    fn llr(codeword: &[u8]) -> Vec<f32> {
+        codeword.iter().map(|&b| if b == 1 { 4.0 } else { -4.0 }).collect()
+    }
+
+    #[test]
+    fn crc14_of_known_message() {
+        // 多項式の割り算で求めた値(メッセージに0を5ビット加えた82ビットのCRC)
+        assert_eq!(crc14(&message()), 0b00101100101110);
+    }
+
+    #[test]
+    fn crc_ok_rejects_corrupted_and_zero_messages() {
+        let codeword = ldpc_encode(&message());
+        assert!(crc_ok(&codeword));
+        let mut corrupted = codeword;
+        corrupted[40] ^= 1;
+        assert!(!crc_ok(&corrupted));
+        assert!(!crc_ok(&[0u8; CODEWORD_BITS]));
+    }
+
+    #[test]
+    fn decodes_clean_codeword() {
+        let codeword = ldpc_encode(&message());
+        assert_eq!(ldpc_decode(&llr(&codeword), 30), Some(codeword));
+    }
+
+    #[test]
+    fn corrects_flipped_bits() {
+        let codeword = ldpc_encode(&message());
+        let mut received = llr(&codeword);
+        // 確信度の低い誤りと、確信度の高い誤りを混ぜる。
+        for (n, &i) in [3, 50, 77, 100, 150, 170].iter().enumerate() {
+            received[i] = -received[i] * if n % 2 == 0 { 0.5 } else { 1.0 };
+        }
+        let decoded = ldpc_decode(&received, 30).unwrap();
+        assert_eq!(decoded, codeword);
+        assert!(crc_ok(&decoded));
+    }
+}
@@ -0,0 +1,58 @@
+// THSDRのライブラリ
+// DSPの部品、復調器、デコーダ、IFの入出力、コマンドの型を公開し、他のプログラムからも使えるようにする。
+// 公開していないモジュール(個々のデコーダ等)は、decoder::create_decoderから使う。
+
+// 定数とDSPの部品
+pub mod constants;
+pub mod firfilter;
+pub mod agc;
+pub mod nco;
+pub mod bfo;
+pub mod fft;
+pub mod notch;
+pub mod afc;
+pub mod squelch;
+
+// 復調器とトーン検出
+pub mod fmdemod;
+pub mod fsk;
+pub mod tonedetect;
+
+// デコーダ
+pub mod decoder;
+mod cwdecoder;
+mod aprs;
+mod rtty;
+mod psk;
+mod wefax;
+mod navtex;
+mod sstv;
+mod ldpc;
+mod ft8msg;
+mod ft8;
+mod pocsag;
+mod utc;
+mod imageout;
+pub mod fileout;
+
+// 処理ブロックとその構成
+pub mod pipeline;
+pub mod blocks;
+
+// IFの入出力とスレッド間のキュー、名前付きパイプとKISSへの出力
+pub mod audio;
+pub mod iq;
+pub mod spsc;
+pub mod resampler;
+pub mod pipes;
+pub mod kiss;
+
+// コマンドと受信処理、設定ファイルとスクリプト
+pub mod command;
+pub mod receiver;
+pub mod config;
+pub mod script;
+
+// 試験用の信号
+#[cfg(test)]
+mod testsignal;
@@ -0,0 +1,324 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use crate::fsk::create_fsk_demod;
+use crate::rtty::{LETTERS, FIGURES, FIGS, LTRS, SPACE};
+use crate::utc::timestamp;
+use crate::fileout::FileOutput;
+use std::sync::mpsc::Sender;
+
+
+// NAVTEX(SITOR-B)関連の定数の定義
+const BAUD: f32 = 100.0;
+const SHIFT: f32 = 170.0;
+const PLL_GAIN: f32 = 0.1;          // ビット同期の引き込みの強さ
+const SYNC_WORDS: usize = 13;       // 同期の判定に使う符号の数(DX/RXの比較が4組になる)
+const SYNC_MATCHES: u32 = 3;        // DXとRXが一致する必要がある数
+const LOST_ERRORS: u32 = 12;        // 誤りがこれだけ溜まったら同期を外す。
+const MAX_MESSAGE: usize = 20000;   // これより長いメッセージは途中で捨てる。
+const STORE_DIR: &str = "navtex";
+
+// CCIR476の制御符号
+const ALPHA: u8 = 0x0F;             // 位相合わせ信号1(DXの位置)
+const BETA: u8 = 0x33;              // 位相合わせ信号2
+const REP: u8 = 0x66;               // 位相合わせ信号の間のRXの位置
+
+// CCIR476(7ビット、マーク4つ)からITA2(5ビット)への変換表
+const CCIR476: [(u8, u8); 32] = [
+    (0x47, 0x03), (0x72, 0x19), (0x1D, 0x0E), (0x53, 0x09), (0x56, 0x01), (0x1B, 0x0D), (0x35, 0x1A), (0x69, 0x14),
+    (0x4D, 0x06), (0x17, 0x0B), (0x1E, 0x0F), (0x65, 0x12), (0x39, 0x1C), (0x59, 0x0C), (0x71, 0x18), (0x2D, 0x16),
+    (0x2E, 0x17), (0x55, 0x0A), (0x4B, 0x05), (0x74, 0x10), (0x4E, 0x07), (0x3C, 0x1E), (0x27, 0x13), (0x3A, 0x1D),
+    (0x2B, 0x15), (0x63, 0x11), (0x78, 0x08), (0x6C, 0x02), (0x5C, 0x04), (0x5A, 0x1F), (0x36, 0x1B), (0x6A, 0x00),
+];
+
+// NAVTEXデコーダ
+// centerはマークとスペースの中心の音の周波数[Hz]。マークとスペースの極性は受信した符号から自動的に判定する。
+// FECで2回送られる文字(DXとRX)のうち正しい方を使い、ZCZC～NNNNのメッセージを局ごとにファイルに保存する。
+pub fn create_navtex_decoder(center: f32, file_tx: Sender<FileOutput>) -> impl FnMut(&[f32]) -> String {
+    let mut demod = create_fsk_demod(center - SHIFT / 2.0, center + SHIFT / 2.0, BAUD);
+
+    // ビット同期(DPLL)
+    let mut pll_phase: f32 = 0.0;
+    let mut last_level = false;
+
+    // 文字同期
+    let mut bits: u128 = 0;         // 受信したビット(新しいものが下位)
+    let mut synced = false;
+    let mut inverted = false;
+    let mut bit_count = 0;
+    let mut next_is_dx = false;
+    let mut words: [u8; 5] = [0; 5];    // 直近の5つの符号(RXの5つ前がDX)
+    let mut errors: u32 = 0;
+
+    let mut figures = false;
+    let mut message = create_message_receiver(file_tx);
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+
+        for &x in input {
+            let level = demod(x) > 0.0;
+
+            // 変化点でビットの位相を中央に引き込む。
+            if level != last_level {
+                pll_phase -= (pll_phase - 0.5) * PLL_GAIN;
+                last_level = level;
+            }
+            pll_phase += BAUD / SAMPLING_FREQ;
+            if pll_phase < 1.0 { continue; }
+            pll_phase -= 1.0;
+
+            bits = (bits << 1) | level as u128;
+
+            if !synced {
+                // 符号の区切りとDX/RXの位置を探す。極性は両方を試す。
+                for invert in [false, true] {
+                    let value = if invert { !bits } else { bits };
+                    if let Some(last_is_dx) = find_sync(value) {
+                        synced = true;
+                        inverted = invert;
+                        next_is_dx = !last_is_dx;
+                        bit_count = 0;
+                        errors = 0;
+                        for (i, w) in words.iter_mut().enumerate() {
+                            *w = ((value >> (7 * (4 - i))) & 0x7F) as u8;
+                        }
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            bit_count += 1;
+            if bit_count < 7 { continue; }
+            bit_count = 0;
+            let word = ((if inverted { !bits } else { bits }) & 0x7F) as u8;
+            let is_dx = next_is_dx;
+            next_is_dx = !next_is_dx;
+            let dx = words[0];
+            words.copy_within(1.., 0);
+            words[4] = word;
+            if is_dx { continue; }
+
+            // RXを受信したら、5つ前のDXと合わせて文字を決める。
+            let code = if is_valid(dx) {
+                errors = errors.saturating_sub(1);
+                dx
+            } else if is_valid(word) {
+                errors = errors.saturating_sub(1);
+                word
+            } else {
+                errors += 2;
+                if errors > LOST_ERRORS {
+                    synced = false;
+                }
+                text.push_str(&message('*'));
+                continue;
+            };
+
+            if let Some(data) = ccir476_to_ita2(code) {
+                match data {
+                    FIGS => figures = true,
+                    LTRS => figures = false,
+                    _ => {
+                        let table = if figures { &FIGURES } else { &LETTERS };
+                        if let Some(c) = table[data as usize] {
+                            text.push_str(&message(c));
+                        }
+                        if data == SPACE { figures = false; }
+                    },
+                }
+            }
+        }
+
+        text
+    }
+}
+
+// 直近の符号がすべて正しく、DXとRXが一致する区切りを探す。
+// 見つかれば、最後の符号がDXかどうかを返す。
+fn find_sync(bits: u128) -> Option<bool> {
+    let words: Vec<u8> = (0..SYNC_WORDS)
+        .map(|i| ((bits >> (7 * (SYNC_WORDS - 1 - i))) & 0x7F) as u8)
+        .collect();
+    if !words.iter().all(|&w| is_valid(w)) {
+        return None;
+    }
+    // 位相合わせ信号では、DXの位置にα、RXの位置にREPが送られる。
+    let matches: Vec<u32> = (0..2).map(|parity| {
+        (parity..SYNC_WORDS - 5).step_by(2)
+            .filter(|&i| words[i + 5] == words[i] || (words[i] == ALPHA && words[i + 5] == REP))
+            .count() as u32
+    }).collect();
+    let parity = if matches[0] > matches[1] { 0 } else { 1 };
+    if matches[parity] >= SYNC_MATCHES && matches[parity] > matches[1 - parity] {
+        Some((SYNC_WORDS - 1) % 2 == parity)
+    } else {
+        None
+    }
+}
+
+// CCIR476の符号はマークが4つ、スペースが3つ
+fn is_valid(word: u8) -> bool {
+    word.count_ones() == 4 && word < 0x80
+}
+
+// 文字になる符号をITA2に変換する。位相合わせ信号はNoneになる。
+fn ccir476_to_ita2(word: u8) -> Option<u8> {
+    if word == ALPHA || word == BETA || word == REP {
+        return None;
+    }
+    CCIR476.iter().find(|(code, _)| *code == word).map(|(_, data)| *data)
+}
+
+// 受信した文字からZCZC B1B2B3B4 ～ NNNNのメッセージを取り出し、file_txに送って局(B1)ごとのディレクトリに保存する。
+// 戻り値は画面とTEXTの名前付きパイプに出力する文字列(受信した文字と保存の結果)。
+fn create_message_receiver(file_tx: Sender<FileOutput>) -> impl FnMut(char) -> String {
+    let mut recent = String::new();     // メッセージの開始を探すための直近の文字
+    let mut body: Option<String> = None;
+
+    move |c: char| -> String {
+        let mut text = c.to_string();
+
+        match body.as_mut() {
+            None => {
+                recent.push(c);
+                if recent.len() > 8 { recent.remove(0); }
+                if recent.ends_with("ZCZC") {
+                    body = Some("ZCZC".to_string());
+                    recent.clear();
+                }
+            },
+            Some(b) => {
+                b.push(c);
+                if b.ends_with("NNNN") {
+                    text.push('\n');
+                    text.push_str(&store_message(b, &file_tx));
+                    body = None;
+                } else if b.len() > MAX_MESSAGE {
+                    body = None;
+                }
+            },
+        }
+
+        text
+    }
+}
+
+// メッセージを保存するスレッドに送り、navtex/B1/B1B2B3B4.txtに保存する。番号が00のメッセージは毎回別のファイルにする。
+fn store_message(body: &str, file_tx: &Sender<FileOutput>) -> String {
+    let header: Vec<char> = body.chars().skip(4).filter(|c| *c != ' ').take(4).collect();
+    let valid = header.len() == 4
+        && header[0].is_ascii_uppercase() && header[1].is_ascii_uppercase()
+        && header[2].is_ascii_digit() && header[3].is_ascii_digit();
+    if !valid {
+        return "[NAVTEX header error]\n".to_string();
+    }
+    let id: String = header.iter().collect();
+    let station = header[0];
+    let dir = format!("{}/{}", STORE_DIR, station);
+    let path = if &id[2..] == "00" {
+        format!("{}/{}_{}.txt", dir, id, timestamp())
+    } else {
+        format!("{}/{}.txt", dir, id)
+    };
+    match file_tx.send(FileOutput::Text { path: path.clone(), text: body.to_string() }) {
+        Ok(()) => format!("[NAVTEX {} station {} {} saved {}]\n", id, station, subject_name(header[1]), path),
+        Err(_) => format!("[NAVTEX {} save failed {}]\n", id, path),
+    }
+}
+
+// B2(メッセージの種類)の名前
+fn subject_name(subject: char) -> &'static str {
+    match subject {
+        'A' => "navigational warning",
+        'B' => "meteorological warning",
+        'C' => "ice report",
+        'D' => "search and rescue",
+        'E' => "meteorological forecast",
+        'F' => "pilot service",
+        'G' => "AIS",
+        'H' => "LORAN",
+        'J' => "SATNAV",
+        'K' => "other navaid",
+        'L' => "navigational warning",
+        'T' => "test",
+        'Z' => "no message on hand",
+        _ => "special service",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testsignal::{fsk_signal, ita2_encode};
+    use std::sync::mpsc::channel;
+
+    const CENTER: f32 = 1000.0;
+    const MESSAGE: &str = "ZCZC SE07\r\nGALE WARNING 123\r\nNNNN";
+
+    fn ita2_to_ccir476(data: u8) -> u8 {
+        CCIR476.iter().find(|(_, d)| *d == data).unwrap().0
+    }
+
+    // 位相合わせ信号(DXにα、RXにREP)に続けて文字をFECで送る。RXはDXの5符号後に同じ符号を送る。
+    // corruptは、(文字の番号, DXならtrue)で指定した符号を1ビット誤らせる。
+    fn sitor_b_words(text: &str, corrupt: &[(usize, bool)]) -> Vec<u8> {
+        let phasing = 16;
+        let mut pairs: Vec<(u8, u8)> = vec![(ALPHA, REP); phasing];
+        pairs.extend(ita2_encode(text).into_iter().map(|data| { let code = ita2_to_ccir476(data); (code, code) }));
+        pairs.extend(vec![(ALPHA, REP); 8]);
+        for &(n, dx) in corrupt {
+            let pair = &mut pairs[phasing + n];
+            if dx { pair.0 ^= 0x01; } else { pair.1 ^= 0x01; }
+        }
+        let mut words = vec![REP; 2 * pairs.len() + 5];
+        for (k, &(dx, rx)) in pairs.iter().enumerate() {
+            words[2 * k] = dx;
+            words[2 * k + 5] = rx;
+        }
+        words
+    }
+
+    fn signal(words: &[u8], reverse: bool) -> Vec<f32> {
+        let bits: Vec<bool> = words.iter().flat_map(|&w| (0..7).rev().map(move |i| (w >> i) & 1 == 1)).collect();
+        let (mark, space) = (CENTER + SHIFT / 2.0, CENTER - SHIFT / 2.0);
+        let mut signal = vec![0.0; 4800];
+        signal.extend(if reverse { fsk_signal(&bits, space, mark, BAUD) } else { fsk_signal(&bits, mark, space, BAUD) });
+        signal
+    }
+
+    #[test]
+    fn decodes_fec_message_with_corrupted_copies() {
+        // 文字の番号は、先頭のLTRSを含めたITA2の符号の位置
+        let words = sitor_b_words(MESSAGE, &[(3, true), (8, false), (20, true)]);
+        for reverse in [false, true] {
+            let (file_tx, file_rx) = channel();
+            let mut decoder = create_navtex_decoder(CENTER, file_tx);
+            let text: String = signal(&words, reverse).chunks(1024).map(&mut decoder).collect();
+            assert_eq!(text, "ZCZC SE07\nGALE WARNING 123\nNNNN\n[NAVTEX SE07 station S meteorological forecast saved navtex/S/SE07.txt]\n", "reverse: {}", reverse);
+            match file_rx.try_recv() {
+                Ok(FileOutput::Text { path, text }) => {
+                    assert_eq!(path, "navtex/S/SE07.txt");
+                    assert_eq!(text, "ZCZC SE07\nGALE WARNING 123\nNNNN");
+                },
+                _ => panic!("メッセージを保存していません。"),
+            }
+        }
+    }
+
+    #[test]
+    fn marks_characters_with_both_copies_corrupted() {
+        let words = sitor_b_words(MESSAGE, &[(15, true), (15, false)]);
+        let (file_tx, _file_rx) = channel();
+        let mut decoder = create_navtex_decoder(CENTER, file_tx);
+        let text: String = signal(&words, false).chunks(1024).map(&mut decoder).collect();
+        assert!(text.starts_with("ZCZC SE07\nG*LE WARNING 123\nNNNN\n"), "{}", text);
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let (file_tx, file_rx) = channel();
+        assert_eq!(store_message("ZCZC 1E07\nTEST\nNNNN", &file_tx), "[NAVTEX header error]\n");
+        assert!(file_rx.try_recv().is_err());
+    }
+}
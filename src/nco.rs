@@ -0,0 +1,133 @@
+// システムで使う定数の読み込み
+use crate::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+
+
+// NCO関連の定数の定義
+const TABLE_BITS: u32 = 10;                     // 正弦波の表は1周期を1024点にする。
+const TABLE_SIZE: usize = 1 << TABLE_BITS;
+const FRACTION_BITS: u32 = 32 - TABLE_BITS;     // 位相のうち、表の点の間の補間に使うビット数
+const QUARTER: u32 = 1 << 30;                   // 90度の位相
+
+/// NCO(数値制御発振器)
+/// 32ビットの固定小数点の位相を積算し、正弦波の表を直線補間してsinとcosを求める。
+/// 周波数の分解能は約0.00001\[Hz\]で、補間の誤差によるスプリアスは-100\[dBc\]以下になる。
+/// 周波数と振幅はチャンクごとに指定し、変えた時はチャンクの中で滑らかに変化させるので、位相も振幅も不連続にならない。
+///
+/// ```
+/// use thsdr::nco::create_nco;
+///
+/// let mut nco = create_nco();
+/// let (sin, cos) = nco(1000.0, 1.0);
+/// assert!(sin[0].abs() < 1e-6 && (cos[0] - 1.0).abs() < 1e-6);
+/// assert!(sin.iter().zip(cos.iter()).all(|(s, c)| (s * s + c * c - 1.0).abs() < 1e-3));
+/// ```
+pub fn create_nco() -> impl FnMut(f32, f32) -> ([f32; CHUNK_SIZE], [f32; CHUNK_SIZE]) {
+
+    // 補間のために最後に1点(先頭と同じ値)を加えておく。
+    let table: Vec<f32> = (0..=TABLE_SIZE)
+        .map(|i| (2.0 * core::f64::consts::PI * i as f64 / TABLE_SIZE as f64).sin() as f32)
+        .collect();
+    let lookup = move |phase: u32| -> f32 {
+        let index = (phase >> FRACTION_BITS) as usize;
+        let fraction = (phase & ((1 << FRACTION_BITS) - 1)) as f32 / (1u32 << FRACTION_BITS) as f32;
+        table[index] + (table[index + 1] - table[index]) * fraction
+    };
+
+    let mut phase: u32 = 0;
+    let mut last_step: Option<i64> = None;
+    let mut last_amplitude: Option<f32> = None;
+
+    move |freq: f32, amplitude: f32| -> ([f32; CHUNK_SIZE], [f32; CHUNK_SIZE]) {
+        let mut sin: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+        let mut cos: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+
+        // 1サンプルあたりの位相の増分(負の周波数は逆回転になる)
+        let step = (freq as f64 / SAMPLING_FREQ as f64 * 4294967296.0).round() as i64;
+        let start_step = last_step.unwrap_or(step);
+        let start_amplitude = last_amplitude.unwrap_or(amplitude);
+
+        for i in 0..CHUNK_SIZE {
+            let ratio = (i + 1) as f64 / CHUNK_SIZE as f64;
+            let a = start_amplitude + (amplitude - start_amplitude) * ratio as f32;
+            sin[i] = a * lookup(phase);
+            cos[i] = a * lookup(phase.wrapping_add(QUARTER));
+            let s = start_step + ((step - start_step) as f64 * ratio) as i64;
+            phase = phase.wrapping_add(s as u32);
+        }
+        last_step = Some(step);
+        last_amplitude = Some(amplitude);
+
+        (sin, cos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft;
+    use core::f64::consts::PI;
+
+    const FFT_SIZE: usize = 65536;
+    const LIMIT_DBC: f64 = -90.0;
+    const CARRIER_BINS: usize = 8;      // キャリアの両側のこのビン数は、窓関数のメインローブとしてスプリアスから除く。
+
+    // 7項のBlackman-Harris窓(サイドローブ約-180dB)をかけてFFTし、
+    // キャリアの電力に対する最大のスプリアスの比[dBc]を返す。
+    fn worst_spur(signal: &[f32]) -> f64 {
+        const A: [f64; 7] = [0.271051400693424, -0.433297939234486, 0.218122999543110, -0.065925446388031,
+            0.010811742098371, -0.000776584825226, 0.000015558083758];
+        let n = signal.len();
+        let mut re: Vec<f32> = signal.iter().enumerate().map(|(i, &x)| {
+            let w: f64 = A.iter().enumerate().map(|(k, a)| a * (2.0 * PI * k as f64 * i as f64 / n as f64).cos()).sum();
+            (x as f64 * w) as f32
+        }).collect();
+        let mut im = vec![0.0; n];
+        fft::fft(&mut re, &mut im);
+        let power: Vec<f64> = re.iter().zip(im.iter()).take(n / 2)
+            .map(|(&r, &i)| r as f64 * r as f64 + i as f64 * i as f64).collect();
+
+        let (peak, &carrier) = power.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap();
+        let spur = power.iter().enumerate()
+            .filter(|(i, _)| i.abs_diff(peak) > CARRIER_BINS)
+            .map(|(_, &p)| p)
+            .fold(0.0, f64::max);
+        10.0 * (spur / carrier).log10()
+    }
+
+    // FFTのビンに合わない周波数も含め、IFの周辺と低い周波数、高い周波数で、キャリア以外の最大のスプリアスが-90dBc以下であること。
+    #[test]
+    fn spurs_below_limit() {
+        for freq in [700.0, 11_979.3, 12_000.0, 12_020.0, 12_345.678, 21_000.5] {
+            let mut nco = create_nco();
+            let mut sin = Vec::with_capacity(FFT_SIZE);
+            let mut cos = Vec::with_capacity(FFT_SIZE);
+            while sin.len() < FFT_SIZE {
+                let (s, c) = nco(freq, 1.0);
+                sin.extend_from_slice(&s);
+                cos.extend_from_slice(&c);
+            }
+            let (spur_sin, spur_cos) = (worst_spur(&sin), worst_spur(&cos));
+            assert!(spur_sin < LIMIT_DBC && spur_cos < LIMIT_DBC, "{}Hz  sin {:.1}dBc  cos {:.1}dBc", freq, spur_sin, spur_cos);
+        }
+    }
+
+    // 周波数を変えながら出力しても、サンプル間の変化が最大の周波数の正弦波の変化(2sin(πf/fs))を超えないこと(位相が連続していること)。
+    #[test]
+    fn phase_continuous_when_frequency_changes() {
+        let mut nco = create_nco();
+        let mut last: Option<f32> = None;
+        let mut max_jump: f32 = 0.0;
+        for n in 0..200 {
+            let freq = if n % 20 < 10 { 11_000.0 } else { 13_000.0 };
+            let (s, _) = nco(freq, 1.0);
+            for x in s {
+                if let Some(l) = last {
+                    max_jump = max_jump.max((x - l).abs());
+                }
+                last = Some(x);
+            }
+        }
+        let limit = (2.0 * (PI * 13_000.0 / SAMPLING_FREQ as f64).sin()) as f32 * 1.001;
+        assert!(max_jump <= limit, "max step {:.4} (limit {:.4})", max_jump, limit);
+    }
+}
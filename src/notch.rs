@@ -0,0 +1,213 @@
+// システムで使う定数の読み込み
+use crate::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+use core::f32::consts::PI;
+
+
+// 自動ノッチ(ANF)関連の定数の定義
+const ANF_TAPS: usize = 64;     // 適応フィルタのタップ数
+const ANF_DELAY: usize = 48;    // 参照信号の遅延。音声の相関が小さくなる1[ms]程度にする。
+const ANF_MU: f32 = 0.002;      // NLMSのステップサイズ
+const ANF_LEAK: f32 = 0.99999;  // 係数の漏れ。無音時に係数が残り続けるのを防ぐ。
+
+/// LMS(NLMS)による自動ノッチフィルタ
+/// 遅延させた入力から現在の入力を予測し、予測できた成分(持続するキャリアやビート音)を取り除く。
+/// 音声は遅延後の相関が小さいので、予測誤差としてそのまま出力される。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::notch::create_anf;
+///
+/// let mut anf = create_anf();
+/// assert!(anf(&[0.0; CHUNK_SIZE]).iter().all(|&x| x == 0.0));
+/// ```
+pub fn create_anf() -> impl FnMut(&[f32]) -> [f32; CHUNK_SIZE] {
+
+    let mut weights: [f32; ANF_TAPS] = [0.0; ANF_TAPS];
+    let mut delay_line: [f32; ANF_TAPS + ANF_DELAY] = [0.0; ANF_TAPS + ANF_DELAY];
+    let mut power: f32 = 0.0;   // 参照信号の電力(タップ分の二乗和)
+
+    move |input: &[f32]| -> [f32; CHUNK_SIZE] {
+        let mut output: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+
+        for (idx, &x) in input.iter().enumerate() {
+            // 参照信号はANF_DELAYだけ遅れた入力
+            let reference = &delay_line[ANF_DELAY..];
+
+            // 予測値の計算
+            let mut y = 0.0;
+            for i in 0..ANF_TAPS {
+                y += weights[i] * reference[i];
+            }
+            let error = x - y;
+
+            // 係数の更新(正規化LMS)
+            let step = ANF_MU * error / (power + 1e-6);
+            for i in 0..ANF_TAPS {
+                weights[i] = weights[i] * ANF_LEAK + step * reference[i];
+            }
+
+            // 遅延線の更新と電力の差分更新
+            let oldest = delay_line[ANF_TAPS + ANF_DELAY - 1];
+            let newest = delay_line[ANF_DELAY - 1];
+            power += newest * newest - oldest * oldest;
+            if power < 0.0 { power = 0.0; }
+            delay_line.copy_within(0..ANF_TAPS + ANF_DELAY - 1, 1);
+            delay_line[0] = x;
+
+            output[idx] = error;
+        }
+
+        output
+    }
+}
+
+/// 指定した周波数と帯域幅の手動ノッチフィルタ(2次IIR)
+/// freqが0以下の時は、入力をそのまま出力する。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::notch::create_notch;
+///
+/// let input = [0.5; CHUNK_SIZE];
+/// let mut notch = create_notch(0.0, 0.0);
+/// assert_eq!(notch(&input), input);
+/// ```
+pub fn create_notch(freq: f32, width: f32) -> impl FnMut(&[f32]) -> [f32; CHUNK_SIZE] {
+
+    let enabled = freq > 0.0 && freq < SAMPLING_FREQ / 2.0;
+
+    #[cfg(debug_assertions)]
+    if enabled {
+        println!("NOTCH {} Hz, width {} Hz", freq, width);
+    } else {
+        println!("NOTCH OFF");
+    }
+
+    // RBJ Audio EQ Cookbookのノッチの係数
+    let w0 = 2.0 * PI * freq / SAMPLING_FREQ;
+    let q = freq / width.max(1.0);
+    let alpha = w0.sin() / (2.0 * q);
+    let a0 = 1.0 + alpha;
+    let b = [1.0 / a0, -2.0 * w0.cos() / a0, 1.0 / a0];
+    let a = [-2.0 * w0.cos() / a0, (1.0 - alpha) / a0];
+
+    create_biquad(b, a, enabled)
+}
+
+/// CW用のオーディオピークフィルタ(2次IIRのバンドパス、中心周波数での利得は0\[dB\])
+/// 受信したキャリアがpitchの周波数で出てくるので、その周辺だけを通す。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::notch::create_peak;
+///
+/// let mut peak = create_peak(700.0, 200.0);
+/// assert!(peak(&[0.0; CHUNK_SIZE]).iter().all(|&x| x == 0.0));
+/// ```
+pub fn create_peak(pitch: f32, width: f32) -> impl FnMut(&[f32]) -> [f32; CHUNK_SIZE] {
+
+    #[cfg(debug_assertions)]
+    println!("CW peak {} Hz, width {} Hz", pitch, width);
+
+    // RBJ Audio EQ Cookbookのバンドパスの係数
+    let w0 = 2.0 * PI * pitch / SAMPLING_FREQ;
+    let q = pitch / width.max(1.0);
+    let alpha = w0.sin() / (2.0 * q);
+    let a0 = 1.0 + alpha;
+    let b = [alpha / a0, 0.0, -alpha / a0];
+    let a = [-2.0 * w0.cos() / a0, (1.0 - alpha) / a0];
+
+    create_biquad(b, a, true)
+}
+
+// 2次IIRフィルタ(直接型I)。enabledがfalseの時は入力をそのまま出力する。
+fn create_biquad(b: [f32; 3], a: [f32; 2], enabled: bool) -> impl FnMut(&[f32]) -> [f32; CHUNK_SIZE] {
+    let mut x_state: [f32; 2] = [0.0; 2];
+    let mut y_state: [f32; 2] = [0.0; 2];
+
+    move |input: &[f32]| -> [f32; CHUNK_SIZE] {
+        let mut output: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+
+        if !enabled {
+            output[..input.len()].copy_from_slice(input);
+            return output;
+        }
+
+        for (idx, &x) in input.iter().enumerate() {
+            let y = b[0] * x + b[1] * x_state[0] + b[2] * x_state[1]
+                  - a[0] * y_state[0] - a[1] * y_state[1];
+            x_state = [x, x_state[0]];
+            y_state = [y, y_state[0]];
+            output[idx] = y;
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 正弦波のチャンク(nはチャンクの番号)
+    fn tone(freq: f32, amplitude: f32, n: usize) -> [f32; CHUNK_SIZE] {
+        std::array::from_fn(|i| amplitude * (2.0 * PI * freq * (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ).sin())
+    }
+
+    // 広帯域の雑音(線形合同法、-0.5～0.5)
+    fn noise(state: &mut u32) -> [f32; CHUNK_SIZE] {
+        std::array::from_fn(|_| {
+            *state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (*state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+        })
+    }
+
+    fn power_db(data: &[f32]) -> f32 {
+        10.0 * (data.iter().map(|x| x * x).sum::<f32>() / data.len() as f32).max(1e-20).log10()
+    }
+
+    // 収束させてから、最後の10チャンクの入力と出力の電力の比[dB]を測る。
+    fn gain_db(filter: &mut impl FnMut(&[f32]) -> [f32; CHUNK_SIZE], mut source: impl FnMut(usize) -> [f32; CHUNK_SIZE]) -> f32 {
+        let (mut input_power, mut output_power) = (0.0, 0.0);
+        for n in 0..100 {
+            let input = source(n);
+            let output = filter(&input);
+            if n >= 90 {
+                input_power += 10.0_f32.powf(power_db(&input) / 10.0);
+                output_power += 10.0_f32.powf(power_db(&output) / 10.0);
+            }
+        }
+        10.0 * (output_power / input_power).log10()
+    }
+
+    #[test]
+    fn anf_suppresses_steady_tone() {
+        let mut anf = create_anf();
+        let gain = gain_db(&mut anf, |n| tone(1000.0, 0.5, n));
+        assert!(gain < -30.0, "持続する音の減衰 {:.1}dB", gain);
+    }
+
+    #[test]
+    fn anf_passes_broadband_signal() {
+        let mut anf = create_anf();
+        let mut state = 1;
+        let gain = gain_db(&mut anf, |_| noise(&mut state));
+        assert!(gain.abs() < 3.0, "広帯域の信号の利得 {:.1}dB", gain);
+    }
+
+    #[test]
+    fn notch_suppresses_tone_and_passes_others() {
+        let mut notch = create_notch(1000.0, 50.0);
+        let gain = gain_db(&mut notch, |n| tone(1000.0, 0.5, n));
+        assert!(gain < -30.0, "ノッチの周波数の減衰 {:.1}dB", gain);
+
+        let mut notch = create_notch(1000.0, 50.0);
+        let gain = gain_db(&mut notch, |n| tone(3000.0, 0.5, n));
+        assert!(gain.abs() < 1.0, "ノッチから離れた周波数の利得 {:.1}dB", gain);
+
+        let mut notch = create_notch(1000.0, 50.0);
+        let mut state = 1;
+        let gain = gain_db(&mut notch, |_| noise(&mut state));
+        assert!(gain.abs() < 1.0, "広帯域の信号の利得 {:.1}dB", gain);
+    }
+}
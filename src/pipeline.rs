@@ -0,0 +1,254 @@
+// システムで使う定数の読み込み
+use crate::constants::{CHUNK_SIZE, IF_FREQ};
+use crate::firfilter::FilterType;
+use crate::tonedetect::ToneSquelch;
+
+
+/// 処理ブロックの間で共有する受信の状態
+/// 設定はコマンドでメインループが変更し、チャンクごとの値はブロックが書き込む。
+pub struct Context {
+    // 受信の設定
+    pub if_type: FilterType,
+    pub if_shift: f32,
+    pub if_width: f32,          // 0の時はフィルタの種類で決まる帯域幅
+    pub fm_mode: bool,
+    pub bfo_freq: f32,          // 0の時はBFOを出さない(CWを除く)
+    pub cw_pitch: f32,          // CW受信時のビート音の周波数
+    pub tone_display: bool,
+    pub tone_squelch: ToneSquelch,
+    pub smeter_offset: f32,     // RSSIの補正値[dB](IFのRSSI、パイプへの出力、スケルチのレベルに使う。)
+
+    // チャンクごとの値
+    pub carrier: f32,           // キャリアの周波数[Hz](AFCで補正した値)
+    pub rssi: f32,
+    pub squelch_open: bool,
+    pub tone_open: bool,
+    pub text: String,           // 画面と名前付きパイプに出力する文字列
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Context {
+            if_type: FilterType::AM11K,
+            if_shift: 0.0,
+            if_width: 0.0,
+            fm_mode: false,
+            bfo_freq: 0.0,
+            cw_pitch: 700.0,
+            tone_display: false,
+            tone_squelch: ToneSquelch::None,
+            smeter_offset: 0.0,
+            carrier: IF_FREQ,
+            rssi: 0.0,
+            squelch_open: true,
+            tone_open: true,
+            text: String::new(),
+        }
+    }
+}
+
+/// 信号処理のブロック
+/// 1チャンクずつ処理し、共有する状態はContextでやり取りする。
+pub trait DspBlock {
+    /// ブロックの名前(構成の指定と、ブロックの置き換えに使う。)
+    fn name(&self) -> &'static str;
+    fn process(&mut self, input: &[f32; CHUNK_SIZE], context: &mut Context) -> [f32; CHUNK_SIZE];
+    /// 内部の状態を初期化する。
+    fn reset(&mut self);
+    /// 処理による遅延\[サンプル\]
+    fn latency(&self) -> usize;
+    /// 設定の説明
+    fn describe(&self) -> String;
+}
+
+// クロージャを返す関数(create_filter等)をブロックにする。
+// 初期化はクロージャを作り直して行う。
+struct ClosureBlock<G, F> {
+    name: &'static str,
+    description: String,
+    latency: usize,
+    factory: G,
+    process: F,
+}
+
+impl<G, F> DspBlock for ClosureBlock<G, F>
+where
+    G: FnMut() -> F,
+    F: FnMut(&[f32; CHUNK_SIZE], &mut Context) -> [f32; CHUNK_SIZE],
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn process(&mut self, input: &[f32; CHUNK_SIZE], context: &mut Context) -> [f32; CHUNK_SIZE] {
+        (self.process)(input, context)
+    }
+
+    fn reset(&mut self) {
+        self.process = (self.factory)();
+    }
+
+    fn latency(&self) -> usize {
+        self.latency
+    }
+
+    fn describe(&self) -> String {
+        self.description.clone()
+    }
+}
+
+/// クロージャを作る関数(factory)から、名前、説明、遅延\[サンプル\]を持つブロックをつくる。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::pipeline::{closure_block, Context};
+///
+/// let mut gain = closure_block("GAIN", "2倍".to_string(), 0, || {
+///     |input: &[f32; CHUNK_SIZE], _: &mut Context| input.map(|x| x * 2.0)
+/// });
+/// let output = gain.process(&[0.25; CHUNK_SIZE], &mut Context::default());
+/// assert_eq!(output[0], 0.5);
+/// assert_eq!(gain.name(), "GAIN");
+/// ```
+pub fn closure_block<G, F>(name: &'static str, description: String, latency: usize, mut factory: G) -> Box<dyn DspBlock>
+where
+    G: FnMut() -> F + 'static,
+    F: FnMut(&[f32; CHUNK_SIZE], &mut Context) -> [f32; CHUNK_SIZE] + 'static,
+{
+    let process = factory();
+    Box::new(ClosureBlock { name, description, latency, factory, process })
+}
+
+/// ブロックを順につないだ処理
+pub struct Pipeline {
+    blocks: Vec<Box<dyn DspBlock>>,
+}
+
+impl Pipeline {
+    /// ブロックを並べた順に処理する。
+    ///
+    /// ```
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::pipeline::{closure_block, Context, Pipeline};
+    ///
+    /// let gain = |name| closure_block(name, String::new(), 8, || {
+    ///     |input: &[f32; CHUNK_SIZE], _: &mut Context| input.map(|x| x * 2.0)
+    /// });
+    /// let mut pipeline = Pipeline::new(vec![gain("A"), gain("B")]);
+    /// assert_eq!(pipeline.process(&[1.0; CHUNK_SIZE], &mut Context::default())[0], 4.0);
+    /// assert_eq!(pipeline.latency(), 16);
+    /// ```
+    pub fn new(blocks: Vec<Box<dyn DspBlock>>) -> Self {
+        Pipeline { blocks }
+    }
+
+    /// 入力のチャンクを全てのブロックに順に通す。
+    ///
+    /// ```
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::pipeline::{closure_block, Context, Pipeline};
+    ///
+    /// let add = closure_block("ADD", String::new(), 0, || {
+    ///     |input: &[f32; CHUNK_SIZE], _: &mut Context| input.map(|x| x + 1.0)
+    /// });
+    /// let double = closure_block("DOUBLE", String::new(), 0, || {
+    ///     |input: &[f32; CHUNK_SIZE], _: &mut Context| input.map(|x| x * 2.0)
+    /// });
+    /// let mut pipeline = Pipeline::new(vec![add, double]);
+    /// assert_eq!(pipeline.process(&[1.0; CHUNK_SIZE], &mut Context::default())[0], 4.0);   // (1 + 1) * 2
+    /// ```
+    pub fn process(&mut self, input: &[f32; CHUNK_SIZE], context: &mut Context) -> [f32; CHUNK_SIZE] {
+        self.blocks.iter_mut().fold(*input, |data, block| block.process(&data, context))
+    }
+
+    /// 同じ名前のブロックを置き換える。構成に含まれていないブロックは無視する。
+    ///
+    /// ```
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::pipeline::{closure_block, Context, Pipeline};
+    ///
+    /// let gain = |g: f32| closure_block("GAIN", format!("{}倍", g), 0, move || {
+    ///     move |input: &[f32; CHUNK_SIZE], _: &mut Context| input.map(|x| x * g)
+    /// });
+    /// let mut pipeline = Pipeline::new(vec![gain(2.0)]);
+    /// pipeline.replace(gain(3.0));
+    /// assert_eq!(pipeline.process(&[1.0; CHUNK_SIZE], &mut Context::default())[0], 3.0);
+    /// ```
+    pub fn replace(&mut self, block: Box<dyn DspBlock>) {
+        if let Some(b) = self.blocks.iter_mut().find(|b| b.name() == block.name()) {
+            *b = block;
+        }
+    }
+
+    /// 全てのブロックの内部の状態を初期化する。
+    ///
+    /// ```
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::pipeline::{closure_block, Context, Pipeline};
+    ///
+    /// // 処理したチャンクの数を出力するブロック
+    /// let counter = closure_block("COUNT", String::new(), 0, || {
+    ///     let mut n = 0.0;
+    ///     move |_: &[f32; CHUNK_SIZE], _: &mut Context| { n += 1.0; [n; CHUNK_SIZE] }
+    /// });
+    /// let mut pipeline = Pipeline::new(vec![counter]);
+    /// let mut context = Context::default();
+    /// pipeline.process(&[0.0; CHUNK_SIZE], &mut context);
+    /// assert_eq!(pipeline.process(&[0.0; CHUNK_SIZE], &mut context)[0], 2.0);
+    /// pipeline.reset();
+    /// assert_eq!(pipeline.process(&[0.0; CHUNK_SIZE], &mut context)[0], 1.0);
+    /// ```
+    pub fn reset(&mut self) {
+        self.blocks.iter_mut().for_each(|block| block.reset());
+    }
+
+    /// 全てのブロックの遅延の合計\[サンプル\]
+    ///
+    /// ```
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::pipeline::{closure_block, Context, Pipeline};
+    ///
+    /// let delay = |name, latency| closure_block(name, String::new(), latency, || {
+    ///     |input: &[f32; CHUNK_SIZE], _: &mut Context| *input
+    /// });
+    /// let pipeline = Pipeline::new(vec![delay("A", 8), delay("B", 4)]);
+    /// assert_eq!(pipeline.latency(), 12);
+    /// ```
+    pub fn latency(&self) -> usize {
+        self.blocks.iter().map(|block| block.latency()).sum()
+    }
+
+    /// 構成しているブロックの名前(処理する順)
+    ///
+    /// ```
+    /// use thsdr::blocks::{build_pipeline, DEFAULT_CHAIN};
+    /// use std::sync::mpsc::channel;
+    ///
+    /// let (frame_tx, _frame_rx) = channel();
+    /// let (file_tx, _file_rx) = channel();
+    /// let pipeline = build_pipeline(&DEFAULT_CHAIN, frame_tx, file_tx).unwrap();
+    /// assert_eq!(pipeline.names(), DEFAULT_CHAIN);
+    /// ```
+    pub fn names(&self) -> Vec<&'static str> {
+        self.blocks.iter().map(|block| block.name()).collect()
+    }
+
+    /// 構成を1行に1ブロックずつ表示する文字列にする。
+    ///
+    /// ```
+    /// use thsdr::blocks::{build_pipeline, DEFAULT_CHAIN};
+    /// use std::sync::mpsc::channel;
+    ///
+    /// let (frame_tx, _frame_rx) = channel();
+    /// let (file_tx, _file_rx) = channel();
+    /// let pipeline = build_pipeline(&DEFAULT_CHAIN, frame_tx, file_tx).unwrap();
+    /// assert_eq!(pipeline.describe().lines().count(), DEFAULT_CHAIN.len() + 1);
+    /// ```
+    pub fn describe(&self) -> String {
+        let mut text: String = self.blocks.iter()
+            .map(|block| format!("{:6} {:5}  {}\n", block.name(), block.latency(), block.describe()))
+            .collect();
+        text.push_str(&format!("合計の遅延 {}サンプル\n", self.latency()));
+        text
+    }
+}
@@ -0,0 +1,96 @@
+// 名前付きパイプへの出力
+use crate::constants::CHUNK_SIZE;
+
+// プロセス間通信用のクレート
+use interprocess::local_socket::LocalSocketStream;
+use std::io::Write;
+
+/// RSSI データを名前付きパイプに出力する関数
+///
+/// ```
+/// use thsdr::pipes::rssi_output;
+///
+/// // パスが空の時は何もしない。
+/// assert!(rssi_output(0.5, ""));
+/// ```
+pub fn rssi_output( rssi: f32, rssi_path: &str ) -> bool {
+
+    let path = rssi_path;
+
+    if !path.is_empty() {
+        let rssi_pipe = LocalSocketStream::connect(path);
+        // RSSIを名前付きパイプに出力する。
+        if let Ok(mut rssi_pipe) = rssi_pipe {
+            let bytes = rssi.to_ne_bytes();  // RSSIをバイト列に変換する。
+            if let Err(e) = rssi_pipe.write_all(&bytes) {
+                println!("RSSI用名前付きパイプへの書き込みに失敗しました: {}", e);
+                return false;
+            }
+        } else {
+            println!("RSSI用名前付きパイプに出力できません。");
+            return false;
+        }
+    }
+    true
+}
+
+/// 受信データを名前付きパイプに出力する関数
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::pipes::receive_data_output;
+///
+/// // パスが空の時は何もしない。パイプに接続できない時はfalseを返す。
+/// assert!(receive_data_output(&[0.0; CHUNK_SIZE], ""));
+/// assert!(!receive_data_output(&[0.0; CHUNK_SIZE], "/tmp/thsdr_doctest_no_pipe"));
+/// ```
+pub fn receive_data_output( data: &[f32; CHUNK_SIZE], receive_data_path: &str ) -> bool {
+
+    let path = receive_data_path;
+
+    if !path.is_empty() {
+        let receive_data_pipe = LocalSocketStream::connect(path);
+        // 受信データを名前付きパイプに出力する。
+        if let Ok(mut receive_data_pipe) = receive_data_pipe {
+            // 配列をバイト列Vec<u8>にする。
+            let bytes = data.iter().flat_map(|&value| value.to_ne_bytes()).collect::<Vec<u8>>();
+            if let Err(e) = receive_data_pipe.write_all(&bytes) {
+                println!("受信データ用名前付きパイプへの書き込みに失敗しました: {}", e);
+                return false;
+            }
+        } else {
+            println!("受信データ用名前付きパイプに出力できません。");
+            return false;
+        }
+    }
+    true
+}
+
+/// デコードした文字列を名前付きパイプに出力する関数
+///
+/// ```
+/// use thsdr::pipes::text_output;
+///
+/// // パスが空の時は何もしない。パイプに接続できない時はfalseを返す。
+/// assert!(text_output("CQ", ""));
+/// assert!(!text_output("CQ", "/tmp/thsdr_doctest_no_pipe"));
+/// ```
+pub fn text_output( text: &str, text_path: &str ) -> bool {
+
+    let path = text_path;
+
+    if !path.is_empty() {
+        let text_pipe = LocalSocketStream::connect(path);
+        // 文字列をUTF-8のバイト列で名前付きパイプに出力する。
+        if let Ok(mut text_pipe) = text_pipe {
+            if let Err(e) = text_pipe.write_all(text.as_bytes()) {
+                println!("文字列用名前付きパイプへの書き込みに失敗しました: {}", e);
+                return false;
+            }
+        } else {
+            println!("文字列用名前付きパイプに出力できません。");
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,338 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use std::collections::HashMap;
+use core::f32::consts::PI;
+
+
+// POCSAG関連の定数の定義
+const BAUD_RATES: [f32; 3] = [512.0, 1200.0, 2400.0];
+const PLL_GAIN: f32 = 0.1;              // ビット同期の引き込みの強さ
+const PEAK_DECAY: f32 = 0.02;           // 判定のしきい値を求める、最大値と最小値の追従の速さ(1ビットあたり)
+const SYNC_CODEWORD: u32 = 0x7CD2_15D8;
+const IDLE_CODEWORD: u32 = 0x7A89_C197;
+const SYNC_ERRORS: u32 = 1;             // 同期符号語を探す時に許す誤りビット数
+const RESYNC_ERRORS: u32 = 3;           // 同期中に、バッチの後の同期符号語で許す誤りビット数
+const BATCH_CODEWORDS: usize = 16;      // 1バッチは8フレーム(各2符号語)
+const BCH_POLY: u32 = 0x769;            // BCH(31,21)の生成多項式 x^10+x^9+x^8+x^6+x^5+x^3+1
+const NUMERIC: [char; 16] = ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '*', 'U', ' ', '-', ')', '('];
+
+// 受信中のメッセージ
+struct Message {
+    address: u32,
+    function: u32,
+    bits: Vec<bool>,            // メッセージの符号語のデータ(送信順)
+    errors: u32,                // 訂正できなかった符号語の数
+}
+
+// POCSAGデコーダ
+// FM復調した音声から、512、1200、2400bpsの信号を同時に受信する。
+// BCH(31,21)で2ビットまでの誤りを訂正し、アドレス、ファンクションとメッセージ(数字または英数字)を表示する。
+pub fn create_pocsag_decoder() -> impl FnMut(&[f32]) -> String {
+    let syndromes = syndrome_table();
+    let mut receivers: Vec<_> = BAUD_RATES.iter().map(|&baud| create_receiver(baud, syndromes.clone())).collect();
+
+    move |input: &[f32]| -> String {
+        receivers.iter_mut().map(|receiver| receiver(input)).collect()
+    }
+}
+
+// 1つの速度の受信機
+fn create_receiver(baud: f32, syndromes: HashMap<u32, u32>) -> impl FnMut(&[f32]) -> String {
+    let alpha = 1.0 - (-2.0 * PI * baud / SAMPLING_FREQ).exp();    // ビットレート程度のローパスフィルタ
+    let mut smooth: f32 = 0.0;
+
+    // ビット同期(DPLL)と積分による判定
+    let mut pll_phase: f32 = 0.0;
+    let mut last_level = false;
+    let mut sum: f32 = 0.0;
+    let mut count = 0;
+    let mut high: f32 = 0.0;
+    let mut low: f32 = 0.0;
+
+    // 符号語の同期
+    let mut register: u32 = 0;
+    let mut synced = false;
+    let mut inverted = false;
+    let mut bit_count = 0;
+    let mut index = 0;              // バッチ内の符号語の番号
+    let mut message: Option<Message> = None;
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+
+        for &x in input {
+            smooth += (x - smooth) * alpha;
+
+            // 変化点がビットの境界(位相0)になるように引き込む。
+            let level = smooth > (high + low) / 2.0;
+            if level != last_level {
+                let error = if pll_phase > 0.5 { pll_phase - 1.0 } else { pll_phase };
+                pll_phase -= error * PLL_GAIN;
+                last_level = level;
+            }
+            sum += smooth;
+            count += 1;
+            pll_phase += baud / SAMPLING_FREQ;
+            if pll_phase < 1.0 {
+                continue;
+            }
+            pll_phase -= 1.0;
+
+            // 1ビットの平均を、最大値と最小値の中央で判定する。
+            let value = sum / count.max(1) as f32;
+            sum = 0.0;
+            count = 0;
+            high = if value > high { value } else { high - (high - low) * PEAK_DECAY };
+            low = if value < low { value } else { low + (high - low) * PEAK_DECAY };
+            let bit = value > (high + low) / 2.0;
+
+            register = (register << 1) | bit as u32;
+            if !synced {
+                // 同期符号語を探す。極性は両方を試す。
+                if (register ^ SYNC_CODEWORD).count_ones() <= SYNC_ERRORS {
+                    (synced, inverted) = (true, false);
+                } else if (!register ^ SYNC_CODEWORD).count_ones() <= SYNC_ERRORS {
+                    (synced, inverted) = (true, true);
+                }
+                bit_count = 0;
+                index = 0;
+                continue;
+            }
+
+            bit_count += 1;
+            if bit_count < 32 {
+                continue;
+            }
+            bit_count = 0;
+            let codeword = if inverted { !register } else { register };
+
+            // バッチの後には同期符号語が続く。無ければ同期を外す。
+            if index == BATCH_CODEWORDS {
+                if (codeword ^ SYNC_CODEWORD).count_ones() <= RESYNC_ERRORS {
+                    index = 0;
+                } else {
+                    synced = false;
+                    text.push_str(&flush(&mut message, baud));
+                }
+                continue;
+            }
+            let frame = (index / 2) as u32;
+            index += 1;
+
+            match correct(codeword, &syndromes) {
+                None => {
+                    // 訂正できない符号語は、メッセージの途中ならデータを0として数を記録する。
+                    if let Some(m) = message.as_mut() {
+                        m.bits.extend([false; 20]);
+                        m.errors += 1;
+                    }
+                },
+                Some(IDLE_CODEWORD) => text.push_str(&flush(&mut message, baud)),
+                Some(codeword) if codeword & 0x8000_0000 == 0 => {
+                    // アドレス符号語(上位18ビットとフレーム番号で21ビットのアドレスになる。)
+                    text.push_str(&flush(&mut message, baud));
+                    message = Some(Message {
+                        address: (((codeword >> 13) & 0x3FFFF) << 3) | frame,
+                        function: (codeword >> 11) & 3,
+                        bits: Vec::new(),
+                        errors: 0,
+                    });
+                },
+                Some(codeword) => {
+                    if let Some(m) = message.as_mut() {
+                        m.bits.extend((0..20).map(|i| (codeword >> (30 - i)) & 1 == 1));
+                    }
+                },
+            }
+        }
+
+        text
+    }
+}
+
+// 受信中のメッセージを表示する文字列にする。
+// ファンクションが0なら数字、それ以外は英数字として表示し、データが無ければトーンのみの呼び出しとする。
+fn flush(message: &mut Option<Message>, baud: f32) -> String {
+    let m = match message.take() {
+        Some(m) => m,
+        None => return String::new(),
+    };
+    let body = if m.bits.is_empty() {
+        "Tone".to_string()
+    } else if m.function == 0 {
+        // 4ビットずつ、下位ビットから送られる。
+        let digits: String = m.bits.chunks_exact(4)
+            .map(|c| NUMERIC[c.iter().rev().fold(0, |n, &b| (n << 1) | b as usize)])
+            .collect();
+        format!("Numeric: {}", digits.trim_end())
+    } else {
+        // 7ビットのASCIIが、下位ビットから送られる。
+        let chars: String = m.bits.chunks_exact(7)
+            .map(|c| c.iter().rev().fold(0u8, |n, &b| (n << 1) | b as u8))
+            .filter(|&c| c != 0x00 && c != 0x03 && c != 0x04)
+            .map(|c| if c.is_ascii_graphic() || c == b' ' { c as char } else { '.' })
+            .collect();
+        format!("Alpha: {}", chars.trim_end())
+    };
+    let errors = if m.errors > 0 { format!("  Errors: {}", m.errors) } else { String::new() };
+    format!("POCSAG{}: Address: {:7}  Function: {}  {}{}\n", baud, m.address, m.function, body, errors)
+}
+
+// 符号語の誤りを訂正する。訂正できなければNoneを返す。
+// 上位31ビットがBCH(31,21)、最下位ビットが全体の偶数パリティになる。
+fn correct(codeword: u32, syndromes: &HashMap<u32, u32>) -> Option<u32> {
+    let syndrome = bch_remainder(codeword >> 1);
+    let (corrected, flips) = if syndrome == 0 {
+        (codeword, 0)
+    } else {
+        let pattern = *syndromes.get(&syndrome)?;
+        (codeword ^ (pattern << 1), pattern.count_ones())
+    };
+    // BCHで2ビット訂正した上にパリティも合わなければ、3ビット以上の誤りとみなす。
+    if corrected.count_ones() % 2 != 0 {
+        if flips == 2 {
+            return None;
+        }
+        return Some(corrected ^ 1);
+    }
+    Some(corrected)
+}
+
+// 31ビットの多項式を生成多項式で割った余り
+fn bch_remainder(mut value: u32) -> u32 {
+    for i in (10..31).rev() {
+        if value & (1 << i) != 0 {
+            value ^= BCH_POLY << (i - 10);
+        }
+    }
+    value
+}
+
+// 1ビットと2ビットの誤りについて、シンドロームから誤りのパターンを引く表
+fn syndrome_table() -> HashMap<u32, u32> {
+    let mut table = HashMap::new();
+    for i in 0..31 {
+        table.insert(bch_remainder(1 << i), 1 << i);
+        for j in i + 1..31 {
+            let pattern = (1 << i) | (1 << j);
+            table.insert(bch_remainder(pattern), pattern);
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 21ビットのデータにBCH(31,21)のパリティと偶数パリティを付けた符号語
+    fn encode(data: u32) -> u32 {
+        let bch = (data << 10) | bch_remainder(data << 10);
+        (bch << 1) | (bch.count_ones() % 2)
+    }
+
+    fn address_codeword(address: u32, function: u32) -> u32 {
+        encode(((address >> 3) << 2) | function)
+    }
+
+    // メッセージのビット列(送信順)を20ビットずつの符号語にする。余りは0で埋める。
+    fn message_codewords(bits: &[bool]) -> Vec<u32> {
+        bits.chunks(20).map(|chunk| {
+            let data = (0..20).fold(0, |n, i| (n << 1) | *chunk.get(i).unwrap_or(&false) as u32);
+            encode((1 << 20) | data)
+        }).collect()
+    }
+
+    // 文字を下位ビットからnビットずつ並べる。
+    fn lsb_first(values: impl Iterator<Item = u32>, n: usize) -> Vec<bool> {
+        values.flat_map(|v| (0..n).map(move |i| (v >> i) & 1 == 1)).collect()
+    }
+
+    fn numeric_bits(digits: &str) -> Vec<bool> {
+        lsb_first(digits.chars().map(|c| NUMERIC.iter().position(|&d| d == c).unwrap() as u32), 4)
+    }
+
+    fn alpha_bits(text: &str) -> Vec<bool> {
+        lsb_first(text.bytes().map(|c| c as u32), 7)
+    }
+
+    #[test]
+    fn syndromes_are_unique() {
+        let table = syndrome_table();
+        assert_eq!(table.len(), 31 + 31 * 30 / 2);      // 1ビットと2ビットのパターンがすべて異なるシンドロームになる。
+        assert!(!table.contains_key(&0));
+    }
+
+    #[test]
+    fn corrects_up_to_two_errors() {
+        let syndromes = syndrome_table();
+        let codeword = address_codeword(1234560, 3);
+        assert_eq!(correct(codeword, &syndromes), Some(codeword));
+        for i in 0..32 {
+            assert_eq!(correct(codeword ^ (1 << i), &syndromes), Some(codeword));
+            for j in i + 1..32 {
+                assert_eq!(correct(codeword ^ (1 << i) ^ (1 << j), &syndromes), Some(codeword));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_three_errors() {
+        // BCHの部分の3ビットの誤りは全体のパリティが合わないので、2ビットの訂正をしても誤りと判定する。
+        let syndromes = syndrome_table();
+        let codeword = encode(0x12345);
+        for i in 1..32 {
+            for j in i + 1..32 {
+                for k in j + 1..32 {
+                    assert_eq!(correct(codeword ^ (1 << i) ^ (1 << j) ^ (1 << k), &syndromes), None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flushes_numeric_and_alpha_messages() {
+        let mut numeric = Some(Message { address: 2000002, function: 0, bits: numeric_bits("0123 456 U-()*    "), errors: 0 });
+        assert_eq!(flush(&mut numeric, 1200.0), "POCSAG1200: Address: 2000002  Function: 0  Numeric: 0123 456 U-()*\n");
+        assert!(numeric.is_none());
+
+        let mut alpha = Some(Message { address: 1234560, function: 3, bits: alpha_bits("Hello\n\x04"), errors: 1 });
+        assert_eq!(flush(&mut alpha, 512.0), "POCSAG512: Address: 1234560  Function: 3  Alpha: Hello.  Errors: 1\n");
+
+        let mut tone = Some(Message { address: 8, function: 1, bits: Vec::new(), errors: 0 });
+        assert_eq!(flush(&mut tone, 2400.0), "POCSAG2400: Address:       8  Function: 1  Tone\n");
+        assert_eq!(flush(&mut None, 2400.0), "");
+    }
+
+    // プリアンブル、同期符号語、2つのメッセージの入ったバッチ、アイドルのバッチを1200bpsのNRZの音声にする。
+    fn batch_signal(inverted: bool) -> Vec<f32> {
+        let mut codewords = vec![IDLE_CODEWORD; 2 * BATCH_CODEWORDS];
+        codewords[0] = address_codeword(1234560, 3);                          // フレーム0
+        codewords[1..3].copy_from_slice(&message_codewords(&alpha_bits("Hello")));
+        codewords[4] = address_codeword(2000002, 0);                          // フレーム2、数字の余りは空白で埋める。
+        codewords[5..7].copy_from_slice(&message_codewords(&numeric_bits("0123 456  ")));
+        codewords[5] ^= 0x0010_0200;                                          // 2ビットの誤り
+        codewords.insert(BATCH_CODEWORDS, SYNC_CODEWORD);
+        codewords.insert(0, SYNC_CODEWORD);
+
+        let mut bits: Vec<bool> = (0..576).map(|i| i % 2 == 0).collect();
+        bits.extend(codewords.iter().flat_map(|&c| (0..32).rev().map(move |i| (c >> i) & 1 == 1)));
+        bits.extend([true; 64]);
+
+        let samples_per_bit = (SAMPLING_FREQ / 1200.0) as usize;
+        let mut signal = vec![0.0; 4800];
+        signal.extend(bits.iter().flat_map(|&b| std::iter::repeat_n(if b != inverted { 0.5 } else { -0.5 }, samples_per_bit)));
+        signal.extend(vec![0.0; 4800]);
+        signal
+    }
+
+    #[test]
+    fn decodes_1200_baud_batch_in_both_polarities() {
+        for inverted in [false, true] {
+            let mut decoder = create_pocsag_decoder();
+            let text: String = batch_signal(inverted).chunks(1024).map(&mut decoder).collect();
+            assert_eq!(text, "POCSAG1200: Address: 1234560  Function: 3  Alpha: Hello\n\
+                              POCSAG1200: Address: 2000002  Function: 0  Numeric: 0123 456\n", "inverted: {}", inverted);
+        }
+    }
+}
@@ -0,0 +1,383 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use crate::fft::power_spectrum;
+use crate::firfilter::design_lowpass;
+use core::f32::consts::PI;
+
+
+// PSK関連の定数の定義
+const DECIMATION: usize = 6;                // 48[kHz]から8[kHz]に間引く。
+const RATE: f32 = SAMPLING_FREQ / DECIMATION as f32;
+const CHANNEL_DECIMATION: usize = 16;       // 各チャンネルは8[kHz]から500[Hz]に間引く。
+const CHANNEL_RATE: f32 = RATE / CHANNEL_DECIMATION as f32;
+const FFT_SIZE: usize = 1024;               // 信号を探すFFTのサイズ(7.8[Hz]/ビン)
+const SEARCH_INTERVAL: usize = 4000;        // 信号を探す間隔(0.5秒)
+const MIN_FREQ: f32 = 200.0;                // 信号を探す範囲
+const MAX_FREQ: f32 = 3000.0;
+const DETECT_RATIO: f32 = 10.0;             // ノイズより10[dB]以上強いピークを信号とみなす。
+const MAX_CHANNELS: usize = 8;
+const LOST_COUNT: u32 = 6;                  // 信号が無くなってから、チャンネルを削除するまでの探索回数
+const LINE_LENGTH: usize = 60;              // 1行にまとめて出力する最大の文字数
+const FLUSH_SAMPLES: usize = 24000;         // 文字が途切れてから出力するまでの時間(3秒)
+const LOOP_ALPHA: f32 = 0.1;                // コスタスループの位相のゲイン
+const LOOP_BETA: f32 = 0.005;               // コスタスループの周波数のゲイン(1シンボルあたりの位相に換算)
+const FLL_GAIN: f32 = 0.05;                 // 周波数の引き込みのゲイン
+
+// Varicodeの表(ASCIIの0～127)
+const VARICODE: [&str; 128] = [
+    "1010101011", "1011011011", "1011101101", "1101110111", "1011101011", "1101011111", "1011101111", "1011111101",
+    "1011111111", "11101111", "11101", "1101101111", "1011011101", "11111", "1101110101", "1110101011",
+    "1011110111", "1011110101", "1110101101", "1110101111", "1101011011", "1101101011", "1101101101", "1101010111",
+    "1101111011", "1101111101", "1110110111", "1101010101", "1101011101", "1110111011", "1011111011", "1101111111",
+    "1", "111111111", "101011111", "111110101", "111011011", "1011010101", "1010111011", "101111111",
+    "11111011", "11110111", "101101111", "111011111", "1110101", "110101", "1010111", "110101111",
+    "10110111", "10111101", "11101101", "11111111", "101110111", "101011011", "101101011", "110101101",
+    "110101011", "110110111", "11110101", "110111101", "111101101", "1010101", "111010111", "1010101111",
+    "1010111101", "1111101", "11101011", "10101101", "10110101", "1110111", "11011011", "11111101",
+    "101010101", "1111111", "111111101", "101111101", "11010111", "10111011", "11011101", "10101011",
+    "11010101", "111011101", "10101111", "1101111", "1101101", "101010111", "110110101", "101011101",
+    "101110101", "101111011", "1010101101", "111110111", "111101111", "111111011", "1010111111", "101101101",
+    "1011011111", "1011", "1011111", "101111", "101101", "11", "111101", "1011011",
+    "101011", "1101", "111101011", "10111111", "11011", "111011", "1111", "111",
+    "111111", "110111111", "10101", "10111", "101", "110111", "1111011", "1101011",
+    "11011111", "1011101", "111010101", "1010110111", "110111011", "1010110101", "1011010111", "1110110101",
+];
+
+// 1つの信号を復調するチャンネル
+struct PskChannel {
+    freq: f32,                  // 信号の周波数[Hz](コスタスループで追従する)
+    phase: f64,                 // ミキサの位相
+    boxcar: (f32, f32, usize),  // 500[Hz]に間引くための積算
+    filter: Vec<f32>,           // シンボルに合わせたローパスフィルタ
+    history: Vec<(f32, f32)>,   // ローパスフィルタの入力
+    samples: Vec<(f32, f32)>,   // フィルタの出力(シンボル同期用に2シンボル分)
+    sps: f32,                   // 1シンボルのサンプル数(500[Hz]で)
+    strobe: f32,                // 次のシンボルまでのサンプル数
+    carrier: f32,               // コスタスループの位相
+    last_symbol: (f32, f32),
+    last_raw: (f32, f32),       // 位相補正前の前のシンボル
+    code: String,               // 受信中のVaricode
+    zeros: u32,
+    line: String,               // 出力待ちの文字
+    idle: usize,                // 最後に文字を受信してからのサンプル数
+    lost: u32,                  // 信号が見つからなかった探索の回数
+    level: f32,                 // シンボルの電力の平均
+    peak: f32,                  // シンボルの電力の最大値(ゆっくり下げる)
+}
+
+impl PskChannel {
+    fn new(freq: f32, baud: f32) -> Self {
+        let sps = CHANNEL_RATE / baud;
+        let taps = (2.0 * sps) as usize | 1;
+        // design_lowpassは48[kHz]を基準にしているので、500[Hz]での遮断周波数に換算する。
+        let filter = design_lowpass(baud * SAMPLING_FREQ / CHANNEL_RATE, taps);
+        let gain: f32 = filter.iter().sum();
+        PskChannel {
+            freq,
+            phase: 0.0,
+            boxcar: (0.0, 0.0, 0),
+            filter: filter.iter().map(|c| c / gain).collect(),
+            history: vec![(0.0, 0.0); taps],
+            samples: vec![(0.0, 0.0); 2 * sps as usize + 1],
+            sps,
+            strobe: sps,
+            carrier: 0.0,
+            last_symbol: (0.0, 0.0),
+            last_raw: (0.0, 0.0),
+            code: String::new(),
+            zeros: 0,
+            line: String::new(),
+            idle: 0,
+            lost: 0,
+            level: 0.0,
+            peak: 0.0,
+        }
+    }
+
+    // 8[kHz]のサンプルを1つ処理する。文字を受信したら返す。
+    fn process(&mut self, x: f32) -> Option<char> {
+        self.idle += 1;
+
+        // 複素ミキサと500[Hz]への間引き
+        let (sin, cos) = self.phase.sin_cos();
+        self.phase = (self.phase + 2.0 * std::f64::consts::PI * self.freq as f64 / RATE as f64) % (2.0 * std::f64::consts::PI);
+        self.boxcar.0 += x * cos as f32;
+        self.boxcar.1 -= x * sin as f32;
+        self.boxcar.2 += 1;
+        if self.boxcar.2 < CHANNEL_DECIMATION { return None; }
+        let z = (self.boxcar.0, self.boxcar.1);
+        self.boxcar = (0.0, 0.0, 0);
+
+        // ローパスフィルタ
+        self.history.rotate_right(1);
+        self.history[0] = z;
+        let y = self.history.iter().zip(self.filter.iter())
+            .fold((0.0, 0.0), |acc, (h, c)| (acc.0 + h.0 * c, acc.1 + h.1 * c));
+        self.samples.rotate_right(1);
+        self.samples[0] = y;
+
+        self.strobe -= 1.0;
+        if self.strobe > 0.0 { return None; }
+
+        // シンボル同期(Gardner)
+        let half = (self.sps / 2.0).round() as usize;
+        let current = self.samples[0];
+        let middle = self.samples[half];
+        let previous = self.samples[2 * half];
+        let power = current.0 * current.0 + current.1 * current.1 + 1e-12;
+        self.level = self.level * 0.9 + power * 0.1;
+        self.peak = (self.peak * 0.995).max(self.level);
+        let timing_error = ((previous.0 - current.0) * middle.0 + (previous.1 - current.1) * middle.1) / power;
+        self.strobe += self.sps + (timing_error * 0.5).clamp(-1.0, 1.0);
+
+        // 周波数の引き込み(FLL)  前のシンボルとの位相差を2乗してBPSKの変調を取り除く。
+        let (dr, di) = (current.0 * self.last_raw.0 + current.1 * self.last_raw.1,
+                        current.1 * self.last_raw.0 - current.0 * self.last_raw.1);
+        let rotation = (2.0 * dr * di).atan2(dr * dr - di * di) / 2.0;
+        self.last_raw = current;
+        let baud = CHANNEL_RATE / self.sps;
+        self.freq += FLL_GAIN * rotation * baud / (2.0 * PI);
+
+        // コスタスループ  BPSKなので、判定した符号を使って位相誤差を求める。
+        let (s, c) = self.carrier.sin_cos();
+        let symbol = (current.0 * c + current.1 * s, current.1 * c - current.0 * s);
+        let error = symbol.0.signum() * symbol.1 / power.sqrt();
+        self.carrier += LOOP_ALPHA * error;
+        self.freq += LOOP_BETA * error * baud / (2.0 * PI);
+
+        // 差動復号 位相が反転していなければ1
+        let bit = symbol.0 * self.last_symbol.0 + symbol.1 * self.last_symbol.1 > 0.0;
+        self.last_symbol = symbol;
+        self.varicode(bit)
+    }
+
+    // Varicodeの復号 符号は"00"で区切られる。
+    fn varicode(&mut self, bit: bool) -> Option<char> {
+        if bit {
+            if self.zeros == 1 {
+                self.code.push('0');    // 符号の中の0
+            }
+            self.zeros = 0;
+            self.code.push('1');
+            if self.code.len() > 12 { self.code.clear(); }
+            return None;
+        }
+        self.zeros += 1;
+        if self.zeros != 2 || self.code.is_empty() { return None; }
+
+        let code = std::mem::take(&mut self.code);
+        let c = VARICODE.iter().position(|v| *v == code)? as u8 as char;
+        self.idle = 0;
+        Some(c)
+    }
+
+    // 出力する行ができていれば返す。
+    fn take_line(&mut self, force: bool) -> Option<String> {
+        let text = self.line.trim();
+        if text.is_empty() {
+            self.line.clear();
+            return None;
+        }
+        if force || self.line.len() >= LINE_LENGTH || self.idle > FLUSH_SAMPLES {
+            let result = format!("[{:6.1} Hz] {}\n", self.freq, text);
+            self.line.clear();
+            return Some(result);
+        }
+        None
+    }
+}
+
+// PSK31/PSK63デコーダ
+// 音声の帯域全体からPSKの信号を探し、信号ごとにチャンネルを割り当てて復調する。
+// 出力は、信号の周波数と受信した文字の行になる。
+pub fn create_psk_decoder(baud: f32) -> impl FnMut(&[f32]) -> String {
+    let decimation_filter = design_lowpass(3500.0, 63);
+    let mut history: Vec<f32> = vec![0.0; decimation_filter.len()];
+    let mut phase = 0;
+    let mut spectrum_buffer: Vec<f32> = Vec::with_capacity(FFT_SIZE);
+    let mut average: Vec<f32> = vec![0.0; FFT_SIZE / 2];
+    let mut search_count = 0;
+    let mut channels: Vec<PskChannel> = Vec::new();
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+
+        for &x in input {
+            // 8[kHz]に間引く。
+            history.rotate_right(1);
+            history[0] = x;
+            phase += 1;
+            if phase < DECIMATION { continue; }
+            phase = 0;
+            let y: f32 = history.iter().zip(decimation_filter.iter()).map(|(a, b)| a * b).sum();
+
+            for channel in channels.iter_mut() {
+                // 信号が見えなくなったチャンネルや、急に弱くなったチャンネルの文字はノイズなので捨てる。
+                if let Some(c) = channel.process(y).filter(|_| channel.lost == 0 && channel.level > channel.peak * 0.1) {
+                    match c {
+                        '\n' | '\r' => channel.line.push('\n'),
+                        c if !c.is_control() => channel.line.push(c),
+                        _ => {},
+                    }
+                }
+                let force = channel.line.ends_with('\n');
+                if let Some(line) = channel.take_line(force) {
+                    text.push_str(&line);
+                }
+            }
+
+            // スペクトルの平均
+            spectrum_buffer.push(y);
+            if spectrum_buffer.len() == FFT_SIZE {
+                let power = power_spectrum(&spectrum_buffer);
+                for (a, p) in average.iter_mut().zip(power.iter()) {
+                    *a = *a * 0.7 + p * 0.3;
+                }
+                spectrum_buffer.drain(..FFT_SIZE / 2);
+            }
+
+            search_count += 1;
+            if search_count >= SEARCH_INTERVAL {
+                search_count = 0;
+                search_signals(&average, baud, &mut channels, &mut text);
+            }
+        }
+
+        text
+    }
+}
+
+// 平均したスペクトルから信号を探し、チャンネルを追加・削除する。
+fn search_signals(average: &[f32], baud: f32, channels: &mut Vec<PskChannel>, text: &mut String) {
+    let bin_hz = RATE / FFT_SIZE as f32;
+    let width = (baud / bin_hz).ceil() as usize;   // 信号の幅(片側)のビン数
+    let low = (MIN_FREQ / bin_hz) as usize;
+    let high = (MAX_FREQ / bin_hz) as usize;
+
+    // NaNや無限大になったビンは0として扱い、信号とみなさないようにする。
+    let average: Vec<f32> = average.iter().map(|&p| if p.is_finite() { p } else { 0.0 }).collect();
+
+    // 信号の幅で平滑化したスペクトル
+    let smooth: Vec<f32> = (0..average.len()).map(|i| {
+        let a = i.saturating_sub(width);
+        let b = (i + width + 1).min(average.len());
+        average[a..b].iter().sum::<f32>()
+    }).collect();
+    let mut sorted: Vec<f32> = smooth[low..high].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let noise = sorted[sorted.len() / 2];
+    let strongest = sorted[sorted.len() - 1];
+    // ノイズが極端に少ない時に、強い信号のサイドローブを拾わないようにする。
+    let threshold = (noise * DETECT_RATIO).max(strongest * 0.01);
+    if threshold <= 0.0 { return; }
+
+    // 信号が無くなったチャンネルと、重なったチャンネルを削除する。
+    for channel in channels.iter_mut() {
+        let bin = (channel.freq / bin_hz).round() as usize;
+        if bin < smooth.len() && smooth[bin] > threshold {
+            channel.lost = 0;
+        } else {
+            channel.lost += 1;
+        }
+    }
+    let mut index = 0;
+    while index < channels.len() {
+        let duplicated = channels[..index].iter().any(|c| (c.freq - channels[index].freq).abs() < baud);
+        if channels[index].lost >= LOST_COUNT || duplicated {
+            let mut channel = channels.remove(index);
+            if let Some(line) = channel.take_line(true) {
+                text.push_str(&line);
+            }
+        } else {
+            index += 1;
+        }
+    }
+
+    // 新しい信号のピークにチャンネルを割り当てる。
+    for i in low.max(1)..high {
+        if channels.len() >= MAX_CHANNELS { break; }
+        if smooth[i] < threshold || smooth[i] < smooth[i - 1] || smooth[i] < smooth[i + 1] { continue; }
+        // 重心を求め直して、信号の中心に合わせる。(アイドル信号は中心から±baud/2の2本の線になる。)
+        let mut center = i as f32;
+        for _ in 0..3 {
+            let c = center.round() as usize;
+            let a = c.saturating_sub(width);
+            let b = (c + width + 1).min(average.len());
+            let total: f32 = average[a..b].iter().sum();
+            if total <= 0.0 { break; }
+            center = average[a..b].iter().enumerate().map(|(k, p)| (a + k) as f32 * p).sum::<f32>() / total;
+        }
+        let freq = center * bin_hz;
+        if channels.iter().all(|c| (c.freq - freq).abs() > 2.0 * baud) {
+            #[cfg(debug_assertions)]
+            println!("PSK signal {:.1} Hz", freq);
+            channels.push(PskChannel::new(freq, baud));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CHUNK_SIZE;
+    use crate::testsignal::gaussian_noise;
+
+    // PSK31の信号(振幅0.2)  2秒のアイドル(位相反転の連続)の後に文字をVaricodeで送り、最後は無変調のキャリアにする。
+    // 0は位相を反転し、振幅をコサインの形で変えて帯域を狭くする。
+    fn psk31_signal(text: &str, freq: f32, seconds: f32) -> Vec<f32> {
+        let baud = 31.25;
+        let mut bits: Vec<bool> = vec![false; (2.0 * baud) as usize];
+        for c in text.bytes() {
+            bits.extend(VARICODE[c as usize].bytes().map(|b| b == b'1'));
+            bits.extend([false, false]);
+        }
+        bits.extend(vec![false; 16]);
+        bits.extend(vec![true; (baud * seconds) as usize]);
+
+        let sps = (SAMPLING_FREQ / baud) as usize;
+        let mut sign = 1.0f32;
+        let mut signal = Vec::with_capacity(bits.len() * sps);
+        for bit in bits {
+            for i in 0..sps {
+                let envelope = if bit { sign } else { sign * (PI * i as f32 / sps as f32).cos() };
+                let t = signal.len() as f32 / SAMPLING_FREQ;
+                signal.push(0.2 * envelope * (2.0 * PI * freq * t).sin());
+            }
+            if !bit { sign = -sign; }
+        }
+        signal
+    }
+
+    #[test]
+    fn decodes_two_signals() {
+        let first = psk31_signal("CQ CQ DE JA1XYZ PSE K\n", 800.0, 4.0);
+        let second = psk31_signal("K1ABC DE W9XYZ 599 TNX\n", 1500.0, 4.0);
+        let length = first.len().max(second.len());
+        let mut noise = gaussian_noise(31);
+        let signal: Vec<f32> = (0..length).map(|i| {
+            first.get(i).unwrap_or(&0.0) + second.get(i).unwrap_or(&0.0) + 0.05 * noise()
+        }).collect();
+
+        let mut decoder = create_psk_decoder(31.25);
+        let text: String = signal.chunks(CHUNK_SIZE).map(&mut decoder).collect();
+        let mut lines: Vec<(f32, &str)> = text.lines().map(|line| {
+            let (freq, message) = line.trim_start_matches('[').split_once(" Hz] ").unwrap();
+            (freq.trim().parse().unwrap(), message)
+        }).collect();
+        lines.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(lines.len(), 2, "{}", text);
+        assert!((lines[0].0 - 800.0).abs() < 2.0 && (lines[1].0 - 1500.0).abs() < 2.0, "{}", text);
+        assert_eq!(lines[0].1, "CQ CQ DE JA1XYZ PSE K");
+        assert_eq!(lines[1].1, "K1ABC DE W9XYZ 599 TNX");
+    }
+
+    #[test]
+    fn search_ignores_nan_in_spectrum() {
+        let mut average = vec![1.0f32; FFT_SIZE / 2];
+        average[100] = f32::NAN;
+        let mut channels = Vec::new();
+        let mut text = String::new();
+        search_signals(&average, 31.25, &mut channels, &mut text);
+        assert!(channels.is_empty());
+    }
+}
@@ -0,0 +1,635 @@
+// 受信処理のスレッド
+// IFのデータを受け取り、コマンドに従って処理の設定を変えながら、復調した音声を送る。
+// 同じIFから複数の受信機(モード、フィルタ、BFO、AGC等の設定と処理ブロックの構成を別々に持つ)で同時に受信できる。
+use crate::constants::{CHUNK_SIZE, IF_FREQ, SAMPLING_FREQ};
+use crate::firfilter::FilterType;
+use crate::afc::{create_afc, AfcStatus, AFC_RANGE, CAL_TIME, SSB_RANGE};
+use crate::fileout::{start_file_writer, FileOutput};
+use crate::kiss::{start_kiss_server, kiss_output, KissClients};
+use crate::decoder::DecoderType;
+use crate::pipeline::{Context, Pipeline};
+use crate::blocks::{build_pipeline, DEFAULT_CHAIN, agc_block, af_block, anf_block, decoder_block, notch_block, peak_block, squelch_block};
+use crate::command::InternalCommand;
+use crate::pipes::{rssi_output, receive_data_output, text_output};
+use crate::audio::StereoChunk;
+use crate::spsc::{ChunkConsumer, ChunkProducer, QueueStats};
+use crate::iq::{create_input_converter, InputMode};
+use crate::agc::AGCType;
+use crate::config::{Config, Preset, ReceiverConfig};
+use crate::squelch::{SquelchMode, SquelchParams};
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+
+// 受信機の数の上限
+pub const MAX_RECEIVERS: usize = 4;
+
+/// 受信機の音声の出力先
+#[derive(Clone, PartialEq, Debug)]
+pub enum AudioRoute {
+    Both,               // 左右の両方
+    Left,
+    Right,
+    Pipe(String),       // 名前付きパイプ(/tmpの下)
+    Off,
+}
+
+// 受信機ごとに持つAFC
+type Afc = Box<dyn FnMut(&[f32], f32, f32) -> AfcStatus>;
+
+// サウンドカードの左右のチャンネルからIFをつくる処理(INPUTコマンドで作り直す)
+type InputConverter = Box<dyn FnMut(&StereoChunk) -> [f32; CHUNK_SIZE]>;
+
+// 1台の受信機
+// 受信の設定と処理ブロック、AFC、RSSIと文字列の出力先を持つ。
+struct Demodulator {
+    context: Context,
+    pipeline: Pipeline,
+    decoder_type: DecoderType,
+    agc_type: AGCType,      // 設定ファイルとプリセットに保存するために、処理ブロックの設定を覚えておく。
+    af_type: FilterType,
+    anf_on: bool,
+    notch: (f32, f32),
+    squelch: SquelchParams,
+    afc: Afc,
+    afc_on: bool,
+    offset: f32,            // IFのキャリアの位置からの受信周波数のずれ[Hz]
+    cal_chunks: usize,      // CALコマンドで測定を待つ残りのチャンク数
+    route: AudioRoute,
+    rssi_path: String,
+    text_path: String,
+}
+
+impl Demodulator {
+    fn new( frame_tx: &Sender<Vec<u8>>, file_tx: &Sender<FileOutput> ) -> Self {
+        Demodulator {
+            context: Context::default(),
+            pipeline: build_pipeline( &DEFAULT_CHAIN, frame_tx.clone(), file_tx.clone() ).expect("標準の構成が正しくありません。"),
+            decoder_type: DecoderType::None,
+            agc_type: AGCType::AGC05,
+            af_type: FilterType::AF11K,
+            anf_on: false,
+            notch: (0.0, 0.0),
+            squelch: SquelchParams::OFF,
+            afc: Box::new( create_afc() ),
+            afc_on: false,
+            offset: 0.0,
+            cal_chunks: 0,
+            route: AudioRoute::Both,
+            rssi_path: "".to_string(),
+            text_path: "".to_string(),
+        }
+    }
+
+    // 設定ファイルの設定で受信機をつくる。
+    fn with_config( config: &ReceiverConfig, frame_tx: &Sender<Vec<u8>>, file_tx: &Sender<FileOutput> ) -> Self {
+        let mut demodulator = Demodulator::new( frame_tx, file_tx );
+        match config.commands() {
+            Ok(commands) => commands.into_iter().for_each(|command| demodulator.command( command, frame_tx, file_tx )),
+            Err(e) => println!("受信機を設定できません: {}", e),
+        }
+        demodulator
+    }
+
+    // 受信機ごとのコマンドを処理する。
+    fn command( &mut self, command: InternalCommand, frame_tx: &Sender<Vec<u8>>, file_tx: &Sender<FileOutput> ) {
+        let context = &mut self.context;
+        let pipeline = &mut self.pipeline;
+        match command {
+            InternalCommand::AM(ftype) |
+            InternalCommand::USB(ftype) |
+            InternalCommand::LSB(ftype) |
+            InternalCommand::AMUSB(ftype) |
+            InternalCommand::AMLSB(ftype) |
+            InternalCommand::CW(ftype) => {
+                if let FilterType::CW(bw) = ftype {
+                    pipeline.replace( peak_block(context.cw_pitch, bw) );
+                }
+                context.if_type = ftype;
+                context.if_shift = 0.0;     // モードを変えたらシフトと帯域幅は初期値に戻す。
+                context.if_width = 0.0;
+                context.fm_mode = false;
+            },
+            InternalCommand::FM => {
+                context.if_type = FilterType::AM11K;
+                context.if_shift = 0.0;
+                context.if_width = 0.0;
+                context.fm_mode = true;
+            },
+            InternalCommand::SHIFT(shift) => context.if_shift = shift,
+            InternalCommand::WIDTH(width) => context.if_width = width,
+            InternalCommand::PITCH(pitch) => {
+                context.cw_pitch = pitch;
+                if let FilterType::CW(bw) = context.if_type {
+                    pipeline.replace( peak_block(context.cw_pitch, bw) );
+                }
+                if self.decoder_type == DecoderType::CW {
+                    pipeline.replace( decoder_block(self.decoder_type, context.cw_pitch, frame_tx.clone(), file_tx.clone()) );
+                }
+            },
+            InternalCommand::DECODE(dtype) => {
+                self.decoder_type = dtype;
+                pipeline.replace( decoder_block(self.decoder_type, context.cw_pitch, frame_tx.clone(), file_tx.clone()) );
+            },
+            InternalCommand::TONE(on) => context.tone_display = on,
+            InternalCommand::TSQL(tsql) => context.tone_squelch = tsql,
+            InternalCommand::SQL(params) => {
+                self.squelch = params;
+                pipeline.replace( squelch_block(params) );
+            },
+            InternalCommand::AFC(on) => {
+                self.afc_on = on;
+                self.afc = Box::new( create_afc() );
+            },
+            InternalCommand::CAL => {
+                self.cal_chunks = (CAL_TIME * SAMPLING_FREQ) as usize / CHUNK_SIZE;
+                println!("CAL: キャリアを測定しています。");
+            },
+            InternalCommand::CHAIN(names) => {
+                if names.is_empty() {
+                    print!("{}", pipeline.describe());
+                } else {
+                    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+                    match build_pipeline( &names, frame_tx.clone(), file_tx.clone() ) {
+                        Ok(p) => {
+                            *pipeline = p;
+                            self.agc_type = AGCType::AGC05;     // 作り直したブロックは標準の設定になる。
+                            self.af_type = FilterType::AF11K;
+                            self.anf_on = false;
+                            self.notch = (0.0, 0.0);
+                            self.squelch = SquelchParams::OFF;
+                            self.decoder_type = DecoderType::None;
+                            print!("{}", pipeline.describe());
+                        },
+                        Err(e) => println!("構成を変更できません: {}", e),
+                    }
+                }
+            },
+            InternalCommand::RESET => pipeline.reset(),
+            InternalCommand::TEXT(text_name) => {
+                if text_name != "None" {
+                    self.text_path = format!("/tmp/{}", text_name);    // /tmpの下に名前付きパイプを作る。
+                }
+                else {
+                    self.text_path = "".to_string();
+                }
+            },
+            InternalCommand::AGC(atype) => {
+                self.agc_type = atype;
+                pipeline.replace( agc_block(atype) );
+            },
+            InternalCommand::AF(ftype) => {
+                self.af_type = ftype;
+                pipeline.replace( af_block(ftype) );
+            },
+            InternalCommand::RSSI(rssi_name) => {
+                if rssi_name != "None" {
+                    self.rssi_path = format!("/tmp/{}", rssi_name);    // /tmpの下に名前付きパイプを作る。
+                }
+                else {
+                    self.rssi_path = "".to_string();
+                }
+            },
+            InternalCommand::BFO(freq) => {
+                context.bfo_freq = freq;
+                self.afc = Box::new( create_afc() );
+            },
+            InternalCommand::OFFSET(offset) => {
+                self.offset = offset;
+                self.afc = Box::new( create_afc() );
+            },
+            InternalCommand::ROUTE(route) => {
+                self.route = match route {
+                    AudioRoute::Pipe(name) => AudioRoute::Pipe(format!("/tmp/{}", name)),    // /tmpの下に名前付きパイプを作る。
+                    route => route,
+                };
+            },
+            InternalCommand::ANF(on) => {
+                self.anf_on = on;
+                pipeline.replace( anf_block(on) );  // 係数を初期化してから動作させる
+            },
+            InternalCommand::NOTCH(freq, width) => {
+                self.notch = (freq, width);
+                pipeline.replace( notch_block(freq, width) );
+            },
+            _ => {},
+        }
+    }
+
+    // 1チャンクを処理して音声を返す。CALの測定が終わったらif_offsetを更新する。
+    // RSSIはsmeter_offset[dB]だけ補正して出力する。スケルチもContextを通して同じ補正をした値で判定する。
+    fn process( &mut self, if_data: &[f32; CHUNK_SIZE], if_offset: &mut f32, smeter_offset: f32 ) -> [f32; CHUNK_SIZE] {
+        let context = &mut self.context;
+        context.smeter_offset = smeter_offset;
+
+        // AFC  BFOを設定していない時は、CALコマンドで測定したずれを補正した位置からOFFSETだけ離れた周波数をキャリアの基準にする。
+        let reference = if context.bfo_freq != 0.0 { context.bfo_freq } else { IF_FREQ + *if_offset + self.offset };
+        let afc_status = (self.afc)( if_data, reference, if self.afc_on { afc_range( context.if_type, context.fm_mode ) } else { 0.0 } );
+        context.text.push_str( &afc_status.text );
+        context.carrier = afc_status.carrier;
+
+        // CALコマンド  測定したキャリアの位置からIFのずれを求めて保存する。
+        if self.cal_chunks > 0 {
+            self.cal_chunks -= 1;
+            if let Some(freq) = afc_status.measured {
+                *if_offset = freq - IF_FREQ - self.offset;
+                self.cal_chunks = 0;
+                println!("CAL: キャリア {:.1}Hz、IFのずれ {:+.1}Hz", freq, if_offset);
+            } else if self.cal_chunks == 0 {
+                println!("CAL: キャリアが見つかりません。");
+            }
+        }
+
+        // IFフィルタからデコーダ、スケルチまでの処理
+        let filtered_audio = self.pipeline.process( if_data, context );
+
+        // RSSI表示  表示に失敗したらfalseが返る。
+        let rssi = context.rssi * 10.0_f32.powf( context.smeter_offset / 20.0 );
+        if !rssi_output( rssi, &self.rssi_path ) { self.rssi_path = "".to_string(); };
+
+        // 名前付きパイプへの音声の出力
+        if let AudioRoute::Pipe(path) = &self.route {
+            if !receive_data_output( &filtered_audio, path ) { self.route = AudioRoute::Off; };
+        }
+
+        filtered_audio
+    }
+
+    // プリセットに保存する設定
+    fn preset( &self ) -> Preset {
+        let context = &self.context;
+        let (mode, filter) = mode_param( context.if_type, context.fm_mode );
+        Preset {
+            mode: mode.to_string(),
+            filter,
+            shift: context.if_shift,
+            width: context.if_width,
+            pitch: context.cw_pitch,
+            agc: agc_param( self.agc_type ),
+            af: af_param( self.af_type ),
+            bfo: context.bfo_freq,
+            anf: self.anf_on,
+            notch: if self.notch.0 > 0.0 { format!("{} {}", self.notch.0, self.notch.1) } else { "OFF".to_string() },
+            squelch: sql_param( &self.squelch ),
+            decode: decode_param( self.decoder_type ),
+        }
+    }
+
+    // 設定ファイルに保存する設定
+    fn config( &self ) -> ReceiverConfig {
+        let names = self.pipeline.names();
+        ReceiverConfig {
+            preset: self.preset(),
+            offset: self.offset,
+            afc: self.afc_on,
+            route: match &self.route {
+                AudioRoute::Both => "LR".to_string(),
+                AudioRoute::Left => "L".to_string(),
+                AudioRoute::Right => "R".to_string(),
+                AudioRoute::Pipe(path) => format!("PIPE {}", pipe_name(path)),
+                AudioRoute::Off => "OFF".to_string(),
+            },
+            rssi: pipe_name( &self.rssi_path ).to_string(),
+            text: pipe_name( &self.text_path ).to_string(),
+            chain: if names == DEFAULT_CHAIN { Vec::new() } else { names.iter().map(|name| name.to_string()).collect() },
+        }
+    }
+
+    // 受信機の一覧の表示用の設定の説明
+    fn describe( &self ) -> String {
+        let mode = if self.context.fm_mode { "FM".to_string() } else { format!("{:?}", self.context.if_type) };
+        format!("{} OFFSET {:+}Hz DECODE {:?} ROUTE {:?}", mode, self.offset, self.decoder_type, self.route)
+    }
+}
+
+/// データ処理スレッド
+/// 受信機ごとの音声を出力先に従って左右に加えて送る。コマンドはRXコマンドで選んだ受信機に対して実行する。
+/// 起動時の設定はconfigから取り、SAVEコマンドで現在の状態をconfig_path(または指定したファイル)に書き戻す。
+/// IFのキューの送る側が無くなったら終了する。
+///
+/// ```no_run
+/// use std::sync::mpsc::channel;
+/// use std::thread;
+/// use thsdr::spsc::chunk_queue;
+/// use thsdr::config::Config;
+///
+/// let (if_tx, if_rx) = chunk_queue(16);
+/// let (audio_tx, audio_rx) = chunk_queue(8);
+/// let (_command_tx, command_rx) = channel();
+/// thread::spawn(move || thsdr::receiver::process_thread(if_rx, audio_tx, command_rx, Config::default(), "thsdr.toml".into()));
+/// # let _ = (if_tx, audio_rx);
+/// ```
+pub fn process_thread( mut if_rx: ChunkConsumer, mut audio_tx: ChunkProducer, rx: Receiver<InternalCommand>, config: Config, config_path: PathBuf ) {
+    let (frame_tx, frame_rx): (Sender<Vec<u8>>, Receiver<Vec<u8>>) = channel();
+    let (file_tx, file_writer) = start_file_writer();
+    let mut receivers: Vec<Option<Demodulator>> = (0..MAX_RECEIVERS).map(|_| None).collect();
+    for (receiver, rx_config) in receivers.iter_mut().zip(config.receiver.iter()) {
+        *receiver = Some( Demodulator::with_config( rx_config, &frame_tx, &file_tx ) );
+    }
+    if receivers[0].is_none() {
+        receivers[0] = Some( Demodulator::new( &frame_tx, &file_tx ) );
+    }
+    let mut current: usize = 0;           // コマンドを送る受信機
+    let mut last_text: usize = 0;         // 最後に文字列を表示した受信機
+    let mut receive_data_path: String = if config.ifout.is_empty() { "".to_string() } else { format!("/tmp/{}", config.ifout) };
+    let mut kiss_clients: Option<KissClients> = None;
+    let mut kiss_port: u16 = 0;
+    let mut if_offset: f32 = config.if_offset;     // CALコマンドで測定した、IFのキャリアの位置のずれ
+    let mut input_mode: InputMode = config.input_mode().unwrap_or(InputMode::Left);
+    let mut input_converter: InputConverter = Box::new( create_input_converter( input_mode ) );
+    let mut presets: BTreeMap<String, Preset> = config.preset.clone();
+    if config.kiss != 0 {
+        match start_kiss_server( config.kiss ) {
+            Ok(clients) => { kiss_clients = Some(clients); kiss_port = config.kiss; },
+            Err(e) => println!("KISSのポートを開けません: {}", e),
+        }
+    }
+
+    // 受信したデータに対する処理を行う
+    loop {
+        let command = rx.try_recv().unwrap_or(InternalCommand::None); // キー入力結果を受信
+
+        // IFコマンド  選んでいる受信機のRSSIが条件を満たす時だけ、コマンドを実行する。
+        let command = match command {
+            InternalCommand::IF(condition, command) => {
+                let rssi_db = receivers[current].as_ref()
+                    .map_or(f32::NEG_INFINITY, |receiver| 20.0 * receiver.context.rssi.max(1e-10).log10() + config.smeter_offset);
+                if condition.holds( rssi_db ) { *command } else { InternalCommand::None }
+            },
+            command => command,
+        };
+        match command {
+            InternalCommand::RX(None) => {
+                for (n, receiver) in receivers.iter().enumerate() {
+                    if let Some(receiver) = receiver {
+                        println!("RX{}{} {}", n + 1, if n == current { "*" } else { " " }, receiver.describe());
+                    }
+                }
+            },
+            InternalCommand::RX(Some(n)) => {
+                if receivers[n].is_none() {
+                    receivers[n] = Some( Demodulator::new( &frame_tx, &file_tx ) );
+                    println!("RX{}を追加しました。", n + 1);
+                }
+                current = n;
+            },
+            InternalCommand::RXOFF(n) => {
+                if receivers.iter().filter(|r| r.is_some()).count() <= 1 || receivers[n].is_none() {
+                    println!("RX{}は削除できません。", n + 1);
+                } else {
+                    receivers[n] = None;
+                    if current == n {
+                        current = receivers.iter().position(|r| r.is_some()).unwrap_or(0);
+                        println!("RX{}を削除しました。コマンドはRX{}に送ります。", n + 1, current + 1);
+                    }
+                }
+            },
+            InternalCommand::KISS(port) => {
+                if kiss_clients.is_some() {
+                    println!("KISSのポートは既に開いています。");
+                } else {
+                    match start_kiss_server( port ) {
+                        Ok(clients) => { kiss_clients = Some(clients); kiss_port = port; },
+                        Err(e) => println!("KISSのポートを開けません: {}", e),
+                    }
+                }
+            },
+            InternalCommand::IFOUT(ifout_name) => {
+                if ifout_name != "None" {
+                    receive_data_path = format!("/tmp/{}", ifout_name);    // /tmpの下に名前付きパイプを作る。
+                }
+                else {
+                    receive_data_path = "".to_string();
+                }
+            },
+            InternalCommand::STORE(name) => {
+                if let Some(receiver) = &receivers[current] {
+                    presets.insert( name.clone(), receiver.preset() );
+                    println!("プリセット{}を記憶しました。SAVEで設定ファイルに保存します。", name);
+                }
+            },
+            InternalCommand::RECALL(name) => {
+                match (presets.get( &name ), &mut receivers[current]) {
+                    (Some(preset), Some(receiver)) => match preset.commands() {
+                        Ok(commands) => commands.into_iter().for_each(|command| receiver.command( command, &frame_tx, &file_tx )),
+                        Err(e) => println!("プリセット{}を設定できません: {}", name, e),
+                    },
+                    _ => println!("プリセット{}がありません。", name),
+                }
+            },
+            InternalCommand::LIST => {
+                for (name, preset) in &presets {
+                    println!("{:12} {}", name, preset.command_lines().join(", "));
+                }
+            },
+            InternalCommand::STATS => print!("{}", stats_text( &if_rx.stats(), &audio_tx.stats(), &receivers )),
+            InternalCommand::INPUT(mode) => {
+                input_mode = mode;
+                input_converter = Box::new( create_input_converter( mode ) );
+            },
+            InternalCommand::SAVE(path) => {
+                // 起動時の設定に、受信機とIFの入出力の現在の状態を反映して書き出す。
+                let path = path.map_or( config_path.clone(), PathBuf::from );
+                let saved = Config {
+                    input: input_param( input_mode ).to_string(),
+                    if_offset,
+                    ifout: pipe_name( &receive_data_path ).to_string(),
+                    kiss: kiss_port,
+                    receiver: receivers.iter().flatten().map(|receiver| receiver.config()).collect(),
+                    preset: presets.clone(),
+                    ..config.clone()
+                };
+                save_config( &saved, &path );
+            },
+            InternalCommand::None => {},
+            InternalCommand::EXIT => { break; },
+            command => {
+                if let Some(receiver) = &mut receivers[current] {
+                    receiver.command( command, &frame_tx, &file_tx );
+                }
+            },
+        };
+
+        // データ待ち
+        let Some(input) = if_rx.wait_pop() else { break };
+        let if_data: [f32; CHUNK_SIZE] = input_converter( &input );
+
+
+        // 中間周波数のデータを名前付きパイプに出力する。
+        // 帯域の状態を表示することを想定している。
+        if !receive_data_output( &if_data, &receive_data_path ) { receive_data_path = "".to_string(); };
+
+        let multiple = receivers.iter().filter(|r| r.is_some()).count() > 1;
+        let mut stereo: StereoChunk = [[0.0; CHUNK_SIZE]; 2];
+        for (n, receiver) in receivers.iter_mut().enumerate() {
+            let Some(receiver) = receiver else { continue };
+            let audio = receiver.process( &if_data, &mut if_offset, config.smeter_offset );
+
+            // 出力先の左右に加える。
+            let (left, right) = match receiver.route {
+                AudioRoute::Both => (true, true),
+                AudioRoute::Left => (true, false),
+                AudioRoute::Right => (false, true),
+                AudioRoute::Pipe(_) | AudioRoute::Off => (false, false),
+            };
+            for (channel, on) in stereo.iter_mut().zip([left, right]) {
+                if on {
+                    channel.iter_mut().zip(audio.iter()).for_each(|(c, a)| *c += a);
+                }
+            }
+
+            // AFCの状態、トーンの検出結果とデコードした文字列は、画面と名前付きパイプに出力する。
+            // 複数の受信機がある時は、画面には受信機が変わるごとに番号を付ける。
+            let context = &mut receiver.context;
+            if !context.text.is_empty() {
+                if multiple && n != last_text {
+                    print!("\n[RX{}] ", n + 1);
+                }
+                last_text = n;
+                print!("{}", context.text);
+                let _ = io::stdout().flush();
+                if !text_output( &context.text, &receiver.text_path ) { receiver.text_path = "".to_string(); };
+                context.text.clear();
+            }
+        }
+
+        // デコーダが受信したフレームをKISSのクライアントに送る。
+        while let Ok(frame) = frame_rx.try_recv() {
+            if let Some(clients) = &kiss_clients {
+                kiss_output( &frame, clients );
+            }
+        }
+
+        // データの送信
+        audio_tx.push( &stereo );
+    }
+
+    // 保存を待っている画像を書き終えてから終了する。
+    drop( receivers );
+    drop( file_tx );
+    let _ = file_writer.join();
+}
+
+// SAVEコマンド  書き出した結果を表示する。
+fn save_config( config: &Config, path: &Path ) {
+    match config.save( path ) {
+        Ok(()) => println!("設定を{}に保存しました。", path.display()),
+        Err(e) => println!("設定を保存できません: {}", e),
+    }
+}
+
+// STATSコマンドの表示
+// キューの状態と、入力から出力までの遅延の目安(チャンクをまとめる時間、キューに溜まっている時間、処理ブロックの遅延)
+fn stats_text( if_stats: &QueueStats, audio_stats: &QueueStats, receivers: &[Option<Demodulator>] ) -> String {
+    let ms = |samples: usize| samples as f32 * 1000.0 / SAMPLING_FREQ;
+    let input = ms( (if_stats.depth + 1) * CHUNK_SIZE );
+    let output = ms( (audio_stats.depth + 1) * CHUNK_SIZE );
+    let mut text = format!("IF入力   キュー {:2}/{:2}  溢れ {}\n", if_stats.depth, if_stats.capacity, if_stats.overruns);
+    text.push_str( &format!("音声出力 キュー {:2}/{:2}  溢れ {}  途切れ {}\n", audio_stats.depth, audio_stats.capacity, audio_stats.overruns, audio_stats.underruns) );
+    text.push_str( &format!("遅延 入力 {:.0}ms  出力 {:.0}ms\n", input, output) );
+    for (n, receiver) in receivers.iter().enumerate() {
+        if let Some(receiver) = receiver {
+            let processing = ms( receiver.pipeline.latency() );
+            text.push_str( &format!("RX{} 処理 {:.0}ms  合計 {:.0}ms\n", n + 1, processing, input + processing + output) );
+        }
+    }
+    text
+}
+
+// AFCで追従する範囲[Hz]
+// CWは受信している信号をキャリアとして、IFフィルタの通過域の中で追従する。
+// SSBは抑圧されたキャリアの位置に残る低減搬送波(パイロット)を、音声の帯域より内側の狭い範囲で追従する。
+// 完全に抑圧されている時は見つからないので、最後の位置を保持する。
+fn afc_range( ftype: FilterType, fm_mode: bool ) -> f32 {
+    match ftype {
+        _ if fm_mode => AFC_RANGE,
+        FilterType::CW(bw) => bw / 2.0,
+        FilterType::USB2K | FilterType::USB3K | FilterType::LSB2K | FilterType::LSB3K => SSB_RANGE,
+        _ => AFC_RANGE,
+    }
+}
+
+// 名前付きパイプのパスから、コマンドで指定した名前(/tmpの下)に戻す。
+fn pipe_name( path: &str ) -> &str {
+    path.strip_prefix("/tmp/").unwrap_or(path)
+}
+
+// 設定ファイルに保存する値(コマンドの値と同じ)
+// フィルタ無し(FilterType::None)は、モードを区別しないので広い帯域のAMにする。
+fn mode_param( ftype: FilterType, fm_mode: bool ) -> (&'static str, i32) {
+    match ftype {
+        _ if fm_mode => ("FM", 0),
+        FilterType::AM3K => ("AM", 3),
+        FilterType::AM6K => ("AM", 6),
+        FilterType::AM11K => ("AM", 11),
+        FilterType::USB2K => ("USB", 2),
+        FilterType::USB3K => ("USB", 3),
+        FilterType::LSB2K => ("LSB", 2),
+        FilterType::LSB3K => ("LSB", 3),
+        FilterType::AMUSB3K => ("AMUSB", 3),
+        FilterType::AMUSB6K => ("AMUSB", 6),
+        FilterType::AMUSB7K => ("AMUSB", 7),
+        FilterType::AMLSB3K => ("AMLSB", 3),
+        FilterType::AMLSB6K => ("AMLSB", 6),
+        FilterType::AMLSB7K => ("AMLSB", 7),
+        FilterType::CW(bw) => ("CW", bw as i32),
+        _ => ("AM", 20),
+    }
+}
+
+fn agc_param( atype: AGCType ) -> i32 {
+    match atype {
+        AGCType::None => -1,
+        AGCType::AGC05 => 0,
+        AGCType::AGC15 => 1,
+        AGCType::AGC20 => 2,
+        AGCType::AGC30 => 3,
+        AGCType::AGC50 => 4,
+    }
+}
+
+fn af_param( ftype: FilterType ) -> i32 {
+    match ftype {
+        FilterType::AF3K => 3,
+        FilterType::AF6K => 6,
+        _ => 11,
+    }
+}
+
+fn input_param( mode: InputMode ) -> &'static str {
+    match mode {
+        InputMode::Left => "L",
+        InputMode::Right => "R",
+        InputMode::IQ => "IQ",
+        InputMode::QI => "QI",
+    }
+}
+
+fn sql_param( params: &SquelchParams ) -> String {
+    let mode = match params.mode {
+        SquelchMode::Level => "LEVEL",
+        SquelchMode::Noise => "NOISE",
+        SquelchMode::SNR => "SNR",
+        SquelchMode::None => return "OFF".to_string(),
+    };
+    format!("{} {} {} {}", mode, params.level, params.hysteresis, params.tail)
+}
+
+fn decode_param( dtype: DecoderType ) -> String {
+    match dtype {
+        DecoderType::CW => "CW".to_string(),
+        DecoderType::APRS => "APRS".to_string(),
+        DecoderType::RTTY { shift, baud, reverse } => format!("RTTY {} {} {}", shift, baud, if reverse { "REV" } else { "NORM" }),
+        DecoderType::PSK31 => "PSK31".to_string(),
+        DecoderType::PSK63 => "PSK63".to_string(),
+        DecoderType::WEFAX { lpm, slant } => format!("WEFAX {} {}", lpm, slant),
+        DecoderType::NAVTEX { center } => format!("NAVTEX {}", center),
+        DecoderType::SSTV => "SSTV".to_string(),
+        DecoderType::FT8 => "FT8".to_string(),
+        DecoderType::FT4 => "FT4".to_string(),
+        DecoderType::POCSAG => "POCSAG".to_string(),
+        DecoderType::None => "OFF".to_string(),
+    }
+}
@@ -0,0 +1,73 @@
+// システムで使う定数の読み込み
+use crate::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+
+
+// クロックのずれの補正関連の定数の定義
+const UPDATE_TIME: f64 = CHUNK_SIZE as f64 / SAMPLING_FREQ as f64;    // 制御する間隔(1チャンク)[秒]
+const LOOP_FREQ: f64 = 0.01;            // 制御ループの固有周波数[Hz](約100秒で追従する。)
+const DAMPING: f64 = 1.0;               // 制御ループの減衰係数
+const KP: f64 = 2.0 * DAMPING * 2.0 * core::f64::consts::PI * LOOP_FREQ / SAMPLING_FREQ as f64;
+const KI: f64 = (2.0 * core::f64::consts::PI * LOOP_FREQ) * (2.0 * core::f64::consts::PI * LOOP_FREQ) / SAMPLING_FREQ as f64;
+const FILL_SMOOTHING: f64 = 0.005;      // 溜まっているサンプル数の平滑化(1チャンクあたり、時定数約4秒)
+pub const MAX_ADJUST: f64 = 0.001;      // 比率を変える範囲(±1000[ppm])
+
+/// クロックのずれの補正(PI制御)
+/// 出力側のキューに溜まっているサンプル数(fill)を1チャンクごとに受け取り、targetに保つように、
+/// 出力の1サンプルあたりに進める入力のサンプル数(比率)を返す。溜まりすぎていれば1より大きく(早く読む)、少なければ1より小さくする。
+/// 積分の項が2つのクロックの周波数の比になるので、何時間動かしても遅延は変わらない。
+///
+/// ```
+/// use thsdr::resampler::create_drift_controller;
+///
+/// let mut controller = create_drift_controller(2048.0);
+/// assert_eq!(controller(2048.0), 1.0);
+/// assert!(controller(3072.0) > 1.0);
+/// ```
+pub fn create_drift_controller(target: f64) -> impl FnMut(f64) -> f64 {
+    let mut smoothed: Option<f64> = None;
+    let mut integral: f64 = 0.0;
+
+    move |fill: f64| -> f64 {
+        let s = smoothed.map_or(fill, |s| s + (fill - s) * FILL_SMOOTHING);
+        smoothed = Some(s);
+        let error = s - target;
+        integral = (integral + KI * error * UPDATE_TIME).clamp(-MAX_ADJUST, MAX_ADJUST);
+        1.0 + (KP * error + integral).clamp(-MAX_ADJUST, MAX_ADJUST)
+    }
+}
+
+/// 比率を連続的に変えられるリサンプラ(4点のエルミート補間)
+/// 出力の1サンプルごとに、比率(ratio)と入力を1サンプルずつ取り出す関数(source)を受け取り、補間した値を返す。
+/// 入力は必要な分だけsourceから取り出すので、比率が1より大きければ多く、小さければ少なく読む。
+///
+/// ```
+/// use thsdr::resampler::create_resampler;
+///
+/// // 比率が1の時は、2サンプル遅れて入力をそのまま出力する。
+/// let mut resampler = create_resampler();
+/// let mut n = 0.0;
+/// let output: Vec<(f32, f32)> = (0..8).map(|_| resampler(1.0, &mut || { n += 1.0; (n, -n) })).collect();
+/// assert_eq!(output[7], (6.0, -6.0));
+/// ```
+pub fn create_resampler() -> impl FnMut(f64, &mut dyn FnMut() -> (f32, f32)) -> (f32, f32) {
+    let mut history: [(f32, f32); 4] = [(0.0, 0.0); 4];
+    let mut phase: f64 = 0.0;       // history[1]とhistory[2]の間の位置(0～1)
+
+    move |ratio: f64, source: &mut dyn FnMut() -> (f32, f32)| -> (f32, f32) {
+        phase += ratio;
+        while phase >= 1.0 {
+            phase -= 1.0;
+            history.rotate_left(1);
+            history[3] = source();
+        }
+        let t = phase as f32;
+        let hermite = |y0: f32, y1: f32, y2: f32, y3: f32| -> f32 {
+            let c1 = 0.5 * (y2 - y0);
+            let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+            let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+            ((c3 * t + c2) * t + c1) * t + y1
+        };
+        let [a, b, c, d] = history;
+        (hermite(a.0, b.0, c.0, d.0), hermite(a.1, b.1, c.1, d.1))
+    }
+}
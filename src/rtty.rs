@@ -0,0 +1,159 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use crate::fsk::create_fsk_demod;
+
+
+// RTTY関連の定数の定義
+const MARK_FREQ: f32 = 2125.0;      // 音声でのマークの周波数
+const MIN_LEVEL: f32 = 0.2;         // これより弱い判定はノイズとして扱う。
+
+// ITA2(Baudot)の文字表。0x1BはFIGS、0x1FはLTRSへの切り替えなので、ここでは使わない。
+pub const LETTERS: [Option<char>; 32] = [
+    None, Some('E'), Some('\n'), Some('A'), Some(' '), Some('S'), Some('I'), Some('U'),
+    None, Some('D'), Some('R'), Some('J'), Some('N'), Some('F'), Some('C'), Some('K'),
+    Some('T'), Some('Z'), Some('L'), Some('W'), Some('H'), Some('Y'), Some('P'), Some('Q'),
+    Some('O'), Some('B'), Some('G'), None, Some('M'), Some('X'), Some('V'), None,
+];
+pub const FIGURES: [Option<char>; 32] = [
+    None, Some('3'), Some('\n'), Some('-'), Some(' '), Some('\''), Some('8'), Some('7'),
+    None, None, Some('4'), None, Some(','), Some('!'), Some(':'), Some('('),
+    Some('5'), Some('+'), Some(')'), Some('2'), Some('#'), Some('6'), Some('0'), Some('1'),
+    Some('9'), Some('?'), Some('&'), None, Some('.'), Some('/'), Some('='), None,
+];
+pub const FIGS: u8 = 0x1B;
+pub const LTRS: u8 = 0x1F;
+pub const SPACE: u8 = 0x04;
+
+// 受信の状態
+enum RxState {
+    Idle,                   // マーク(アイドル)でスタートビットを待つ
+    Start(f32),             // スタートビットの中央まで待つ
+    Data(f32, u8, u8),      // データビットの受信中(次のサンプルまでの残り、受信したビット数、データ)
+    Stop(f32, u8),          // ストップビットの確認
+}
+
+// RTTYデコーダ
+// shiftはマークとスペースの周波数差[Hz]、baudは速度、reverseがtrueならマークとスペースを入れ替える。
+// 調歩同期なので、スタートビットの変化点を基準に各ビットの中央で判定する。
+pub fn create_rtty_decoder(shift: f32, baud: f32, reverse: bool) -> impl FnMut(&[f32]) -> String {
+    let (mark, space) = if reverse {
+        (MARK_FREQ + shift, MARK_FREQ)
+    } else {
+        (MARK_FREQ, MARK_FREQ + shift)
+    };
+    let mut demod = create_fsk_demod(mark, space, baud);
+    let bit_samples = SAMPLING_FREQ / baud;
+
+    let mut state = RxState::Idle;
+    let mut last = 0.0;
+    let mut figures = false;
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+
+        for &x in input {
+            let level = demod(x);
+
+            state = match state {
+                RxState::Idle => {
+                    if last >= 0.0 && level < 0.0 {
+                        RxState::Start(bit_samples / 2.0)
+                    } else {
+                        RxState::Idle
+                    }
+                },
+                RxState::Start(remain) if remain > 1.0 => RxState::Start(remain - 1.0),
+                RxState::Start(_) => {
+                    if level < -MIN_LEVEL {
+                        RxState::Data(bit_samples, 0, 0)
+                    } else {
+                        RxState::Idle   // ノイズによる変化だった。
+                    }
+                },
+                RxState::Data(remain, count, data) if remain > 1.0 => RxState::Data(remain - 1.0, count, data),
+                RxState::Data(_, count, data) => {
+                    // LSBから送られてくる。マークが1
+                    let data = if level > 0.0 { data | (1 << count) } else { data };
+                    if count + 1 == 5 {
+                        RxState::Stop(bit_samples, data)
+                    } else {
+                        RxState::Data(bit_samples, count + 1, data)
+                    }
+                },
+                RxState::Stop(remain, data) if remain > 1.0 => RxState::Stop(remain - 1.0, data),
+                RxState::Stop(_, data) => {
+                    if level > MIN_LEVEL {
+                        match data {
+                            FIGS => figures = true,
+                            LTRS => figures = false,
+                            _ => {
+                                let table = if figures { &FIGURES } else { &LETTERS };
+                                if let Some(c) = table[data as usize] {
+                                    text.push(c);
+                                }
+                                // スペースの後は文字に戻す(Unshift on space)
+                                if data == SPACE { figures = false; }
+                            },
+                        }
+                    }
+                    RxState::Idle
+                },
+            };
+            last = level;
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CHUNK_SIZE;
+    use crate::testsignal::{fsk_signal, gaussian_noise, ita2_encode};
+
+    const TEXT: &str = "RYRY CQ DE JA1XYZ 599 73-1/2 K\r\n";
+
+    // 調歩同期(スタート1、データ5(LSBから)、ストップ1.5ビット)のビット列
+    // ストップビットが1.5ビットなので、半ビットごとのビット列(trueがマーク)にする。
+    fn half_bits(codes: &[u8]) -> Vec<bool> {
+        let mut halves: Vec<bool> = vec![true; 20];
+        for &code in codes {
+            halves.extend([false; 2]);
+            halves.extend((0..5).flat_map(|i| [(code >> i) & 1 == 1; 2]));
+            halves.extend([true; 3]);
+        }
+        halves.extend([true; 20]);
+        halves
+    }
+
+    // 45.45bd、シフト170HzのRTTY信号に雑音を加える。
+    fn rtty_signal(codes: &[u8], reverse: bool) -> Vec<f32> {
+        let (mark, space) = if reverse { (MARK_FREQ + 170.0, MARK_FREQ) } else { (MARK_FREQ, MARK_FREQ + 170.0) };
+        let mut noise = gaussian_noise(3);
+        fsk_signal(&half_bits(codes), mark, space, 2.0 * 45.45).iter().map(|x| x + 0.1 * noise()).collect()
+    }
+
+    fn decode(signal: &[f32], reverse: bool) -> String {
+        let mut decoder = create_rtty_decoder(170.0, 45.45, reverse);
+        signal.chunks(CHUNK_SIZE).map(&mut decoder).collect()
+    }
+
+    #[test]
+    fn decodes_normal_polarity() {
+        assert_eq!(decode(&rtty_signal(&ita2_encode(TEXT), false), false), "RYRY CQ DE JA1XYZ 599 73-1/2 K\n");
+    }
+
+    #[test]
+    fn decodes_reverse_polarity() {
+        let signal = rtty_signal(&ita2_encode(TEXT), true);
+        assert_eq!(decode(&signal, true), "RYRY CQ DE JA1XYZ 599 73-1/2 K\n");
+        assert_ne!(decode(&signal, false), "RYRY CQ DE JA1XYZ 599 73-1/2 K\n");
+    }
+
+    #[test]
+    fn unshifts_on_space() {
+        // 数字の後の空白で文字に戻るので、FIGS 3 空白 3の符号は"3 E"になる。
+        assert_eq!(decode(&rtty_signal(&[FIGS, 0x01, SPACE, 0x01], false), false), "3 E");
+    }
+}
@@ -0,0 +1,145 @@
+// コマンドのスクリプト
+// ファイルに書いたコマンドを1行ずつ処理スレッドに送る。キーボードから入力するコマンドに加えて、
+// WAIT(待つ)、SET(変数)、RUN(別のスクリプトの実行)と、#から行末までのコメントが使える。
+use crate::command::{command_decode, InternalCommand, UiCommand};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+const MAX_DEPTH: usize = 8;     // RUNで呼び出せる深さ(自分自身を呼び出した時に止める。)
+
+/// スクリプトの1行
+pub enum ScriptLine {
+    Command(UiCommand),         // キーボードから入力するのと同じコマンド
+    Wait(Duration),             // WAIT ミリ秒
+    Set(String, String),        // SET 名前 値(以降の行の$名前を値に置き換える。)
+    Run(PathBuf),               // RUN ファイル
+    Empty,                      // 空行とコメント
+}
+
+/// スクリプトの1行を読む。変数は先にsubstituteで置き換えておく。
+///
+/// ```
+/// use thsdr::script::ScriptLine;
+/// use std::time::Duration;
+///
+/// assert!(matches!("WAIT 500  # 0.5秒待つ".parse(), Ok(ScriptLine::Wait(d)) if d == Duration::from_millis(500)));
+/// assert!(matches!("SET band USB 3".parse(), Ok(ScriptLine::Set(name, value)) if name == "band" && value == "USB 3"));
+/// assert!(matches!("# コメント".parse(), Ok(ScriptLine::Empty)));
+/// assert!(matches!("AGC 2".parse(), Ok(ScriptLine::Command(_))));
+/// assert!("FOO".parse::<ScriptLine>().is_err());
+/// ```
+impl FromStr for ScriptLine {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<ScriptLine, Self::Err> {
+        let line = input.split('#').next().unwrap_or("").trim();
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            [] => Ok(ScriptLine::Empty),
+            ["WAIT", ms] => ms.parse().map(|ms| ScriptLine::Wait(Duration::from_millis(ms)))
+                .map_err(|_| anyhow::anyhow!("WAITの値が正しくありません: {}", ms)),
+            ["SET", name, value @ ..] if is_name(name) && !value.is_empty() => Ok(ScriptLine::Set(name.to_string(), value.join(" "))),
+            ["RUN", path] => Ok(ScriptLine::Run(PathBuf::from(path))),
+            _ => line.parse().map(ScriptLine::Command),
+        }
+    }
+}
+
+/// 行の中の$名前を、SETで設定した値に置き換える。設定していない変数はエラーにする。$$は$になる。
+///
+/// ```
+/// use std::collections::HashMap;
+/// use thsdr::script::substitute;
+///
+/// let variables = HashMap::from([("level".to_string(), "-40".to_string())]);
+/// assert_eq!(substitute("IF RSSI > $level AF 3", &variables).unwrap(), "IF RSSI > -40 AF 3");
+/// assert!(substitute("BFO $bfo", &variables).is_err());
+/// ```
+pub fn substitute( line: &str, variables: &HashMap<String, String> ) -> Result<String, anyhow::Error> {
+    let mut output = String::new();
+    let mut rest = line;
+    while let Some(pos) = rest.find('$') {
+        output.push_str( &rest[..pos] );
+        rest = &rest[pos + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            output.push('$');
+            rest = after;
+            continue;
+        }
+        let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        let name = &rest[..end];
+        match variables.get(name) {
+            Some(value) => output.push_str( value ),
+            None => anyhow::bail!("変数が設定されていません: ${}", name),
+        }
+        rest = &rest[end..];
+    }
+    output.push_str( rest );
+    Ok(output)
+}
+
+/// スクリプトのファイルを実行する。コマンドはtxで処理スレッドに送り、終わるまで(WAITの間も)戻らない。
+/// 読めない行があると、その行で止めてエラーを返す。EXITを送った時と、処理スレッドが終了している時はそこで終わる。
+/// 変数の置き換えは、#から行末までのコメントを除いてから行う。
+///
+/// ```
+/// use std::sync::mpsc::channel;
+/// use thsdr::command::InternalCommand;
+///
+/// let path = std::env::temp_dir().join("thsdr_script_doctest.txt");
+/// std::fs::write(&path, "SET agc 2\nAGC $agc\nWAIT 10\nIF RSSI < -60 AF 3\nAGC 2  # costs $5\n").unwrap();
+/// let (tx, rx) = channel();
+/// thsdr::script::run_script(&path, &tx).unwrap();
+/// assert!(matches!(rx.try_recv(), Ok(InternalCommand::AGC(_))));
+/// assert!(matches!(rx.try_recv(), Ok(InternalCommand::IF(_, _))));
+/// assert!(matches!(rx.try_recv(), Ok(InternalCommand::AGC(_))));     // コメントの$5は変数にしない。
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn run_script( path: &Path, tx: &Sender<InternalCommand> ) -> Result<(), anyhow::Error> {
+    let mut variables: HashMap<String, String> = HashMap::new();
+    run_file( path, tx, &mut variables, 0 )?;
+    Ok(())
+}
+
+// スクリプトを1行ずつ実行する。RUNで呼び出したスクリプトとは変数を共有する。
+// 続けて実行してよい時はtrue、EXITで終わった時はfalseを返す。
+fn run_file( path: &Path, tx: &Sender<InternalCommand>, variables: &mut HashMap<String, String>, depth: usize ) -> Result<bool, anyhow::Error> {
+    if depth >= MAX_DEPTH {
+        anyhow::bail!("RUNの呼び出しが深すぎます: {}", path.display());
+    }
+    let text = fs::read_to_string( path ).map_err(|e| anyhow::anyhow!("{}を読み込めません: {}", path.display(), e))?;
+    for (n, line) in text.lines().enumerate() {
+        // コメントの中の$は変数にしないので、コメントを除いてから置き換える。
+        let line = line.split('#').next().unwrap_or("");
+        let line: ScriptLine = substitute( line, variables ).and_then(|line| line.parse())
+            .map_err(|e| anyhow::anyhow!("{}の{}行目: {}", path.display(), n + 1, e))?;
+        match line {
+            ScriptLine::Command(command) => {
+                let command = command_decode( command );
+                let exit = matches!(command, InternalCommand::EXIT);
+                if tx.send( command ).is_err() || exit {
+                    return Ok(false);
+                }
+            },
+            ScriptLine::Wait(duration) => thread::sleep( duration ),
+            ScriptLine::Set(name, value) => { variables.insert( name, value ); },
+            ScriptLine::Run(path) => {
+                if !run_file( &path, tx, variables, depth + 1 )? {
+                    return Ok(false);
+                }
+            },
+            ScriptLine::Empty => {},
+        }
+    }
+    Ok(true)
+}
+
+// 変数の名前(英数字と_)
+fn is_name( name: &str ) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -0,0 +1,223 @@
+// スレッド間でチャンクを渡すロックフリーのキュー(送る側と受け取る側が1つずつ)
+// サウンドカードのコールバックはリアルタイムで動くので、待ったりロックを取ったりしないように、
+// キューが一杯の時は捨てて数え、空の時はすぐに戻る。サンプルはf32のビット列をアトミックに読み書きする。
+use crate::constants::CHUNK_SIZE;
+use crate::audio::StereoChunk;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(1);     // 空のキューを待つ時の確認の間隔
+
+// 送る側と受け取る側で共有する部分
+struct Shared {
+    buffer: Vec<AtomicU32>,         // capacity + 1 チャンク分(1つは満杯と空を区別するために空けておく)
+    slots: usize,
+    head: AtomicUsize,              // 次に読み出すチャンク(受け取る側だけが進める)
+    tail: AtomicUsize,              // 次に書き込むチャンク(送る側だけが進める)
+    overruns: AtomicU64,            // 一杯で捨てたチャンク数
+    underruns: AtomicU64,           // 受け取る側が間に合わずに途切れた回数
+    closed: AtomicBool,             // どちらかが無くなった
+}
+
+/// キューの状態
+#[derive(Clone, Copy, Debug)]
+pub struct QueueStats {
+    pub depth: usize,               // 溜まっているチャンク数
+    pub capacity: usize,
+    pub overruns: u64,
+    pub underruns: u64,
+}
+
+impl Shared {
+    fn depth(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + self.slots - head) % self.slots
+    }
+
+    fn stats(&self) -> QueueStats {
+        QueueStats {
+            depth: self.depth(),
+            capacity: self.slots - 1,
+            overruns: self.overruns.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// チャンクを送る側
+pub struct ChunkProducer {
+    shared: Arc<Shared>,
+}
+
+/// チャンクを受け取る側
+pub struct ChunkConsumer {
+    shared: Arc<Shared>,
+}
+
+/// capacityチャンクまで溜められるキューをつくる。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::spsc::chunk_queue;
+///
+/// let (mut tx, mut rx) = chunk_queue(1);
+/// assert!(tx.push(&[[0.5; CHUNK_SIZE], [0.25; CHUNK_SIZE]]));
+/// assert!(!tx.push(&[[1.0; CHUNK_SIZE]; 2]));     // 一杯なので捨てる。
+/// assert_eq!(tx.stats().overruns, 1);
+/// assert_eq!(rx.pop().map(|[l, r]| (l[0], r[0])), Some((0.5, 0.25)));
+/// assert!(rx.pop().is_none());
+///
+/// drop(tx);
+/// assert!(rx.wait_pop().is_none());       // 送る側が無くなると待たずに戻る。
+/// ```
+pub fn chunk_queue(capacity: usize) -> (ChunkProducer, ChunkConsumer) {
+    let slots = capacity.max(1) + 1;
+    let shared = Arc::new(Shared {
+        buffer: (0..slots * 2 * CHUNK_SIZE).map(|_| AtomicU32::new(0)).collect(),
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        overruns: AtomicU64::new(0),
+        underruns: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+    });
+    (ChunkProducer { shared: shared.clone() }, ChunkConsumer { shared })
+}
+
+impl ChunkProducer {
+    /// チャンクを入れる。一杯の時は捨てて溢れた数を数え、falseを返す。
+    pub fn push(&mut self, chunk: &StereoChunk) -> bool {
+        let shared = &self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % shared.slots;
+        if next == shared.head.load(Ordering::Acquire) {
+            shared.overruns.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let slot = &shared.buffer[tail * 2 * CHUNK_SIZE..(tail + 1) * 2 * CHUNK_SIZE];
+        for (s, &x) in slot.iter().zip(chunk.iter().flatten()) {
+            s.store(x.to_bits(), Ordering::Relaxed);
+        }
+        shared.tail.store(next, Ordering::Release);
+        true
+    }
+
+    /// キューの状態(溜まっているチャンク数、容量、溢れと途切れの回数)
+    ///
+    /// ```
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::spsc::chunk_queue;
+    ///
+    /// let (mut tx, _rx) = chunk_queue(4);
+    /// tx.push(&[[0.0; CHUNK_SIZE]; 2]);
+    /// let stats = tx.stats();
+    /// assert_eq!((stats.depth, stats.capacity, stats.overruns, stats.underruns), (1, 4, 0, 0));
+    /// ```
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+}
+
+impl ChunkConsumer {
+    /// チャンクを取り出す。空の時はすぐにNoneを返す。
+    ///
+    /// ```
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::spsc::chunk_queue;
+    ///
+    /// let (mut tx, mut rx) = chunk_queue(4);
+    /// assert!(rx.pop().is_none());
+    /// tx.push(&[[0.5; CHUNK_SIZE], [-0.5; CHUNK_SIZE]]);
+    /// let [left, right] = rx.pop().unwrap();
+    /// assert_eq!((left[CHUNK_SIZE - 1], right[0]), (0.5, -0.5));
+    /// ```
+    pub fn pop(&mut self) -> Option<StereoChunk> {
+        let shared = &self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        if head == shared.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let slot = &shared.buffer[head * 2 * CHUNK_SIZE..(head + 1) * 2 * CHUNK_SIZE];
+        let mut chunk: StereoChunk = [[0.0; CHUNK_SIZE]; 2];
+        for (x, s) in chunk.iter_mut().flatten().zip(slot.iter()) {
+            *x = f32::from_bits(s.load(Ordering::Relaxed));
+        }
+        shared.head.store((head + 1) % shared.slots, Ordering::Release);
+        Some(chunk)
+    }
+
+    /// チャンクが来るまで待って取り出す。送る側が無くなって空になった時はNoneを返す。
+    /// リアルタイムのスレッド(サウンドカードのコールバック)からは使わない。
+    ///
+    /// ```
+    /// use std::thread;
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::spsc::chunk_queue;
+    ///
+    /// let (mut tx, mut rx) = chunk_queue(4);
+    /// let sender = thread::spawn(move || {
+    ///     tx.push(&[[1.0; CHUNK_SIZE]; 2]);
+    /// });
+    /// assert_eq!(rx.wait_pop().map(|[l, _]| l[0]), Some(1.0));
+    /// sender.join().unwrap();
+    /// assert!(rx.wait_pop().is_none());       // 送る側が無くなった。
+    /// ```
+    pub fn wait_pop(&mut self) -> Option<StereoChunk> {
+        loop {
+            if let Some(chunk) = self.pop() {
+                return Some(chunk);
+            }
+            if self.shared.closed.load(Ordering::Acquire) && self.shared.depth() == 0 {
+                return None;
+            }
+            thread::sleep(WAIT_INTERVAL);
+        }
+    }
+
+    /// 間に合わずに出力が途切れたことを記録する。
+    ///
+    /// ```
+    /// use thsdr::spsc::chunk_queue;
+    ///
+    /// let (tx, rx) = chunk_queue(4);
+    /// rx.count_underrun();
+    /// assert_eq!(tx.stats().underruns, 1);      // 送る側からも見える。
+    /// ```
+    pub fn count_underrun(&self) {
+        self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// キューの状態
+    ///
+    /// ```
+    /// use thsdr::constants::CHUNK_SIZE;
+    /// use thsdr::spsc::chunk_queue;
+    ///
+    /// let (mut tx, mut rx) = chunk_queue(2);
+    /// tx.push(&[[0.0; CHUNK_SIZE]; 2]);
+    /// tx.push(&[[0.0; CHUNK_SIZE]; 2]);
+    /// assert_eq!((rx.stats().depth, rx.stats().capacity), (2, 2));
+    /// rx.pop();
+    /// rx.count_underrun();
+    /// let stats = rx.stats();
+    /// assert_eq!((stats.depth, stats.overruns, stats.underruns), (1, 0, 1));
+    /// ```
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+}
+
+impl Drop for ChunkProducer {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for ChunkConsumer {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,202 @@
+// システムで使う定数の読み込み
+use crate::constants::{CHUNK_SIZE, SAMPLING_FREQ};
+use crate::firfilter::design_lowpass;
+
+
+// スケルチ関連の定数の定義
+const CHUNK_TIME: f32 = CHUNK_SIZE as f32 / SAMPLING_FREQ;
+const NOISE_TAPS: usize = 63;
+const NOISE_CUTOFF: f32 = 4000.0;       // 音声の帯域より上の雑音を測る。
+const NOISE_REF_DB: f32 = -2.0;         // 無信号の時のFM復調後の雑音の電力[dB]
+const FLOOR_RISE_CLOSED: f32 = 3.0;     // ノイズフロアが上がる速さ[dB/秒](スケルチが閉じている時)
+const FLOOR_RISE_OPEN: f32 = 0.01;      // 信号を受信している間は、ノイズフロアをほとんど上げない。
+const SMOOTHING: f32 = 0.3;             // チャンクごとの測定値の平滑化
+
+/// スケルチの判定方法
+#[derive(Clone, Copy, PartialEq)]
+pub enum SquelchMode {
+    Level,      // RSSI[dB]がレベル以上で開く。
+    Noise,      // FM復調後の雑音が、無信号の時からレベル[dB]以上減ったら開く。
+    SNR,        // RSSIがノイズフロアよりレベル[dB]以上高ければ開く。
+    None,
+}
+
+/// スケルチの設定
+/// 開く時はlevel、閉じる時はlevel - hysteresisで判定し、閉じてからもtail\[秒\]の間は開いたままにする。
+#[derive(Clone, Copy, PartialEq)]
+pub struct SquelchParams {
+    pub mode: SquelchMode,
+    pub level: f32,
+    pub hysteresis: f32,
+    pub tail: f32,
+}
+
+impl SquelchParams {
+    pub const OFF: SquelchParams = SquelchParams { mode: SquelchMode::None, level: 0.0, hysteresis: 0.0, tail: 0.0 };
+}
+
+/// スケルチ
+/// チャンクごとにAGCのRSSIと検波後の音声を受け取り、音声を出すかどうか(開いているか)を返す。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::squelch::{create_squelch, SquelchMode, SquelchParams};
+///
+/// // RSSIが-20[dB]を超えると開く。
+/// let params = SquelchParams { mode: SquelchMode::Level, level: -20.0, hysteresis: 2.0, tail: 0.0 };
+/// let mut squelch = create_squelch(params);
+/// assert!(squelch(0.5, &[0.0; CHUNK_SIZE]));
+/// ```
+pub fn create_squelch(params: SquelchParams) -> impl FnMut(f32, &[f32]) -> bool {
+
+    #[cfg(debug_assertions)]
+    debug_squelch_print( &params );
+
+    // 雑音の測定用のハイパスフィルタ(遅延のみのフィルタからローパスを引く)
+    let mut coefficients: Vec<f32> = design_lowpass(NOISE_CUTOFF, NOISE_TAPS).iter().map(|c| -c).collect();
+    coefficients[NOISE_TAPS / 2] += 1.0;
+    let mut history: [f32; 2 * NOISE_TAPS] = [0.0; 2 * NOISE_TAPS];
+    let mut pos = 0;
+
+    let mut smoothed_rssi: Option<f32> = None;
+    let mut floor: Option<f32> = None;
+    let mut value: Option<f32> = None;
+    let mut open = false;
+    let mut tail_left: f32 = 0.0;
+
+    move |rssi: f32, input: &[f32]| -> bool {
+        let rssi_db = 20.0 * rssi.max(1e-10).log10();
+
+        // 判定方法ごとの測定値(大きいほど信号が強い)
+        let measured = match params.mode {
+            SquelchMode::Level => rssi_db,
+            SquelchMode::Noise => {
+                let mut power: f32 = 0.0;
+                for &x in input {
+                    pos = if pos == 0 { NOISE_TAPS - 1 } else { pos - 1 };
+                    history[pos] = x;
+                    history[pos + NOISE_TAPS] = x;
+                    let y: f32 = history[pos..pos + NOISE_TAPS].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+                    power += y * y;
+                }
+                let noise_db = 10.0 * (power / input.len() as f32).max(1e-10).log10();
+                NOISE_REF_DB - noise_db
+            },
+            SquelchMode::SNR => {
+                // ノイズフロアは平滑化したRSSIの最小値に合わせ、ゆっくり上げて雑音の変化に追従する。
+                let s = smoothed_rssi.map_or(rssi_db, |s| s + (rssi_db - s) * SMOOTHING);
+                smoothed_rssi = Some(s);
+                let rise = if open { FLOOR_RISE_OPEN } else { FLOOR_RISE_CLOSED } * CHUNK_TIME;
+                let f = floor.map_or(s, |f| (f + rise).min(s));
+                floor = Some(f);
+                rssi_db - f
+            },
+            SquelchMode::None => return true,
+        };
+        let v = value.map_or(measured, |v| v + (measured - v) * SMOOTHING);
+        value = Some(v);
+
+        let threshold = if open { params.level - params.hysteresis } else { params.level };
+        if v >= threshold {
+            open = true;
+            tail_left = params.tail;
+        } else if open {
+            tail_left -= CHUNK_TIME;
+            if tail_left <= 0.0 {
+                open = false;
+            }
+        }
+        open
+    }
+}
+
+#[cfg(debug_assertions)]
+fn debug_squelch_print( params: &SquelchParams ) {
+    match params.mode {
+        SquelchMode::Level => println!("SQL LEVEL {}dB", params.level),
+        SquelchMode::Noise => println!("SQL NOISE {}dB", params.level),
+        SquelchMode::SNR => println!("SQL SNR {}dB", params.level),
+        SquelchMode::None => println!("SQL OFF"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fmdemod::create_fm_demod;
+    use crate::testsignal::gaussian_noise;
+    use core::f32::consts::PI;
+
+    const SILENCE: [f32; CHUNK_SIZE] = [0.0; CHUNK_SIZE];
+
+    fn level(db: f32) -> f32 {
+        10.0_f32.powf(db / 20.0)
+    }
+
+    // RSSI[dB]をchunks回与えて、最後に開いていたかを返す。
+    fn feed(squelch: &mut impl FnMut(f32, &[f32]) -> bool, db: f32, chunks: usize) -> bool {
+        (0..chunks).fold(false, |_, _| squelch(level(db), &SILENCE))
+    }
+
+    #[test]
+    fn level_stays_open_within_hysteresis() {
+        let mut squelch = create_squelch(SquelchParams { mode: SquelchMode::Level, level: -20.0, hysteresis: 4.0, tail: 0.0 });
+        assert!(!feed(&mut squelch, -22.0, 20));         // 閉じている時は-20dBに届かなければ開かない。
+        assert!(feed(&mut squelch, -10.0, 20));
+        assert!(feed(&mut squelch, -22.0, 50));          // -24dBと-20dBの間では開いたまま
+        assert!(!feed(&mut squelch, -30.0, 20));
+        assert!(!feed(&mut squelch, -22.0, 50));
+    }
+
+    #[test]
+    fn closes_after_tail() {
+        let tail = 0.5;
+        let mut squelch = create_squelch(SquelchParams { mode: SquelchMode::Level, level: -20.0, hysteresis: 2.0, tail });
+        assert!(feed(&mut squelch, -10.0, 20));
+        let open_chunks = (0..100).take_while(|_| squelch(level(-60.0), &SILENCE)).count();
+        // 平滑化でしきい値を下回るまでの数チャンクと、テールの時間だけ開いている。
+        let tail_chunks = (tail / CHUNK_TIME) as usize;
+        assert!(open_chunks >= tail_chunks && open_chunks <= tail_chunks + 5, "{}", open_chunks);
+    }
+
+    // FM復調した音声(IFは12kHz、キャリアの振幅とガウス雑音の標準偏差を指定する。)
+    fn fm_audio(carrier: f32, noise_sigma: f32, chunks: usize) -> Vec<[f32; CHUNK_SIZE]> {
+        let mut demod = create_fm_demod();
+        let mut noise = gaussian_noise(99);
+        (0..chunks).map(|n| {
+            let input: Vec<f32> = (0..CHUNK_SIZE).map(|i| {
+                let t = (n * CHUNK_SIZE + i) as f32 / SAMPLING_FREQ;
+                carrier * (2.0 * PI * 12000.0 * t).sin() + noise_sigma * noise()
+            }).collect();
+            demod(&input, 12000.0)
+        }).collect()
+    }
+
+    #[test]
+    fn noise_opens_on_quiet_carrier() {
+        let params = SquelchParams { mode: SquelchMode::Noise, level: 6.0, hysteresis: 2.0, tail: 0.0 };
+        let mut squelch = create_squelch(params);
+        assert!(fm_audio(0.0, 0.1, 40).iter().all(|audio| !squelch(1.0, audio)));
+        // RSSIによらず、雑音の減り方で判定する。
+        let mut squelch = create_squelch(params);
+        assert!(fm_audio(0.5, 0.1, 40).iter().skip(10).all(|audio| squelch(1e-6, audio)));
+    }
+
+    #[test]
+    fn snr_follows_noise_floor() {
+        let params = SquelchParams { mode: SquelchMode::SNR, level: 10.0, hysteresis: 2.0, tail: 0.0 };
+
+        // ノイズフロアが下がるとすぐに追従し、同じ-45dBの信号で開く。
+        let mut squelch = create_squelch(params);
+        assert!(!feed(&mut squelch, -40.0, 50));
+        assert!(!feed(&mut squelch, -45.0, 20));
+        assert!(!feed(&mut squelch, -60.0, 50));
+        assert!(feed(&mut squelch, -45.0, 20));
+
+        // 雑音が-52dBに上がると、閉じている間にノイズフロアがゆっくり上がり、-45dBでは開かなくなる。
+        let mut squelch = create_squelch(params);
+        assert!(!feed(&mut squelch, -60.0, 50));
+        assert!(!feed(&mut squelch, -52.0, (4.0 / CHUNK_TIME) as usize));
+        assert!(!feed(&mut squelch, -45.0, 20));
+    }
+}
@@ -0,0 +1,371 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use crate::fmdemod::create_tone_meter;
+use crate::fileout::FileOutput;
+use crate::utc::timestamp;
+use std::sync::mpsc::Sender;
+
+
+// SSTV関連の定数の定義
+const DECIMATION: usize = 4;                // 48[kHz]から12[kHz]に間引いて処理する。
+const RATE: f64 = SAMPLING_FREQ as f64 / DECIMATION as f64;
+const MS: f64 = RATE / 1000.0;              // 1[ms]のサンプル数
+const CENTER_FREQ: f32 = 1700.0;            // 1100～2300[Hz]の中心
+const LOWPASS_CUTOFF: f32 = 1100.0;
+const SYNC_FREQ: f32 = 1200.0;
+const BLACK_FREQ: f32 = 1500.0;
+const WHITE_FREQ: f32 = 2300.0;
+const LEADER_FREQ: f32 = 1900.0;
+const VIS_BIT: usize = (30.0 * MS) as usize;    // VISの1ビット(30[ms])
+const VIS_TOLERANCE: f32 = 60.0;            // VISの各ビットの周波数の許容誤差[Hz]
+const HISTORY: usize = (RATE * 1.0) as usize;   // VISを探すために保持する時間(1秒)
+const SYNC_SEARCH: f64 = 5.0 * MS;          // 同期信号を探す範囲(予測位置の前後5[ms])
+const SYNC_OUTLIER: f64 = 1.5 * MS;         // 予測から外れた同期信号は使わない。
+const SYNC_LOST: usize = 20;                // 同期信号が続けて見つからなければ受信を終える。
+
+// 1ラインの色の送り方
+#[derive(Clone, Copy, PartialEq)]
+enum ColorFormat {
+    Gbr,            // 緑、青、赤の順(Martin、Scottie)
+    Robot36,        // 輝度と、ラインごとに交互のR-Y/B-Y。3つ目は色差の種類を示す区切り信号
+    Robot72,        // 輝度、R-Y、B-Y
+}
+
+// SSTVのモード
+// 時間は[ms]で、各チャンネルの開始位置はそのラインの同期信号の先頭からの時間(Scottieは負になる)。
+struct SstvMode {
+    name: &'static str,
+    vis: u8,
+    width: usize,
+    lines: usize,
+    period: f64,                    // 1ラインの時間
+    first_sync: f64,                // VISの終わりから最初の同期信号までの時間
+    sync: f64,                      // 同期信号の長さ
+    channels: [(f64, f64); 3],      // (開始位置, 長さ)
+    format: ColorFormat,
+}
+
+const MODES: [SstvMode; 6] = [
+    SstvMode { name: "Martin M1", vis: 44, width: 320, lines: 256, period: 446.446, first_sync: 0.0, sync: 4.862,
+               channels: [(5.434, 146.432), (152.438, 146.432), (299.442, 146.432)], format: ColorFormat::Gbr },
+    SstvMode { name: "Martin M2", vis: 40, width: 320, lines: 256, period: 226.798, first_sync: 0.0, sync: 4.862,
+               channels: [(5.434, 73.216), (79.222, 73.216), (153.010, 73.216)], format: ColorFormat::Gbr },
+    SstvMode { name: "Scottie S1", vis: 60, width: 320, lines: 256, period: 428.22, first_sync: 288.48, sync: 9.0,
+               channels: [(-277.98, 138.24), (-138.24, 138.24), (10.5, 138.24)], format: ColorFormat::Gbr },
+    SstvMode { name: "Scottie S2", vis: 56, width: 320, lines: 256, period: 277.692, first_sync: 188.128, sync: 9.0,
+               channels: [(-177.628, 88.064), (-88.064, 88.064), (10.5, 88.064)], format: ColorFormat::Gbr },
+    SstvMode { name: "Robot 36", vis: 8, width: 320, lines: 240, period: 150.0, first_sync: 0.0, sync: 9.0,
+               channels: [(12.0, 88.0), (106.0, 44.0), (100.0, 4.5)], format: ColorFormat::Robot36 },
+    SstvMode { name: "Robot 72", vis: 12, width: 320, lines: 240, period: 300.0, first_sync: 0.0, sync: 9.0,
+               channels: [(12.0, 138.0), (156.0, 69.0), (231.0, 69.0)], format: ColorFormat::Robot72 },
+];
+
+// SSTVデコーダ
+// 音の周波数を測り、VISコードでモードを判定してから、ラインごとの同期信号を追従しながら画像を受信する。
+// 同期信号の位置は直線で近似するので、サウンドカードのクロックの誤差による傾きも補正される。
+// 受信した画像はfile_txに送り、sstv_YYYYMMDD_HHMMSS_mmm.pngに保存する。
+pub fn create_sstv_decoder(file_tx: Sender<FileOutput>) -> impl FnMut(&[f32]) -> String {
+
+    let mut meter = create_tone_meter(CENTER_FREQ, LOWPASS_CUTOFF, DECIMATION);
+
+    // VISの検出
+    let mut history: Vec<f32> = Vec::new();
+    let mut cumsum: Vec<f64> = vec![0.0];
+    let mut vis_run: Option<(usize, u8)> = None;    // VISが一致し続けている区間の先頭とコード
+
+    // 画像の受信
+    let mut mode: Option<&'static SstvMode> = None;
+    let mut samples: Vec<f32> = Vec::new();         // VISの終わりからの周波数
+    let mut syncs: Vec<(f64, f64)> = Vec::new();    // 見つかった同期信号(ライン番号, 位置)
+    let mut lines: Vec<[Vec<u8>; 3]> = Vec::new();
+    let mut missed = 0;
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+
+        for &x in input {
+            let freq = match meter(x) {
+                Some(freq) => freq,
+                None => continue,
+            };
+
+            let m = match mode {
+                Some(m) => m,
+                None => {
+                    history.push(freq);
+                    cumsum.push(cumsum[cumsum.len() - 1] + freq as f64);
+                    let end = history.len();
+                    match (find_vis(&cumsum, end), vis_run) {
+                        (Some(code), None) => vis_run = Some((end, code)),
+                        (None, Some((start, code))) => {
+                            // 一致した区間の中央をVISの終わりとする。
+                            vis_run = None;
+                            match MODES.iter().find(|m| m.vis == code) {
+                                Some(m) => {
+                                    text.push_str(&format!("SSTV {} (VIS {})\n", m.name, code));
+                                    samples = history[(start + end) / 2..].to_vec();
+                                    syncs.clear();
+                                    lines.clear();
+                                    missed = 0;
+                                    mode = Some(m);
+                                },
+                                None => text.push_str(&format!("SSTV unknown VIS {}\n", code)),
+                            }
+                        },
+                        _ => {},
+                    }
+                    // 古いデータを捨てる。
+                    if history.len() > 2 * HISTORY {
+                        history.drain(..HISTORY);
+                        cumsum = std::iter::once(0.0).chain(history.iter().scan(0.0, |s, &f| { *s += f as f64; Some(*s) })).collect();
+                        vis_run = None;
+                    }
+                    continue;
+                },
+            };
+
+            samples.push(freq);
+
+            // 次のラインの最後まで受信したら、同期信号を探して画素を読み取る。
+            let n = lines.len();
+            let line_end = m.channels.iter().map(|(start, length)| start + length).fold(m.sync, f64::max) * MS;
+            let predicted = predict_sync(m, &syncs, n);
+            if (samples.len() as f64) < predicted + line_end + SYNC_SEARCH {
+                continue;
+            }
+
+            match find_sync(&samples, predicted, m.sync * MS) {
+                Some(position) if syncs.len() < 4 || (position - predicted).abs() < SYNC_OUTLIER => {
+                    syncs.push((n as f64, position));
+                    missed = 0;
+                },
+                _ => missed += 1,
+            }
+            let sync = predict_sync(m, &syncs, n);
+            lines.push(read_line(m, &samples, sync));
+
+            if missed >= SYNC_LOST || lines.len() == m.lines {
+                // 信号が途切れた時は、最後に同期信号が見つかったラインまでを保存する。
+                let count = if missed >= SYNC_LOST { lines.len() - missed } else { lines.len() };
+                if count > 0 {
+                    text.push_str(&save_image(m, &lines[..count], &file_tx));
+                }
+                mode = None;
+                samples.clear();
+                history.clear();
+                cumsum = vec![0.0];
+            }
+        }
+
+        text
+    }
+}
+
+// 最後のサンプルでVISが終わっているかを調べ、終わっていればコードを返す。
+// 1900[Hz]のリーダーの後に、スタートビット(1200[Hz])、7ビットのデータ(LSBから、1100[Hz]が1、1300[Hz]が0)、
+// 偶数パリティ、ストップビット(1200[Hz])が30[ms]ずつ続く。
+fn find_vis(cumsum: &[f64], end: usize) -> Option<u8> {
+    let leader = (250.0 * MS) as usize;
+    if end < 10 * VIS_BIT + leader {
+        return None;
+    }
+    let mean = |from: usize, to: usize| ((cumsum[to] - cumsum[from]) / (to - from) as f64) as f32;
+    let near = |value: f32, freq: f32| (value - freq).abs() < VIS_TOLERANCE;
+
+    let start = end - 10 * VIS_BIT;
+    if !near(mean(start - leader, start - VIS_BIT / 2), LEADER_FREQ) {
+        return None;
+    }
+    // 各ビットの両端は変化の途中なので、中央の2/3で判定する。
+    let margin = VIS_BIT / 6;
+    let bit = |k: usize| mean(start + k * VIS_BIT + margin, start + (k + 1) * VIS_BIT - margin);
+    if !near(bit(0), SYNC_FREQ) || !near(bit(9), SYNC_FREQ) {
+        return None;
+    }
+    let mut code = 0u8;
+    let mut parity = false;
+    for k in 1..9 {
+        let value = bit(k);
+        let one = if near(value, 1100.0) {
+            true
+        } else if near(value, 1300.0) {
+            false
+        } else {
+            return None;
+        };
+        if k < 8 && one {
+            code |= 1 << (k - 1);
+        }
+        parity ^= one;
+    }
+    if parity { None } else { Some(code) }
+}
+
+// これまでに見つかった同期信号を直線で近似して、n番目のラインの同期信号の位置を予測する。
+fn predict_sync(m: &SstvMode, syncs: &[(f64, f64)], n: usize) -> f64 {
+    let period = m.period * MS;
+    let n = n as f64;
+    match syncs.len() {
+        0 => m.first_sync * MS + n * period,
+        1 => syncs[0].1 + (n - syncs[0].0) * period,
+        count => {
+            let count = count as f64;
+            let mean_n = syncs.iter().map(|s| s.0).sum::<f64>() / count;
+            let mean_t = syncs.iter().map(|s| s.1).sum::<f64>() / count;
+            let snn: f64 = syncs.iter().map(|s| (s.0 - mean_n).powi(2)).sum();
+            let snt: f64 = syncs.iter().map(|s| (s.0 - mean_n) * (s.1 - mean_t)).sum();
+            // 傾きはクロックの誤差として±0.5%までに制限する。
+            let slope = if snn > 0.0 { (snt / snn).clamp(period * 0.995, period * 1.005) } else { period };
+            mean_t + (n - mean_n) * slope
+        },
+    }
+}
+
+// 予測位置の前後で同期信号の先頭を探す。
+// 同期信号の長さで1200[Hz]付近のサンプルが多く、その前後に少ない位置を先頭とする。
+fn find_sync(samples: &[f32], predicted: f64, length: f64) -> Option<f64> {
+    let length = length.round() as isize;
+    let is_sync = |k: isize| -> f32 {
+        if k >= 0 && (k as usize) < samples.len() && samples[k as usize] < (SYNC_FREQ + BLACK_FREQ) / 2.0 { 1.0 } else { 0.0 }
+    };
+    let from = (predicted - SYNC_SEARCH).round() as isize;
+    let to = (predicted + SYNC_SEARCH).round() as isize;
+    let mut best: Option<(isize, f32)> = None;
+    for t in from..=to {
+        let inside: f32 = (t..t + length).map(is_sync).sum();
+        let outside: f32 = (t - length / 2..t).chain(t + length..t + length + length / 2).map(is_sync).sum();
+        let score = inside - outside;
+        if inside > 0.7 * length as f32 && best.is_none_or(|(_, s)| score > s) {
+            best = Some((t, score));
+        }
+    }
+    best.map(|(t, _)| t as f64)
+}
+
+// 同期信号の位置からラインの各チャンネルの画素を読み取る。
+fn read_line(m: &SstvMode, samples: &[f32], sync: f64) -> [Vec<u8>; 3] {
+    let read = |(start, length): (f64, f64), width: usize| -> Vec<u8> {
+        let begin = sync + start * MS;
+        let step = length * MS / width as f64;
+        (0..width).map(|i| {
+            let from = (begin + i as f64 * step).round().max(0.0) as usize;
+            let to = ((begin + (i + 1) as f64 * step).round() as usize).max(from + 1).min(samples.len());
+            let from = from.min(to.saturating_sub(1));
+            let mean = samples[from..to].iter().sum::<f32>() / (to - from).max(1) as f32;
+            ((mean - BLACK_FREQ) / (WHITE_FREQ - BLACK_FREQ) * 255.0).round().clamp(0.0, 255.0) as u8
+        }).collect()
+    };
+    match m.format {
+        // Robot 36の区切り信号は、R-Yのラインが1500[Hz]、B-Yのラインが2300[Hz]
+        ColorFormat::Robot36 => [read(m.channels[0], m.width), read(m.channels[1], m.width), read(m.channels[2], 1)],
+        _ => [read(m.channels[0], m.width), read(m.channels[1], m.width), read(m.channels[2], m.width)],
+    }
+}
+
+// 受信したラインをRGBにして保存するスレッドに送り、表示する文字列を返す。
+fn save_image(m: &SstvMode, lines: &[[Vec<u8>; 3]], file_tx: &Sender<FileOutput>) -> String {
+    let mut data: Vec<u8> = Vec::with_capacity(m.width * lines.len() * 3);
+    for (n, line) in lines.iter().enumerate() {
+        for i in 0..m.width {
+            let rgb = match m.format {
+                ColorFormat::Gbr => [line[2][i], line[0][i], line[1][i]],
+                ColorFormat::Robot72 => yuv_to_rgb(line[0][i], line[2][i], line[1][i]),
+                ColorFormat::Robot36 => {
+                    // 隣のラインと組み合わせて、R-YとB-Yをそろえる。
+                    let pair = if n % 2 == 0 { (n + 1).min(lines.len() - 1) } else { n - 1 };
+                    let (v, u) = if line[2][0] < 128 {
+                        (line[1][i], lines[pair][1][i])
+                    } else {
+                        (lines[pair][1][i], line[1][i])
+                    };
+                    yuv_to_rgb(line[0][i], u, v)
+                },
+            };
+            data.extend_from_slice(&rgb);
+        }
+    }
+    let path = format!("sstv_{}.png", timestamp());
+    match file_tx.send(FileOutput::RgbImage { path: path.clone(), width: m.width, height: lines.len(), data }) {
+        Ok(()) => format!("SSTV saved {} {}x{}\n", path, m.width, lines.len()),
+        Err(_) => format!("SSTV save failed {}\n", path),
+    }
+}
+
+// 輝度と色差(B-Y、R-Y)をRGBにする。
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let y = y as f32;
+    let u = u as f32 - 128.0;
+    let v = v as f32 - 128.0;
+    [
+        (y + 1.402 * v).round().clamp(0.0, 255.0) as u8,
+        (y - 0.344136 * u - 0.714136 * v).round().clamp(0.0, 255.0) as u8,
+        (y + 1.772 * u).round().clamp(0.0, 255.0) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CHUNK_SIZE;
+    use crate::testsignal::sstv::{generate, mode_lines, reference_image, WIDTH};
+    use core::f32::consts::PI;
+    use std::sync::mpsc::channel;
+
+    const PARTIAL_LINES: usize = 32;    // 途中で止める時に送るライン数
+
+    // lines本の試験画像を送ってデコーダに通し、デコーダが表示した文字列と、受信した画像と試験画像の画素の差の平均(0～255)を確認する。
+    // モードのライン数より少ない時は、同期信号の無い音を続けて、信号が途切れた時の処理で画像を保存させる。
+    fn check(vis: u8, lines: usize) {
+        let (file_tx, file_rx) = channel();
+        let mut decoder = create_sstv_decoder(file_tx);
+        let image = reference_image(lines);
+        let mut samples = generate(vis, &image);
+        if lines < mode_lines(vis) {
+            let tone = (0..(25.0 * 0.45 * SAMPLING_FREQ) as usize)      // 最も長いラインで25ライン分
+                .map(|i| 0.5 * (2.0 * PI * 1900.0 * i as f32 / SAMPLING_FREQ).sin());
+            samples.extend(tone);
+        }
+        let text: String = samples.chunks(CHUNK_SIZE).map(&mut decoder).collect();
+        assert!(text.contains(&format!("(VIS {})", vis)), "VIS {}: {}", vis, text);
+
+        let Ok(FileOutput::RgbImage { width, height, data, .. }) = file_rx.try_recv() else {
+            panic!("VIS {}: 画像を受信できない: {}", vis, text);
+        };
+        assert_eq!((width, height), (WIDTH, lines), "VIS {}", vis);
+        let error = data.iter().zip(image.iter().flatten())
+            .map(|(&p, &q)| (p as f32 - q as f32).abs())
+            .sum::<f32>() / data.len() as f32;
+        assert!(error < 5.0, "VIS {}: 画素の差の平均 {:.1}", vis, error);
+    }
+
+    #[test]
+    fn decodes_martin_m1() {
+        check(44, PARTIAL_LINES);
+    }
+
+    #[test]
+    fn decodes_martin_m2() {
+        check(40, PARTIAL_LINES);
+    }
+
+    #[test]
+    fn decodes_scottie_s1() {
+        check(60, PARTIAL_LINES);
+    }
+
+    #[test]
+    fn decodes_scottie_s2() {
+        check(56, PARTIAL_LINES);
+    }
+
+    #[test]
+    fn decodes_robot_36() {
+        // 最も短いモードは、最後のラインまで送る。
+        check(8, mode_lines(8));
+    }
+
+    #[test]
+    fn decodes_robot_72() {
+        check(12, PARTIAL_LINES);
+    }
+}
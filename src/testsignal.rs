@@ -0,0 +1,92 @@
+// 試験用の信号をつくる関数(cargo testの時だけコンパイルする)
+// デコーダの試験で共通に使う。
+use crate::constants::SAMPLING_FREQ;
+use crate::cwdecoder::MORSE_TABLE;
+use crate::rtty::{LETTERS, FIGURES, FIGS, LTRS, SPACE};
+use core::f32::consts::PI;
+
+pub mod sstv;
+pub mod wefax;
+
+const RISE_MS: f32 = 5.0;       // キーイングの立ち上がりと立ち下がり
+
+// 平均0、分散1のガウス雑音を返す関数をつくる。同じ種からは同じ雑音になる。
+pub fn gaussian_noise(seed: u32) -> impl FnMut() -> f32 {
+    let mut state = seed;
+    let mut uniform = move || {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        ((state >> 8) as f32 + 1.0) / (1u32 << 24) as f32
+    };
+    move || (-2.0 * uniform().ln()).sqrt() * (2.0 * PI * uniform()).cos()
+}
+
+// 文字列をモールス符号でキーイングした振幅0.5の音と、ガウス雑音を加えた信号をつくる。
+// SNRは、キーダウン中の音の電力と、雑音の全帯域(24kHz)の電力の比[dB]
+pub fn keyed_signal(text: &str, pitch: f32, wpm: f32, snr_db: f32) -> Vec<f32> {
+    const AMPLITUDE: f32 = 0.5;
+    let dit = (1200.0 / wpm * SAMPLING_FREQ / 1000.0) as usize;
+    let mut keying: Vec<bool> = vec![false; SAMPLING_FREQ as usize / 2];
+    for (n, word) in text.split(' ').enumerate() {
+        if n > 0 {
+            keying.extend(std::iter::repeat_n(false, 4 * dit));     // 文字間の3短点と合わせて7短点
+        }
+        for c in word.chars() {
+            let (code, _) = MORSE_TABLE.iter().find(|(_, ch)| *ch == c).unwrap();
+            for element in code.chars() {
+                let length = if element == '.' { dit } else { 3 * dit };
+                keying.extend(std::iter::repeat_n(true, length));
+                keying.extend(std::iter::repeat_n(false, dit));
+            }
+            keying.extend(std::iter::repeat_n(false, 2 * dit));
+        }
+    }
+    keying.extend(std::iter::repeat_n(false, SAMPLING_FREQ as usize));
+
+    let rise = RISE_MS * SAMPLING_FREQ / 1000.0;
+    let noise_sigma = (AMPLITUDE * AMPLITUDE / 2.0 / 10.0_f32.powf(snr_db / 10.0)).sqrt();
+    let mut noise = gaussian_noise(12345);
+    let mut envelope: f32 = 0.0;
+    keying.iter().enumerate().map(|(i, &key)| {
+        envelope = if key { (envelope + 1.0 / rise).min(1.0) } else { (envelope - 1.0 / rise).max(0.0) };
+        AMPLITUDE * envelope * (2.0 * PI * pitch * i as f32 / SAMPLING_FREQ).sin() + noise_sigma * noise()
+    }).collect()
+}
+
+// 文字列をITA2の符号にする。必要な所にLTRSとFIGSを入れる。
+// 受信側は空白で文字に戻る(USOS)ので、空白の後に数字が続く時はFIGSを送り直す。CRは0x08にする。
+pub fn ita2_encode(text: &str) -> Vec<u8> {
+    let mut codes = vec![LTRS];
+    let mut figures = false;
+    for c in text.chars() {
+        if c == '\r' {
+            codes.push(0x08);
+            continue;
+        }
+        let find = |table: &[Option<char>; 32]| table.iter().position(|&t| t == Some(c)).map(|code| code as u8);
+        let current = if figures { find(&FIGURES) } else { find(&LETTERS) };
+        let code = match current {
+            Some(code) => code,
+            None => {
+                figures = !figures;
+                codes.push(if figures { FIGS } else { LTRS });
+                if figures { find(&FIGURES) } else { find(&LETTERS) }.expect("ITA2に無い文字です。")
+            },
+        };
+        codes.push(code);
+        if code == SPACE {
+            figures = false;
+        }
+    }
+    codes
+}
+
+// ビット列(trueがマーク)を位相が連続したFSKの音にする(振幅0.5)。
+pub fn fsk_signal(bits: &[bool], mark: f32, space: f32, baud: f32) -> Vec<f32> {
+    let samples = (bits.len() as f32 * SAMPLING_FREQ / baud) as usize;
+    let mut phase = 0.0f32;
+    (0..samples).map(|i| {
+        let bit = bits[((i as f32 * baud / SAMPLING_FREQ) as usize).min(bits.len() - 1)];
+        phase = (phase + 2.0 * PI * if bit { mark } else { space } / SAMPLING_FREQ) % (2.0 * PI);
+        0.5 * phase.sin()
+    }).collect()
+}
@@ -0,0 +1,140 @@
+// SSTVの試験用信号
+// 試験画像(カラーバーと階調)をVISコードとともにSSTVの音声にする。
+// examples/sstv_gen.rsからも読み込んで、WAVファイルに出力する。
+// 対応するVISコード: Martin M1 44、M2 40、Scottie S1 60、S2 56、Robot 36 8、Robot 72 12
+use super::SAMPLING_FREQ;
+use core::f64::consts::PI;
+
+pub const WIDTH: usize = 320;
+
+// 音の周波数と長さ[ms]を順に並べて、位相が連続したFMの音声にする。
+struct ToneWriter {
+    samples: Vec<f32>,
+    phase: f64,
+    time: f64,          // これまでに書いた時間[サンプル]
+}
+
+impl ToneWriter {
+    fn tone(&mut self, freq: f64, ms: f64) {
+        self.time += ms * SAMPLING_FREQ as f64 / 1000.0;
+        while (self.samples.len() as f64) < self.time {
+            self.phase = (self.phase + 2.0 * PI * freq / SAMPLING_FREQ as f64) % (2.0 * PI);
+            self.samples.push(0.5 * self.phase.sin() as f32);
+        }
+    }
+
+    fn silence(&mut self, ms: f64) {
+        self.time += ms * SAMPLING_FREQ as f64 / 1000.0;
+        self.samples.resize(self.time as usize, 0.0);
+    }
+
+    // 画素の値(0～255)を1500～2300Hzにして、1ライン分をmsの時間で送る。
+    fn pixels(&mut self, values: &[u8], ms: f64) {
+        for &v in values {
+            self.tone(1500.0 + 800.0 * v as f64 / 255.0, ms / values.len() as f64);
+        }
+    }
+}
+
+// 試験画像(上半分はカラーバー、下半分は左から右への灰色の階調)
+pub fn reference_image(lines: usize) -> Vec<[u8; 3]> {
+    const BARS: [[u8; 3]; 8] = [
+        [255, 255, 255], [255, 255, 0], [0, 255, 255], [0, 255, 0],
+        [255, 0, 255], [255, 0, 0], [0, 0, 255], [0, 0, 0],
+    ];
+    let mut image = Vec::with_capacity(WIDTH * lines);
+    for y in 0..lines {
+        for x in 0..WIDTH {
+            if y < lines / 2 {
+                image.push(BARS[x * 8 / WIDTH]);
+            } else {
+                let v = (x * 255 / (WIDTH - 1)) as u8;
+                image.push([v, v, v]);
+            }
+        }
+    }
+    image
+}
+
+// モードのライン数
+pub fn mode_lines(vis: u8) -> usize {
+    if vis == 8 || vis == 12 { 240 } else { 256 }
+}
+
+// 画像をSSTVの音声にする。
+pub fn generate(vis: u8, image: &[[u8; 3]]) -> Vec<f32> {
+    let mut w = ToneWriter { samples: Vec::new(), phase: 0.0, time: 0.0 };
+    let lines = image.len() / WIDTH;
+
+    // 無音、リーダー、VIS
+    w.silence(500.0);
+    w.tone(1900.0, 300.0);
+    w.tone(1200.0, 10.0);
+    w.tone(1900.0, 300.0);
+    w.tone(1200.0, 30.0);
+    let mut parity = false;
+    for k in 0..7 {
+        let one = vis & (1 << k) != 0;
+        parity ^= one;
+        w.tone(if one { 1100.0 } else { 1300.0 }, 30.0);
+    }
+    w.tone(if parity { 1100.0 } else { 1300.0 }, 30.0);
+    w.tone(1200.0, 30.0);
+
+    if vis == 60 || vis == 56 {
+        w.tone(1200.0, 9.0);     // Scottieの最初の同期信号
+    }
+    for y in 0..lines {
+        let row = &image[y * WIDTH..(y + 1) * WIDTH];
+        let r: Vec<u8> = row.iter().map(|p| p[0]).collect();
+        let g: Vec<u8> = row.iter().map(|p| p[1]).collect();
+        let b: Vec<u8> = row.iter().map(|p| p[2]).collect();
+        let luma: Vec<f64> = row.iter().map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64).collect();
+        let yy: Vec<u8> = luma.iter().map(|&l| l.round() as u8).collect();
+        let u: Vec<u8> = row.iter().zip(luma.iter()).map(|(p, &l)| (128.0 + (p[2] as f64 - l) / 1.772).round().clamp(0.0, 255.0) as u8).collect();
+        let v: Vec<u8> = row.iter().zip(luma.iter()).map(|(p, &l)| (128.0 + (p[0] as f64 - l) / 1.402).round().clamp(0.0, 255.0) as u8).collect();
+
+        match vis {
+            44 | 40 => {
+                let tc = if vis == 44 { 146.432 } else { 73.216 };
+                w.tone(1200.0, 4.862);
+                w.tone(1500.0, 0.572);
+                for channel in [&g, &b, &r] {
+                    w.pixels(channel, tc);
+                    w.tone(1500.0, 0.572);
+                }
+            },
+            60 | 56 => {
+                let tc = if vis == 60 { 138.24 } else { 88.064 };
+                w.tone(1500.0, 1.5);
+                w.pixels(&g, tc);
+                w.tone(1500.0, 1.5);
+                w.pixels(&b, tc);
+                w.tone(1200.0, 9.0);
+                w.tone(1500.0, 1.5);
+                w.pixels(&r, tc);
+            },
+            8 => {
+                w.tone(1200.0, 9.0);
+                w.tone(1500.0, 3.0);
+                w.pixels(&yy, 88.0);
+                w.tone(if y % 2 == 0 { 1500.0 } else { 2300.0 }, 4.5);
+                w.tone(1900.0, 1.5);
+                w.pixels(if y % 2 == 0 { &v } else { &u }, 44.0);
+            },
+            _ => {
+                w.tone(1200.0, 9.0);
+                w.tone(1500.0, 3.0);
+                w.pixels(&yy, 138.0);
+                w.tone(1500.0, 4.5);
+                w.tone(1900.0, 1.5);
+                w.pixels(&v, 69.0);
+                w.tone(2300.0, 4.5);
+                w.tone(1900.0, 1.5);
+                w.pixels(&u, 69.0);
+            },
+        }
+    }
+    w.tone(1500.0, 300.0);
+    w.samples
+}
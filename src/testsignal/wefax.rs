@@ -0,0 +1,52 @@
+// WEFAXの試験用信号
+// 開始信号、位相合わせ、試験画像、停止信号を順に並べたWEFAXの音声をつくる。
+// examples/wefax_gen.rsからも読み込んで、WAVファイルに出力する。
+use super::SAMPLING_FREQ;
+use core::f32::consts::PI;
+
+// WEFAXの音声を作る。画素の値は0.0(黒、1500Hz)～1.0(白、2300Hz)。
+pub fn generate(lpm: f32, ioc: u32, lines: usize) -> Vec<f32> {
+    let line_samples = SAMPLING_FREQ * 60.0 / lpm;
+    let start_tone = if ioc == 288 { 675.0 } else { 300.0 };
+    let mut values: Vec<f32> = Vec::new();
+
+    // 開始信号(5秒)、位相合わせ(30秒)、画像、停止信号(5秒)、黒(10秒)
+    push_tone(&mut values, start_tone, 5.0);
+    let phasing_lines = (30.0 * lpm / 60.0) as usize;
+    for _ in 0..phasing_lines {
+        for i in 0..line_samples as usize {
+            let x = i as f32 / line_samples;
+            values.push(if !(0.025..0.975).contains(&x) { 1.0 } else { 0.0 });
+        }
+    }
+    for line in 0..lines {
+        for i in 0..line_samples as usize {
+            values.push(test_chart(i as f32 / line_samples, line as f32 / lines as f32));
+        }
+    }
+    push_tone(&mut values, 450.0, 5.0);
+    values.resize(values.len() + (SAMPLING_FREQ * 10.0) as usize, 0.0);
+
+    // 画素の値を周波数にしてFM変調する。
+    let mut phase = 0.0f32;
+    values.iter().map(|&v| {
+        phase += 2.0 * PI * (1500.0 + 800.0 * v) / SAMPLING_FREQ;
+        if phase > 2.0 * PI { phase -= 2.0 * PI; }
+        0.5 * phase.sin()
+    }).collect()
+}
+
+// 黒と白を交互に繰り返す開始/停止信号
+fn push_tone(values: &mut Vec<f32>, freq: f32, seconds: f32) {
+    for i in 0..(SAMPLING_FREQ * seconds) as usize {
+        let cycle = i as f32 * freq / SAMPLING_FREQ;
+        values.push(if cycle.fract() < 0.5 { 1.0 } else { 0.0 });
+    }
+}
+
+// 試験画像(左から右への階調、格子、対角線)
+pub fn test_chart(x: f32, y: f32) -> f32 {
+    if (x - y).abs() < 0.004 { return 0.0; }
+    if (x * 10.0).fract() < 0.01 || (y * 10.0).fract() < 0.02 { return 0.0; }
+    if y < 0.5 { (x * 8.0).floor() / 7.0 } else { 1.0 }
+}
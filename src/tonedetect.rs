@@ -0,0 +1,485 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use crate::firfilter::design_lowpass;
+use std::collections::HashMap;
+use core::f32::consts::PI;
+
+
+// トーン検出関連の定数の定義
+const DECIMATION: usize = 24;               // CTCSS/DCSは48[kHz]から2[kHz]に間引いて処理する。
+const RATE: f32 = SAMPLING_FREQ / DECIMATION as f32;
+const LPF_TAPS: usize = 1023;               // 音声の成分を十分に落とすため、長いフィルタにする。
+const LPF_CUTOFF: f32 = 280.0;
+const CTCSS_WINDOW: usize = 2000;           // 1秒の窓で、隣り合うトーン(最小2.3[Hz]差)を区別する。
+const CTCSS_STEP: usize = 500;              // 0.25秒ごとに判定する。
+const CTCSS_DETECT: f32 = 0.5;              // 300[Hz]以下の電力のうち、トーンの電力の割合
+const CTCSS_HOLD: f32 = 0.3;                // 検出中のトーンは、この割合まで保持する。
+const CTCSS_TOLERANCE: f32 = 0.01;          // トーンの周波数の誤差(±1%)
+const CTCSS_OFFSETS: i32 = 4;               // 誤差の範囲を片側この数に分けて調べる。
+const DCS_BAUD: f32 = 134.4;
+const DCS_BITS: u32 = 23;
+const DCS_PLL_GAIN: f32 = 0.1;
+const DCS_PEAK_DECAY: f32 = 0.001;          // 判定のしきい値を求める、最大値と最小値の追従の速さ
+const DTMF_BLOCK: usize = 1200;             // 25[ms]ごとにGoertzelで判定する。
+const DTMF_LEVEL: f32 = 0.7;                // 全体の電力のうち、行と列の2つの音の電力の割合
+const DTMF_MIN_TONE: f32 = 0.1;             // 2つの音の強さの差(ツイスト)を8[dB]程度までにする。
+const DTMF_END_BLOCKS: u32 = 40;            // 1秒間数字が無ければ、それまでの数字をまとめて表示する。
+
+// CTCSSの標準の50波
+pub const CTCSS_TONES: [f32; 50] = [
+    67.0, 69.3, 71.9, 74.4, 77.0, 79.7, 82.5, 85.4, 88.5, 91.5,
+    94.8, 97.4, 100.0, 103.5, 107.2, 110.9, 114.8, 118.8, 123.0, 127.3,
+    131.8, 136.5, 141.3, 146.2, 151.4, 156.7, 159.8, 162.2, 165.5, 167.9,
+    171.3, 173.8, 177.3, 179.9, 183.5, 186.2, 189.9, 192.8, 196.6, 199.5,
+    203.5, 206.5, 210.7, 218.1, 225.7, 229.1, 233.6, 241.8, 250.3, 254.1,
+];
+
+// DCSの標準の104コード(8進数)
+pub const DCS_CODES: [u16; 104] = [
+    0o023, 0o025, 0o026, 0o031, 0o032, 0o036, 0o043, 0o047, 0o051, 0o053, 0o054, 0o065, 0o071,
+    0o072, 0o073, 0o074, 0o114, 0o115, 0o116, 0o122, 0o125, 0o131, 0o132, 0o134, 0o143, 0o145,
+    0o152, 0o155, 0o156, 0o162, 0o165, 0o172, 0o174, 0o205, 0o212, 0o223, 0o225, 0o226, 0o243,
+    0o244, 0o245, 0o246, 0o251, 0o252, 0o255, 0o261, 0o263, 0o265, 0o266, 0o271, 0o274, 0o306,
+    0o311, 0o315, 0o325, 0o331, 0o332, 0o343, 0o346, 0o351, 0o356, 0o364, 0o365, 0o371, 0o411,
+    0o412, 0o413, 0o423, 0o431, 0o432, 0o445, 0o446, 0o452, 0o454, 0o455, 0o462, 0o464, 0o465,
+    0o466, 0o503, 0o506, 0o516, 0o523, 0o526, 0o532, 0o546, 0o565, 0o606, 0o612, 0o624, 0o627,
+    0o631, 0o632, 0o654, 0o662, 0o664, 0o703, 0o712, 0o723, 0o731, 0o732, 0o734, 0o743, 0o754,
+];
+
+// DTMFの行と列の周波数と、対応する文字
+const DTMF_ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLUMNS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+/// トーンスケルチの設定
+#[derive(Clone, Copy, PartialEq)]
+pub enum ToneSquelch {
+    CTCSS(f32),
+    DCS { code: u16, inverted: bool },
+    None,
+}
+
+impl ToneSquelch {
+    /// 設定したトーンを受信している時(または設定が無い時)に音声を出す。
+    ///
+    /// ```
+    /// use thsdr::tonedetect::{ToneSquelch, ToneStatus};
+    ///
+    /// let status = ToneStatus { ctcss: Some(88.5), dcs: Vec::new(), text: String::new() };
+    /// assert!(ToneSquelch::CTCSS(88.5).is_open(&status));
+    /// assert!(!ToneSquelch::CTCSS(100.0).is_open(&status));
+    /// assert!(ToneSquelch::None.is_open(&status));
+    /// ```
+    pub fn is_open(&self, status: &ToneStatus) -> bool {
+        match self {
+            ToneSquelch::CTCSS(freq) => status.ctcss == Some(*freq),
+            ToneSquelch::DCS { code, inverted } => status.dcs.contains(&(*code, *inverted)),
+            ToneSquelch::None => true,
+        }
+    }
+}
+
+/// チャンクごとの検出結果
+pub struct ToneStatus {
+    pub ctcss: Option<f32>,
+    pub dcs: Vec<(u16, bool)>,      // 受信中のDCSのコードと反転の有無(巡回して同じになるコードを含む)
+    pub text: String,               // 検出したトーンの変化とDTMFの数字
+}
+
+/// FM復調した音声から、CTCSS、DCS、DTMFを検出する。
+///
+/// ```
+/// use thsdr::constants::CHUNK_SIZE;
+/// use thsdr::tonedetect::create_tone_detector;
+///
+/// let mut detector = create_tone_detector();
+/// let status = detector(&[0.0; CHUNK_SIZE]);
+/// assert_eq!(status.ctcss, None);
+/// assert!(status.text.is_empty());
+/// ```
+pub fn create_tone_detector() -> impl FnMut(&[f32]) -> ToneStatus {
+
+    // 間引きのためのローパスフィルタ
+    let coefficients = design_lowpass(LPF_CUTOFF, LPF_TAPS);
+    let mut history: Vec<f32> = vec![0.0; 2 * LPF_TAPS];
+    let mut pos = 0;
+    let mut count = 0;
+
+    // CTCSS
+    let mut ctcss_buffer = vec![0.0f32; CTCSS_WINDOW];
+    let mut ctcss_pos = 0;
+    let mut ctcss_count = 0;
+    let hann: Vec<f32> = (0..CTCSS_WINDOW).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / CTCSS_WINDOW as f32).cos()).collect();
+    let mut ctcss: Option<f32> = None;
+    let mut ctcss_candidate: Option<f32> = None;
+    let mut ctcss_misses = 0;
+
+    // DCS
+    let dcs_table = dcs_table();
+    let mut high: f32 = 0.0;
+    let mut low: f32 = 0.0;
+    let mut pll_phase: f32 = 0.0;
+    let mut last_level = false;
+    let mut word: u32 = 0;
+    let mut bit_count: u32 = 0;
+    let mut dcs_seen: HashMap<(u16, bool), (u32, u32)> = HashMap::new();   // コードごとの(最後に一致したビット, 確認したビット)
+    let mut dcs_shown: Option<(u16, bool)> = None;
+
+    // DTMF
+    let dtmf_freqs: Vec<f32> = DTMF_ROWS.iter().chain(DTMF_COLUMNS.iter()).copied().collect();
+    let dtmf_coeffs: Vec<f32> = dtmf_freqs.iter().map(|f| 2.0 * (2.0 * PI * f / SAMPLING_FREQ).cos()).collect();
+    let mut goertzel = [(0.0f32, 0.0f32); 8];
+    let mut block_power = 0.0;
+    let mut block_count = 0;
+    let mut key_candidate: Option<char> = None;
+    let mut key_down: Option<char> = None;
+    let mut key_idle = 0;
+    let mut digits = String::new();
+
+    move |input: &[f32]| -> ToneStatus {
+        let mut text = String::new();
+
+        for &x in input {
+            // DTMF(48[kHz]のままGoertzelで判定する。)
+            for (g, &coeff) in goertzel.iter_mut().zip(dtmf_coeffs.iter()) {
+                let s0 = x + coeff * g.0 - g.1;
+                g.1 = g.0;
+                g.0 = s0;
+            }
+            block_power += x * x;
+            block_count += 1;
+            if block_count == DTMF_BLOCK {
+                let powers: Vec<f32> = goertzel.iter().zip(dtmf_coeffs.iter())
+                    .map(|(g, &coeff)| 2.0 * (g.0 * g.0 + g.1 * g.1 - coeff * g.0 * g.1) / (DTMF_BLOCK as f32 * block_power.max(1e-12)))
+                    .collect();
+                let key = dtmf_key(&powers[..4], &powers[4..]);
+
+                // 2ブロック続いたら押され、2ブロック無ければ離されたとみなす。
+                if key.is_some() && key == key_candidate && key_down != key {
+                    if let Some(k) = key { digits.push(k); }
+                    key_down = key;
+                }
+                if key.is_none() && key_candidate.is_none() {
+                    key_down = None;
+                }
+                key_candidate = key;
+                key_idle = if key.is_some() { 0 } else { key_idle + 1 };
+                if key_idle == DTMF_END_BLOCKS && !digits.is_empty() {
+                    text.push_str(&format!("DTMF {}\n", digits));
+                    digits.clear();
+                }
+
+                goertzel = [(0.0, 0.0); 8];
+                block_power = 0.0;
+                block_count = 0;
+            }
+
+            // CTCSS/DCSは間引いて処理する。
+            pos = if pos == 0 { LPF_TAPS - 1 } else { pos - 1 };
+            history[pos] = x;
+            history[pos + LPF_TAPS] = x;
+            count += 1;
+            if count < DECIMATION {
+                continue;
+            }
+            count = 0;
+            let y: f32 = history[pos..pos + LPF_TAPS].iter().zip(coefficients.iter()).map(|(a, b)| a * b).sum();
+
+            // CTCSS
+            ctcss_buffer[ctcss_pos] = y;
+            ctcss_pos = (ctcss_pos + 1) % CTCSS_WINDOW;
+            ctcss_count += 1;
+            if ctcss_count == CTCSS_STEP {
+                ctcss_count = 0;
+                let windowed: Vec<f32> = (0..CTCSS_WINDOW).map(|i| ctcss_buffer[(ctcss_pos + i) % CTCSS_WINDOW] * hann[i]).collect();
+                let (freq, ratio) = strongest_tone(&windowed);
+
+                // 新しいトーンは2回続けて検出したら、検出中のトーンは2回続けて失ったら変える。
+                let holding = ctcss == Some(freq) && ratio > CTCSS_HOLD;
+                let found = if ratio > CTCSS_DETECT { Some(freq) } else { None };
+                if holding {
+                    ctcss_misses = 0;
+                } else if found.is_some() && found == ctcss_candidate && found != ctcss {
+                    ctcss = found;
+                    ctcss_misses = 0;
+                    text.push_str(&format!("CTCSS {:.1}Hz\n", freq));
+                } else if ctcss.is_some() {
+                    ctcss_misses += 1;
+                    if ctcss_misses >= 2 {
+                        ctcss = None;
+                        text.push_str("CTCSS off\n");
+                    }
+                }
+                ctcss_candidate = found;
+            }
+
+            // DCS(NRZの2値化とビット同期)
+            high = if y > high { y } else { high - (high - low) * DCS_PEAK_DECAY };
+            low = if y < low { y } else { low + (high - low) * DCS_PEAK_DECAY };
+            let level = y > (high + low) / 2.0;
+            if level != last_level {
+                pll_phase -= (pll_phase - 0.5) * DCS_PLL_GAIN;
+                last_level = level;
+            }
+            pll_phase += DCS_BAUD / RATE;
+            if pll_phase < 1.0 {
+                continue;
+            }
+            pll_phase -= 1.0;
+
+            // 下位ビットから送られるので、新しいビットを上位に入れる。
+            word = (word >> 1) | ((level as u32) << (DCS_BITS - 1));
+            bit_count = bit_count.wrapping_add(1);
+            if let Some(&code) = dcs_table.get(&word) {
+                // 1フレーム後にも同じ符号語を受信したら確認したとみなす。
+                let entry = dcs_seen.entry(code).or_insert((0, 0));
+                if bit_count.wrapping_sub(entry.0) == DCS_BITS {
+                    entry.1 = bit_count;
+                }
+                entry.0 = bit_count;
+            }
+            dcs_seen.retain(|_, (last, _)| bit_count.wrapping_sub(*last) <= 2 * DCS_BITS);
+
+            let present = dcs_present(&dcs_seen, bit_count);
+            let shown = present.first().copied();
+            if shown.is_some() && dcs_shown.is_none_or(|s| !present.contains(&s)) {
+                if let Some(code) = shown {
+                    text.push_str(&format!("DCS {}\n", dcs_name(code, &dcs_table)));
+                }
+                dcs_shown = shown;
+            } else if shown.is_none() && dcs_shown.is_some() {
+                text.push_str("DCS off\n");
+                dcs_shown = None;
+            }
+        }
+
+        ToneStatus {
+            ctcss,
+            dcs: dcs_present(&dcs_seen, bit_count),
+            text,
+        }
+    }
+}
+
+// 300[Hz]以下の電力に対して最も割合の大きいCTCSSのトーンと、その割合
+// 送信側の周波数の誤差を許すため、各トーンの±CTCSS_TOLERANCEの範囲を調べて最大の割合をとる。
+// 窓をかけた純音の時に割合が1になるように正規化する。
+fn strongest_tone(windowed: &[f32]) -> (f32, f32) {
+    let n = windowed.len();
+    let total: f32 = windowed.iter().map(|x| x * x).sum::<f32>().max(1e-12);
+    let mut best = (CTCSS_TONES[0], 0.0);
+    for &tone in CTCSS_TONES.iter() {
+        for step in -CTCSS_OFFSETS..=CTCSS_OFFSETS {
+            let freq = tone * (1.0 + CTCSS_TOLERANCE * step as f32 / CTCSS_OFFSETS as f32);
+            // 回転子を掛けていく(sin、cosを毎回求めない)。
+            let delta = 2.0 * core::f64::consts::PI * freq as f64 / RATE as f64;
+            let (sin, cos) = delta.sin_cos();
+            let (mut c, mut s) = (1.0f64, 0.0f64);
+            let (mut re, mut im) = (0.0f64, 0.0f64);
+            for &x in windowed {
+                re += x as f64 * c;
+                im -= x as f64 * s;
+                (c, s) = (c * cos - s * sin, s * cos + c * sin);
+            }
+            let ratio = 3.0 * (re * re + im * im) as f32 / (n as f32 * total);
+            if ratio > best.1 {
+                best = (tone, ratio);
+            }
+        }
+    }
+    best
+}
+
+// DTMFの行と列の電力の割合から、押されたキーを判定する。
+fn dtmf_key(rows: &[f32], columns: &[f32]) -> Option<char> {
+    let strongest = |powers: &[f32]| -> (usize, f32, f32) {
+        let mut order: Vec<usize> = (0..powers.len()).collect();
+        order.sort_by(|&a, &b| powers[b].total_cmp(&powers[a]));
+        (order[0], powers[order[0]], powers[order[1]])
+    };
+    let (row, row_power, row_second) = strongest(rows);
+    let (column, column_power, column_second) = strongest(columns);
+    let valid = row_power + column_power > DTMF_LEVEL
+        && row_power > DTMF_MIN_TONE && column_power > DTMF_MIN_TONE
+        && row_second < row_power / 4.0 && column_second < column_power / 4.0;
+    if valid { Some(DTMF_KEYS[row][column]) } else { None }
+}
+
+// 受信中のDCSのコード(コードの小さい順、反転無しが先)
+fn dcs_present(seen: &HashMap<(u16, bool), (u32, u32)>, bit_count: u32) -> Vec<(u16, bool)> {
+    let mut present: Vec<(u16, bool)> = seen.iter()
+        .filter(|(_, &(_, confirmed))| confirmed != 0 && bit_count.wrapping_sub(confirmed) <= 2 * DCS_BITS)
+        .map(|(&code, _)| code)
+        .collect();
+    present.sort();
+    present
+}
+
+// DCSのコードの表示(023N等)
+// 巡回すると同じ符号語の列になるコードは区別できないので、/で区切って並べる。
+fn dcs_name(code: (u16, bool), table: &HashMap<u32, (u16, bool)>) -> String {
+    let mask = (1 << DCS_BITS) - 1;
+    let mut word = dcs_codeword(code.0);
+    if code.1 { word = !word & mask; }
+    let mut aliases: Vec<(u16, bool)> = (0..DCS_BITS)
+        .filter_map(|shift| table.get(&(((word >> shift) | (word << (DCS_BITS - shift))) & mask)).copied())
+        .collect();
+    aliases.sort();
+    aliases.dedup();
+    aliases.iter()
+        .map(|(code, inverted)| format!("{:03o}{}", code, if *inverted { "I" } else { "N" }))
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+// DCSの23ビットの符号語から、(コード, 反転の有無)を引く表
+fn dcs_table() -> HashMap<u32, (u16, bool)> {
+    let mut table = HashMap::new();
+    for &code in DCS_CODES.iter() {
+        let word = dcs_codeword(code);
+        table.insert(word, (code, false));
+        table.insert(!word & ((1 << DCS_BITS) - 1), (code, true));
+    }
+    table
+}
+
+/// DCSの符号語(Golay(23,12))
+/// 下位12ビットがコード9ビットと固定の100、上位11ビットがパリティになる。
+///
+/// ```
+/// use thsdr::tonedetect::dcs_codeword;
+///
+/// assert_eq!(dcs_codeword(0o023) & 0xFFF, 0x800 | 0o023);
+/// ```
+pub fn dcs_codeword(code: u16) -> u32 {
+    let data = code as u32 + 0x800;
+    let mut parity = data;
+    for _ in 0..12 {
+        parity <<= 1;
+        if parity & 0x1000 != 0 {
+            parity ^= 0x08EA;
+        }
+    }
+    data | ((parity & 0x0FFE) << 11)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CHUNK_SIZE;
+    use crate::testsignal::gaussian_noise;
+
+    // 信号をチャンクごとに検出器に通し、最後の結果と、表示した文字列をつなげたものを返す。
+    fn detect(signal: &[f32]) -> (ToneStatus, String) {
+        let mut detector = create_tone_detector();
+        let mut text = String::new();
+        let mut last = None;
+        for chunk in signal.chunks(CHUNK_SIZE) {
+            let status = detector(chunk);
+            text.push_str(&status.text);
+            last = Some(status);
+        }
+        (last.unwrap(), text)
+    }
+
+    // CTCSSのトーン(振幅0.1)に、音声の代わりの1kHzの音(振幅0.3)を加えた信号
+    fn ctcss_signal(freq: f32, seconds: f32) -> Vec<f32> {
+        (0..(seconds * SAMPLING_FREQ) as usize).map(|i| {
+            let t = i as f32 / SAMPLING_FREQ;
+            0.1 * (2.0 * PI * freq * t).sin() + 0.3 * (2.0 * PI * 1000.0 * t).sin()
+        }).collect()
+    }
+
+    #[test]
+    fn ctcss_within_one_percent() {
+        // 100.0Hzの±1%は、隣の97.4Hzや103.5Hzではなく100.0Hzと判定する。
+        for freq in [99.0, 100.0, 101.0] {
+            let (status, text) = detect(&ctcss_signal(freq, 3.0));
+            assert_eq!(status.ctcss, Some(100.0), "{}Hz", freq);
+            assert_eq!(text, "CTCSS 100.0Hz\n");
+            assert!(ToneSquelch::CTCSS(100.0).is_open(&status));
+            assert!(!ToneSquelch::CTCSS(97.4).is_open(&status) && !ToneSquelch::CTCSS(103.5).is_open(&status));
+        }
+        assert_eq!(detect(&ctcss_signal(103.5, 3.0)).0.ctcss, Some(103.5));
+        assert_eq!(detect(&ctcss_signal(97.4, 3.0)).0.ctcss, Some(97.4));
+    }
+
+    #[test]
+    fn dcs_codewords_are_golay() {
+        // Golay(23,12)の生成多項式x^11+x^10+x^6+x^5+x^4+x^2+1で割った余りがパリティになる。
+        assert_eq!(dcs_codeword(0o023), 0x763813);
+        for &code in DCS_CODES.iter() {
+            let data = code as u32 + 0x800;
+            let mut remainder = data << 11;
+            for i in (11..23).rev() {
+                if remainder & (1 << i) != 0 {
+                    remainder ^= 0xC75 << (i - 11);
+                }
+            }
+            assert_eq!(dcs_codeword(code), data | (remainder << 12), "{:03o}", code);
+        }
+    }
+
+    // DCSの符号語を134.4bdのNRZで繰り返す信号(下位ビットから送る。反転も指定できる。)
+    fn dcs_signal(code: u16, inverted: bool, seconds: f32) -> Vec<f32> {
+        let word = dcs_codeword(code);
+        let mut noise = gaussian_noise(5);
+        (0..(seconds * SAMPLING_FREQ) as usize).map(|i| {
+            let bit = (i as f32 * DCS_BAUD / SAMPLING_FREQ) as u32 % DCS_BITS;
+            let level = ((word >> bit) & 1 == 1) != inverted;
+            (if level { 0.1 } else { -0.1 }) + 0.3 * (2.0 * PI * 1000.0 * i as f32 / SAMPLING_FREQ).sin() + 0.02 * noise()
+        }).collect()
+    }
+
+    #[test]
+    fn detects_dcs_023() {
+        let (status, text) = detect(&dcs_signal(0o023, false, 2.0));
+        assert!(status.dcs.contains(&(0o023, false)), "{:?}", status.dcs);
+        assert!(text.starts_with("DCS 023N"), "{}", text);
+        assert!(ToneSquelch::DCS { code: 0o023, inverted: false }.is_open(&status));
+        assert!(!ToneSquelch::DCS { code: 0o023, inverted: true }.is_open(&status));
+    }
+
+    #[test]
+    fn detects_inverted_dcs() {
+        // 023Iを反転した符号語は、巡回すると047Nと同じになる。
+        let (status, text) = detect(&dcs_signal(0o023, true, 2.0));
+        assert!(status.dcs.contains(&(0o023, true)) && status.dcs.contains(&(0o047, false)), "{:?}", status.dcs);
+        assert!(text.contains("023I") && text.contains("047N"), "{}", text);
+        assert!(ToneSquelch::DCS { code: 0o023, inverted: true }.is_open(&status));
+        assert!(!ToneSquelch::DCS { code: 0o023, inverted: false }.is_open(&status));
+    }
+
+    // DTMFの文字列(1文字80ms、間隔80ms)。列の音を行の音よりtwist[dB]強くし、雑音を加える。
+    fn dtmf_signal(keys: &str, twist_db: f32, noise_sigma: f32) -> Vec<f32> {
+        let tone = (0.08 * SAMPLING_FREQ) as usize;
+        let mut noise = gaussian_noise(11);
+        let mut signal = Vec::new();
+        for key in keys.chars() {
+            let (row, column) = (0..4).flat_map(|r| (0..4).map(move |c| (r, c))).find(|&(r, c)| DTMF_KEYS[r][c] == key).unwrap();
+            let column_amplitude = 0.2 * 10.0_f32.powf(twist_db / 20.0);
+            for i in 0..tone {
+                let t = i as f32 / SAMPLING_FREQ;
+                signal.push(0.2 * (2.0 * PI * DTMF_ROWS[row] * t).sin() + column_amplitude * (2.0 * PI * DTMF_COLUMNS[column] * t).sin());
+            }
+            signal.extend(vec![0.0; tone]);
+        }
+        signal.extend(vec![0.0; (1.2 * SAMPLING_FREQ) as usize]);
+        signal.iter().map(|x| x + noise_sigma * noise()).collect()
+    }
+
+    #[test]
+    fn decodes_dtmf_with_twist_and_noise() {
+        for twist in [-4.0, 0.0, 4.0] {
+            let (_, text) = detect(&dtmf_signal("0123456789*#ABCD", twist, 0.05));
+            assert_eq!(text, "DTMF 0123456789*#ABCD\n", "twist {}dB", twist);
+        }
+        // 同じ数字が続いても、間隔があれば別の数字として数える。
+        assert_eq!(detect(&dtmf_signal("1155", 0.0, 0.05)).1, "DTMF 1155\n");
+    }
+}
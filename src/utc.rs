@@ -0,0 +1,59 @@
+// UTCの時刻を扱う関数
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static LAST_MILLIS: AtomicU64 = AtomicU64::new(0);     // 最後にtimestampで使った時刻[ミリ秒]
+
+// 現在のUNIX時間[秒](小数点以下も含む)
+pub fn unix_time() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+// UNIX時間を(年, 月, 日, 時, 分, 秒)にする。
+pub fn civil_time(unix: f64) -> (i64, u32, u32, u32, u32, u32) {
+    let seconds = unix.floor() as i64;
+    let days = seconds.div_euclid(86400);
+    let rest = seconds.rem_euclid(86400);
+
+    // 1970-01-01からの日数を日付にする。(Howard Hinnantのアルゴリズム)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, (rest / 3600) as u32, (rest % 3600 / 60) as u32, (rest % 60) as u32)
+}
+
+// ファイル名に使う時刻の文字列(YYYYMMDD_HHMMSS_mmm)
+// 同じミリ秒に複数の受信機が保存しても上書きしないように、前回以前の時刻の時は前回より1ミリ秒進めて、毎回違う文字列にする。
+pub fn timestamp() -> String {
+    let now = (unix_time() * 1000.0) as u64;
+    let last = LAST_MILLIS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1))).unwrap_or(0);
+    let millis = now.max(last + 1);
+    let (year, month, day, hour, minute, second) = civil_time((millis / 1000) as f64);
+    format!("{:04}{:02}{:02}_{:02}{:02}{:02}_{:03}", year, month, day, hour, minute, second, millis % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_time_of_known_dates() {
+        assert_eq!(civil_time(0.0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(civil_time(951_782_400.5), (2000, 2, 29, 0, 0, 0));
+        assert_eq!(civil_time(1_704_110_400.0), (2024, 1, 1, 12, 0, 0));
+    }
+
+    #[test]
+    fn timestamps_differ_within_a_second() {
+        let stamps: Vec<String> = (0..1000).map(|_| timestamp()).collect();
+        assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]), "同じか前の時刻の文字列がある");
+        assert_eq!(stamps[0].len(), "YYYYMMDD_HHMMSS_mmm".len());
+    }
+}
@@ -0,0 +1,278 @@
+// システムで使う定数の読み込み
+use crate::constants::SAMPLING_FREQ;
+use crate::fmdemod::create_tone_meter;
+use crate::fileout::FileOutput;
+use crate::utc::timestamp;
+use core::f32::consts::PI;
+use std::sync::mpsc::Sender;
+
+
+// WEFAX関連の定数の定義
+const DECIMATION: usize = 4;                // 48[kHz]から12[kHz]に間引いて処理する。
+const RATE: f32 = SAMPLING_FREQ / DECIMATION as f32;
+const CENTER_FREQ: f32 = 1900.0;            // 黒1500[Hz]、白2300[Hz]の中心
+const DEVIATION: f32 = 400.0;
+const LOWPASS_CUTOFF: f32 = 1100.0;
+const TONE_WINDOW: usize = 2400;            // 開始/停止信号を検出する区間(0.2秒)。300、450、675[Hz]が整数周期になる。
+const TONE_COUNT: u32 = 10;                 // 2秒続いたら開始/停止信号とみなす。
+const TONE_RATIO: f32 = 0.5;                // 全体の変動に対する、検出する周波数の成分の割合
+const START_576: f32 = 300.0;               // IOC576の開始信号
+const START_288: f32 = 675.0;               // IOC288の開始信号
+const STOP_FREQ: f32 = 450.0;
+const PHASING_BINS: usize = 200;            // 位相合わせで1ラインを分割する数
+const PHASING_LINES: u32 = 20;              // 位相合わせに使うライン数
+const PULSE_RATIO: f32 = 0.05;              // 位相合わせ信号の白パルスの幅(1ラインの5%)
+const MAX_LINES: usize = 2000;              // 1枚の画像の最大ライン数
+
+#[derive(PartialEq)]
+enum FaxState {
+    Idle,               // 開始信号待ち
+    Start,              // 開始信号の受信中
+    Phasing,            // 位相合わせ信号の受信中
+    Image,              // 画像の受信中
+}
+
+// WEFAXデコーダ
+// USBで受信した音声をFM復調して画素の濃淡にし、開始信号、位相合わせ、画像、停止信号の順に受信する。
+// 受信した画像は、停止信号を受けた時(または最大ライン数に達した時)にfile_txに送り、PNGファイルに保存する。
+// lpmは1分あたりのライン数、slantはサウンドカードのクロックの誤差[ppm]で画像の傾きを補正する。
+pub fn create_wefax_decoder(lpm: f32, slant: f32, file_tx: Sender<FileOutput>) -> impl FnMut(&[f32]) -> String {
+
+    let mut meter = create_tone_meter(CENTER_FREQ, LOWPASS_CUTOFF, DECIMATION);
+
+    // 開始/停止信号の検出(Goertzel)
+    let tone_freqs = [START_576, START_288, STOP_FREQ];
+    let coeffs: Vec<f32> = tone_freqs.iter().map(|f| 2.0 * (2.0 * PI * f / RATE).cos()).collect();
+    let mut goertzel = [(0.0f32, 0.0f32); 3];
+    let mut window_power = 0.0;
+    let mut window_count = 0;
+    let mut tone_run = [0u32; 3];
+
+    let line_samples = (RATE as f64 * 60.0 / lpm as f64) * (1.0 + slant as f64 * 1e-6);
+    let mut state = FaxState::Idle;
+    let mut width = 1810;
+    let mut position: f64 = 0.0;            // ライン内の位置[サンプル]
+    let mut lines_done = 0;
+    let mut profile = vec![0.0f32; PHASING_BINS];
+    let mut line_sum: Vec<f32> = Vec::new();
+    let mut line_count: Vec<u32> = Vec::new();
+    let mut image: Vec<Vec<u8>> = Vec::new();
+
+    move |input: &[f32]| -> String {
+        let mut text = String::new();
+
+        for &x in input {
+            // 音の周波数を黒(0.0)～白(1.0)の値にする。
+            let freq = match meter(x) {
+                Some(freq) => freq,
+                None => continue,
+            };
+            let value = ((freq - CENTER_FREQ + DEVIATION) / (2.0 * DEVIATION)).clamp(0.0, 1.0);
+
+            // 開始/停止信号の検出
+            let v = value - 0.5;
+            for (g, &coeff) in goertzel.iter_mut().zip(coeffs.iter()) {
+                let s0 = v + coeff * g.0 - g.1;
+                g.1 = g.0;
+                g.0 = s0;
+            }
+            window_power += v * v;
+            window_count += 1;
+            let mut detected = [false; 3];
+            if window_count == TONE_WINDOW {
+                for (i, g) in goertzel.iter_mut().enumerate() {
+                    let power = g.0 * g.0 + g.1 * g.1 - coeffs[i] * g.0 * g.1;
+                    let ratio = power / (TONE_WINDOW as f32 / 2.0 * window_power).max(1e-9);
+                    let found = window_power > 0.01 * TONE_WINDOW as f32 && ratio > TONE_RATIO;
+                    tone_run[i] = if found { tone_run[i] + 1 } else { 0 };
+                    detected[i] = tone_run[i] == TONE_COUNT;
+                    *g = (0.0, 0.0);
+                }
+                window_power = 0.0;
+                window_count = 0;
+            }
+
+            // 開始信号はどの状態でも受け付ける。受信中の画像があれば保存する。
+            if detected[0] || detected[1] {
+                if state == FaxState::Image {
+                    text.push_str(&save_image(&image, width, &file_tx));
+                }
+                let ioc = if detected[0] { 576 } else { 288 };
+                width = (ioc as f32 * PI).round() as usize;
+                state = FaxState::Start;
+                text.push_str(&format!("WEFAX start IOC{} {} LPM\n", ioc, lpm));
+                continue;
+            }
+
+            match state {
+                FaxState::Idle => {},
+                FaxState::Start => {
+                    // 開始信号が終わったら位相合わせを始める。
+                    if window_count == 0 && tone_run[0] == 0 && tone_run[1] == 0 {
+                        state = FaxState::Phasing;
+                        profile.iter_mut().for_each(|p| *p = 0.0);
+                        position = 0.0;
+                        lines_done = 0;
+                    }
+                },
+                FaxState::Phasing => {
+                    // ラインの長さで折り返して濃淡を積算し、白パルスの位置を探す。
+                    let bin = ((position / line_samples) * PHASING_BINS as f64) as usize;
+                    profile[bin.min(PHASING_BINS - 1)] += value;
+                    position += 1.0;
+                    if position >= line_samples {
+                        position -= line_samples;
+                        lines_done += 1;
+                    }
+                    if lines_done == PHASING_LINES {
+                        let (start, contrast) = find_pulse(&profile);
+                        if contrast > 0.3 {
+                            text.push_str("WEFAX phasing ok\n");
+                        } else {
+                            text.push_str("WEFAX phasing not found\n");
+                        }
+                        // 白パルスの中心をラインの先頭にする。
+                        let offset = start as f64 * line_samples / PHASING_BINS as f64;
+                        position = (position - offset).rem_euclid(line_samples);
+                        line_sum = vec![0.0; width];
+                        line_count = vec![0; width];
+                        image.clear();
+                        state = FaxState::Image;
+                    }
+                },
+                FaxState::Image => {
+                    let pixel = ((position / line_samples) * width as f64) as usize;
+                    line_sum[pixel.min(width - 1)] += value;
+                    line_count[pixel.min(width - 1)] += 1;
+                    position += 1.0;
+                    if position >= line_samples {
+                        position -= line_samples;
+                        // 位相合わせの直後は途中から始まったラインなので、先頭の画素が無い。
+                        let partial = line_count[0] == 0;
+                        let line: Vec<u8> = line_sum.iter().zip(line_count.iter())
+                            .map(|(&s, &n)| (s / n.max(1) as f32 * 255.0).round() as u8)
+                            .collect();
+                        line_sum.iter_mut().for_each(|s| *s = 0.0);
+                        line_count.iter_mut().for_each(|n| *n = 0);
+                        // 画像の前の位相合わせ信号のラインは捨てる。
+                        if !partial && (!image.is_empty() || !is_phasing_line(&line)) {
+                            image.push(line);
+                        }
+                    }
+                    if detected[2] {
+                        // 停止信号の検出にかかった分のラインを取り除く。
+                        let tone_time = (TONE_COUNT as usize + 1) * TONE_WINDOW;
+                        let drop = (tone_time as f64 / line_samples).ceil() as usize;
+                        image.truncate(image.len().saturating_sub(drop));
+                        text.push_str(&save_image(&image, width, &file_tx));
+                        state = FaxState::Idle;
+                    } else if image.len() >= MAX_LINES {
+                        text.push_str(&save_image(&image, width, &file_tx));
+                        state = FaxState::Idle;
+                    }
+                },
+            }
+        }
+
+        text
+    }
+}
+
+// 位相合わせ信号の白パルスの中心のビンと、周囲とのコントラストを求める。
+fn find_pulse(profile: &[f32]) -> (usize, f32) {
+    let n = profile.len();
+    let pulse = ((n as f32 * PULSE_RATIO) as usize).max(1);
+    let total: f32 = profile.iter().sum();
+    let mut best = (0, f32::MIN);
+    for start in 0..n {
+        let sum: f32 = (0..pulse).map(|i| profile[(start + i) % n]).sum();
+        if sum > best.1 {
+            best = (start, sum);
+        }
+    }
+    let inside = best.1 / pulse as f32;
+    let outside = (total - best.1) / (n - pulse) as f32;
+    let scale = total / n as f32 + 1e-9;
+    ((best.0 + pulse / 2) % n, (inside - outside) / (inside.max(scale) + 1e-9))
+}
+
+// 両端が白で、それ以外が黒のラインは位相合わせ信号とみなす。
+fn is_phasing_line(line: &[u8]) -> bool {
+    let n = line.len();
+    let edge = n / 50;
+    let mean = |range: &[u8]| range.iter().map(|&p| p as f32).sum::<f32>() / range.len().max(1) as f32;
+    let edges = (mean(&line[..edge]) + mean(&line[n - edge..])) / 2.0;
+    let middle = mean(&line[n / 10..n * 9 / 10]);
+    edges > 180.0 && middle < 30.0
+}
+
+// 受信した画像を保存するスレッドに送り、表示する文字列を返す。
+fn save_image(image: &[Vec<u8>], width: usize, file_tx: &Sender<FileOutput>) -> String {
+    if image.is_empty() {
+        return "WEFAX stop (no image)\n".to_string();
+    }
+    let path = format!("wefax_{}.png", timestamp());
+    let output = FileOutput::GrayImage { path: path.clone(), width, height: image.len(), data: image.concat() };
+    match file_tx.send(output) {
+        Ok(()) => format!("WEFAX saved {} {}x{}\n", path, width, image.len()),
+        Err(_) => format!("WEFAX save failed {}\n", path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CHUNK_SIZE;
+    use crate::testsignal::wefax::{generate, test_chart};
+    use std::sync::mpsc::channel;
+
+    const LINES: usize = 60;
+
+    // 試験用の信号をデコーダに通し、保存する画像とデコーダが表示した文字列を返す。
+    fn decode(lpm: f32, ioc: u32) -> (usize, Vec<u8>, String) {
+        let (file_tx, file_rx) = channel();
+        let mut decoder = create_wefax_decoder(lpm, 0.0, file_tx);
+        let text: String = generate(lpm, ioc, LINES).chunks(CHUNK_SIZE).map(&mut decoder).collect();
+        match file_rx.try_recv() {
+            Ok(FileOutput::GrayImage { width, data, .. }) => (width, data, text),
+            _ => panic!("画像を受信できない: {}", text),
+        }
+    }
+
+    // 受信したラインと試験画像の画素の差の平均が最も小さくなるように上下の位置を合わせ、その差の平均(0～255)を返す。
+    fn line_error(width: usize, data: &[u8]) -> f32 {
+        let rows: Vec<&[u8]> = data.chunks(width).collect();
+        (0..3).map(|shift| {
+            let mut sum = 0.0;
+            for (n, row) in rows.iter().enumerate() {
+                let y = (n + shift) as f32 / LINES as f32;
+                for (i, &p) in row.iter().enumerate() {
+                    let x = (i as f32 + 0.5) / width as f32;
+                    sum += (p as f32 - 255.0 * test_chart(x, y)).abs();
+                }
+            }
+            sum / data.len() as f32
+        }).fold(f32::MAX, f32::min)
+    }
+
+    fn check(ioc: u32, width: usize) {
+        let (decoded_width, data, text) = decode(120.0, ioc);
+        assert!(text.contains(&format!("WEFAX start IOC{}", ioc)), "{}", text);
+        assert!(text.contains("WEFAX phasing ok"), "{}", text);
+        assert_eq!(decoded_width, width);
+        let lines = data.len() / width;
+        assert!((LINES - 3..=LINES).contains(&lines), "{}ライン", lines);
+        let error = line_error(width, &data);
+        assert!(error < 10.0, "IOC{}: 画素の差の平均 {:.1}", ioc, error);
+    }
+
+    #[test]
+    fn decodes_ioc576() {
+        check(576, 1810);
+    }
+
+    #[test]
+    fn decodes_ioc288() {
+        check(288, 905);
+    }
+}